@@ -0,0 +1,296 @@
+// src/lru_cache.rs as it was before the cache was sharded, kept verbatim apart from the
+// imports so the benchmark compares against the real thing
+#![allow(dead_code)]
+
+use std::collections::{HashMap, VecDeque};
+use std::net::Ipv4Addr;
+use std::time::Instant;
+
+use rdns_resolver_rs::dns_packet::DnsPacket;
+use rdns_resolver_rs::dns_question::DnsQuestion;
+use rdns_resolver_rs::dns_record::DnsRecord;
+use rdns_resolver_rs::dnssec::rrsets;
+use rdns_resolver_rs::query_class::QueryClass;
+use rdns_resolver_rs::query_type::QueryType;
+use rdns_resolver_rs::res_code::ResultCode;
+
+// Names compare case-insensitively, so they are lowercased in the key
+type CacheKey = (String, QueryType, QueryClass);
+
+// Longer CNAME chains than this are treated as loops
+const MAX_CNAME_CHAIN: usize = 8;
+
+// Caches individual RRsets, which answers are assembled from
+#[derive(Debug)]
+pub struct LRUCache {
+    capacity: usize,
+    map: HashMap<CacheKey, Node>,
+    order: VecDeque<CacheKey>,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    // An RRset with the RRSIGs covering it, or the authority section of a negative answer
+    records: Vec<DnsRecord>,
+    negative: Option<ResultCode>,
+    authed: bool,
+    // Learned from a referral, so it only steers recursion and is never served as an answer
+    glue: bool,
+    ttl: u32,
+    time: Instant,
+}
+
+impl Node {
+    fn remaining_ttl(&self) -> u32 {
+        self.ttl
+            .saturating_sub(self.time.elapsed().as_secs().min(u32::MAX as u64) as u32)
+    }
+}
+
+impl LRUCache {
+    pub fn new(capacity: usize) -> Self {
+        LRUCache {
+            capacity,
+            map: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn get(&mut self, question: &DnsQuestion) -> Option<DnsPacket> {
+        let mut packet = DnsPacket::default();
+        packet.header.authed_data = true;
+        let mut name = question.name.to_lowercase();
+        for _ in 0..MAX_CNAME_CHAIN {
+            if let Some(node) = self.get_node(&(name.clone(), question.qtype, question.qclass)) {
+                packet.header.authed_data &= node.authed;
+                match node.negative {
+                    Some(rescode) => {
+                        packet.header.rescode = rescode;
+                        packet.authorities = node.records;
+                    }
+                    None => packet.answers.extend(node.records),
+                }
+                break;
+            }
+            if question.qtype == QueryType::CNAME {
+                break;
+            }
+            // Follow a cached alias to the RRsets of the name it points at
+            let Some(node) = self.get_node(&(name.clone(), QueryType::CNAME, question.qclass))
+            else {
+                break;
+            };
+            packet.header.authed_data &= node.authed;
+            let target = node.records.iter().find_map(|record| match record {
+                DnsRecord::CNAME { host, .. } => Some(host.to_lowercase()),
+                _ => None,
+            });
+            packet.answers.extend(node.records);
+            match target {
+                Some(target) => name = target,
+                None => break,
+            }
+        }
+        if packet.answers.is_empty() && packet.authorities.is_empty() {
+            return None;
+        }
+        packet.header.answers = packet.answers.len() as u16;
+        packet.header.authoritative_entries = packet.authorities.len() as u16;
+        Some(packet)
+    }
+
+    pub fn put(&mut self, question: &DnsQuestion, value: &DnsPacket) {
+        let authed = value.header.authed_data;
+        // Only the RRsets on the way from the question to the answer are cached, anything
+        // else in the answer section wasn't asked for
+        let mut chain = vec![question.name.to_lowercase()];
+        while question.qtype != QueryType::CNAME && chain.len() <= MAX_CNAME_CHAIN {
+            let name = &chain[chain.len() - 1];
+            match value.answers.iter().find_map(|record| match record {
+                DnsRecord::CNAME { domain, host, .. } if domain.eq_ignore_ascii_case(name) => {
+                    Some(host.to_lowercase())
+                }
+                _ => None,
+            }) {
+                Some(target) if !chain.contains(&target) => chain.push(target),
+                _ => break,
+            }
+        }
+        for (rrset, rrsigs) in rrsets(&value.answers) {
+            if !chain.contains(&rrset[0].domain().to_lowercase()) {
+                continue;
+            }
+            self.insert(
+                record_key(&rrset[0]),
+                rrset.into_iter().chain(rrsigs).collect(),
+                None,
+                authed,
+                false,
+            );
+        }
+        if let Some(ttl) = value.get_negative_ttl() {
+            // The SOA sent back with a cached negative answer carries the negative ttl
+            let mut authorities = value.authorities.clone();
+            for record in authorities.iter_mut() {
+                if let DnsRecord::SOA { .. } = record {
+                    record.set_ttl(ttl);
+                }
+            }
+            // The denial is about the end of any CNAME chain in the answer
+            let name = chain.pop().unwrap_or_default();
+            let key = (name, question.qtype, question.qclass);
+            self.insert(key, authorities, Some(value.header.rescode), authed, false);
+        }
+    }
+
+    // Keeps the NS RRsets and glue addresses of a referral for later recursion
+    pub fn put_delegation(&mut self, response: &DnsPacket) {
+        for (rrset, _) in rrsets(&response.authorities) {
+            if rrset[0].qtype() == QueryType::NS {
+                self.insert(record_key(&rrset[0]), rrset, None, false, true);
+            }
+        }
+        for (rrset, _) in rrsets(&response.resources) {
+            if rrset[0].qtype() == QueryType::A {
+                self.insert(record_key(&rrset[0]), rrset, None, false, true);
+            }
+        }
+    }
+
+    // Keeps the addresses a nameserver without glue was resolved to, so later lookups in
+    // its zones can go straight to it
+    pub fn put_nameserver(&mut self, host: &str, response: &DnsPacket) {
+        for (rrset, _) in rrsets(&response.answers) {
+            if rrset[0].qtype() == QueryType::A && rrset[0].domain().eq_ignore_ascii_case(host) {
+                self.insert(record_key(&rrset[0]), rrset, None, false, true);
+            }
+        }
+    }
+
+    // The address of a server for the deepest zone above qname with cached NS records
+    pub fn closest_nameserver(&mut self, qname: &str) -> Option<Ipv4Addr> {
+        let mut name = qname.to_lowercase();
+        loop {
+            if let Some(node) = self.get_any_node(&(name.clone(), QueryType::NS, QueryClass::IN)) {
+                for record in node.records {
+                    let DnsRecord::NS { host, .. } = record else {
+                        continue;
+                    };
+                    let key = (host.to_lowercase(), QueryType::A, QueryClass::IN);
+                    let addr = self.get_any_node(&key).and_then(|node| {
+                        node.records.iter().find_map(|record| match record {
+                            DnsRecord::A { addr, .. } => Some(*addr),
+                            _ => None,
+                        })
+                    });
+                    if addr.is_some() {
+                        return addr;
+                    }
+                }
+            }
+            if name.is_empty() {
+                return None;
+            }
+            name = name
+                .split_once('.')
+                .map_or("", |(_, parent)| parent)
+                .to_string();
+        }
+    }
+
+    pub fn remove(&mut self, question: &DnsQuestion) {
+        self.remove_key(&cache_key(question));
+    }
+
+    fn get_node(&mut self, key: &CacheKey) -> Option<Node> {
+        self.get_any_node(key).filter(|node| !node.glue)
+    }
+
+    fn get_any_node(&mut self, key: &CacheKey) -> Option<Node> {
+        let node = self.map.get(key)?;
+        let ttl = node.remaining_ttl();
+        if ttl == 0 {
+            // TTL expired, remove the key from the cache
+            self.remove_key(key);
+            return None;
+        }
+        let mut node = node.clone();
+        // Clients see how long the records have left rather than their original TTL
+        for record in node.records.iter_mut() {
+            record.set_ttl(ttl);
+        }
+        // Move the key to the front of the order queue
+        self.order.retain(|x| x != key);
+        self.order.push_front(key.clone());
+        Some(node)
+    }
+
+    fn insert(
+        &mut self,
+        key: CacheKey,
+        records: Vec<DnsRecord>,
+        negative: Option<ResultCode>,
+        authed: bool,
+        glue: bool,
+    ) {
+        let ttl = match negative {
+            Some(_) => records.iter().find(|r| r.qtype() == QueryType::SOA),
+            None => records.iter().min_by_key(|r| r.ttl()),
+        }
+        .map_or(0, DnsRecord::ttl);
+        if ttl == 0 {
+            return;
+        }
+        // Glue never replaces what an authoritative answer told us
+        if let Some(node) = self.map.get(&key) {
+            if glue && !node.glue && node.remaining_ttl() > 0 {
+                return;
+            }
+        }
+
+        if self.map.contains_key(&key) {
+            self.order.retain(|x| x != &key);
+        } else if self.map.len() == self.capacity {
+            // If the cache is full, remove the least recently used element
+            if let Some(lru_key) = self.order.pop_back() {
+                self.map.remove(&lru_key);
+            }
+        }
+        self.map.insert(
+            key.clone(),
+            Node {
+                records,
+                negative,
+                authed,
+                glue,
+                ttl,
+                time: Instant::now(),
+            },
+        );
+        self.order.push_front(key);
+    }
+
+    fn remove_key(&mut self, key: &CacheKey) {
+        // Remove the key from the map
+        if self.map.remove(key).is_some() {
+            // Remove the key from the order list
+            self.order.retain(|x| x != key);
+        }
+    }
+}
+
+fn cache_key(question: &DnsQuestion) -> CacheKey {
+    (
+        question.name.to_lowercase(),
+        question.qtype,
+        question.qclass,
+    )
+}
+
+fn record_key(record: &DnsRecord) -> CacheKey {
+    (
+        record.domain().to_lowercase(),
+        record.qtype(),
+        record.class(),
+    )
+}
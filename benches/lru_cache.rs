@@ -0,0 +1,113 @@
+use std::{
+    hint::black_box,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+mod baseline;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rdns_resolver_rs::{
+    dns_packet::DnsPacket, dns_question::DnsQuestion, dns_record::DnsRecord, lru_cache::LRUCache,
+    query_class::QueryClass, query_type::QueryType,
+};
+
+const THREADS: usize = 8;
+
+fn question(i: usize) -> DnsQuestion {
+    DnsQuestion::new(format!("host{}.example.com", i), QueryType::A)
+}
+
+fn packet(i: usize) -> DnsPacket {
+    DnsPacket {
+        answers: vec![DnsRecord::A {
+            domain: format!("host{}.example.com", i),
+            addr: [192, 0, 2, (i % 256) as u8].into(),
+            class: QueryClass::IN,
+            ttl: 3600,
+        }],
+        ..Default::default()
+    }
+}
+
+fn bench_get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get");
+    for size in [1_000, 10_000] {
+        let questions: Vec<DnsQuestion> = (0..size).map(question).collect();
+
+        let mut old = baseline::LRUCache::new(size);
+        let new = LRUCache::new(size);
+        for (i, q) in questions.iter().enumerate() {
+            old.put(q, &packet(i));
+            new.put(q, &packet(i));
+        }
+
+        let mut i = 0;
+        group.bench_function(BenchmarkId::new("single_lock", size), |b| {
+            b.iter(|| {
+                i = (i + 7919) % size;
+                black_box(old.get(&questions[i]))
+            })
+        });
+        group.bench_function(BenchmarkId::new("sharded", size), |b| {
+            b.iter(|| {
+                i = (i + 7919) % size;
+                black_box(new.get(&questions[i]))
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_concurrent(c: &mut Criterion) {
+    let size = 10_000;
+    let questions: Arc<Vec<DnsQuestion>> = Arc::new((0..size).map(question).collect());
+    let old = Arc::new(Mutex::new(baseline::LRUCache::new(size)));
+    let new = Arc::new(LRUCache::new(size));
+    for (i, q) in questions.iter().enumerate() {
+        old.lock().unwrap().put(q, &packet(i));
+        new.put(q, &packet(i));
+    }
+
+    // Each iteration is THREADS threads doing 1000 lookups each
+    let mut group = c.benchmark_group("concurrent_get");
+    group.sample_size(10);
+    group.bench_function("single_lock", |b| {
+        b.iter(|| {
+            let threads: Vec<_> = (0..THREADS)
+                .map(|t| {
+                    let old = old.clone();
+                    let questions = questions.clone();
+                    thread::spawn(move || {
+                        for i in 0..1000 {
+                            let q = &questions[(t * 1000 + i * 31) % size];
+                            black_box(old.lock().unwrap().get(q));
+                        }
+                    })
+                })
+                .collect();
+            threads.into_iter().for_each(|t| t.join().unwrap());
+        })
+    });
+    group.bench_function("sharded", |b| {
+        b.iter(|| {
+            let threads: Vec<_> = (0..THREADS)
+                .map(|t| {
+                    let new = new.clone();
+                    let questions = questions.clone();
+                    thread::spawn(move || {
+                        for i in 0..1000 {
+                            let q = &questions[(t * 1000 + i * 31) % size];
+                            black_box(new.get(q));
+                        }
+                    })
+                })
+                .collect();
+            threads.into_iter().for_each(|t| t.join().unwrap());
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_get, bench_concurrent);
+criterion_main!(benches);
@@ -2,9 +2,12 @@ use std::collections::HashMap;
 
 #[derive(Clone)]
 pub struct BytePacketBuffer {
-    pub buf: [u8; 512],
+    pub buf: Vec<u8>,
     pub pos: usize,
+    pub max_size: usize,
     pub qname_pointer: HashMap<String, usize>,
+    // Off when writing canonical forms, e.g. for DNSSEC signatures
+    pub compression: bool,
 }
 
 impl Default for BytePacketBuffer {
@@ -15,10 +18,28 @@ impl Default for BytePacketBuffer {
 
 impl BytePacketBuffer {
     fn new() -> BytePacketBuffer {
+        BytePacketBuffer::with_size(512)
+    }
+
+    // A zeroed buffer of a fixed size, e.g. for receiving a datagram into
+    pub fn with_size(size: usize) -> BytePacketBuffer {
+        BytePacketBuffer {
+            buf: vec![0; size],
+            pos: 0,
+            max_size: size,
+            qname_pointer: HashMap::new(),
+            compression: true,
+        }
+    }
+
+    // A buffer that grows as it is written to, up to max_size bytes
+    pub fn with_max_size(max_size: usize) -> BytePacketBuffer {
         BytePacketBuffer {
-            buf: [0; 512],
+            buf: Vec::with_capacity(max_size.min(512)),
             pos: 0,
+            max_size,
             qname_pointer: HashMap::new(),
+            compression: true,
         }
     }
 
@@ -33,7 +54,7 @@ impl BytePacketBuffer {
     }
 
     pub fn get(&self, pos: usize) -> Result<u8, String> {
-        if pos >= 512 {
+        if pos >= self.buf.len() {
             return Err("Buffer overflow".to_string());
         }
         Ok(self.buf[pos])
@@ -44,7 +65,7 @@ impl BytePacketBuffer {
     }
 
     pub fn read(&mut self) -> Result<u8, String> {
-        if self.pos >= 512 {
+        if self.pos >= self.buf.len() {
             return Err("Buffer overflow".to_string());
         }
         let val = self.buf[self.pos];
@@ -53,7 +74,7 @@ impl BytePacketBuffer {
     }
 
     pub fn get_range(&mut self, start: usize, len: usize) -> Result<&[u8], String> {
-        if start + len >= 512 {
+        if start + len > self.buf.len() {
             return Err("Buffer overflow on reading provided length".to_string());
         }
         Ok(&self.buf[start..start + len])
@@ -72,6 +93,15 @@ impl BytePacketBuffer {
     }
 
     pub fn read_qname(&mut self, outstr: &mut String) -> Result<(), String> {
+        self.read_labels(outstr, true)
+    }
+
+    // The name as it was sent, to check that a server echoed our 0x20 randomized case
+    pub fn read_qname_preserving_case(&mut self, outstr: &mut String) -> Result<(), String> {
+        self.read_labels(outstr, false)
+    }
+
+    fn read_labels(&mut self, outstr: &mut String, lowercase: bool) -> Result<(), String> {
         let mut pos = self.pos();
         let mut jumped = false;
         let mut max_jumps = 10;
@@ -98,7 +128,12 @@ impl BytePacketBuffer {
                 }
                 outstr.push_str(delimiter);
                 let str_buffer = self.get_range(pos, len as usize)?;
-                outstr.push_str(&String::from_utf8_lossy(str_buffer).to_lowercase());
+                let label = String::from_utf8_lossy(str_buffer);
+                if lowercase {
+                    outstr.push_str(&label.to_lowercase());
+                } else {
+                    outstr.push_str(&label);
+                }
                 delimiter = ".";
                 pos += len as usize;
             }
@@ -110,10 +145,7 @@ impl BytePacketBuffer {
     }
 
     pub fn write(&mut self, val: u8) -> Result<(), String> {
-        if self.pos >= 512 {
-            return Err("Buffer overflow".to_string());
-        }
-        self.buf[self.pos] = val;
+        self.set(self.pos, val)?;
         self.pos += 1;
         Ok(())
     }
@@ -136,14 +168,25 @@ impl BytePacketBuffer {
     }
 
     pub fn write_qname(&mut self, qname: &str) -> Result<(), String> {
+        self.write_labels(qname, true)
+    }
+
+    // Some rdata names may not be compressed, e.g. SRV targets (RFC 2782)
+    pub fn write_qname_uncompressed(&mut self, qname: &str) -> Result<(), String> {
+        self.write_labels(qname, false)
+    }
+
+    fn write_labels(&mut self, qname: &str, compress: bool) -> Result<(), String> {
         let original_pos = self.pos;
         let mut current_pos = 0;
         let qname_bytes = qname.as_bytes();
         let qname_len = qname_bytes.len();
         while current_pos < qname_len {
             let remaining = &qname[current_pos..];
-            if let Some(&pointer) = self.qname_pointer.get(remaining) {
-                return self.write_u16(0xc000 | pointer as u16);
+            if compress && self.compression {
+                if let Some(&pointer) = self.qname_pointer.get(remaining) {
+                    return self.write_u16(0xc000 | pointer as u16);
+                }
             }
             let label_end = qname_bytes[current_pos..]
                 .iter()
@@ -162,11 +205,20 @@ impl BytePacketBuffer {
                 break;
             }
         }
-        self.qname_pointer.insert(qname.to_string(), original_pos);
+        // Compression pointers only have 14 bits for the offset
+        if original_pos < 0x4000 {
+            self.qname_pointer.insert(qname.to_string(), original_pos);
+        }
         self.write_u8(0)
     }
 
     pub fn set(&mut self, pos: usize, val: u8) -> Result<(), String> {
+        if pos >= self.max_size {
+            return Err("Buffer overflow".to_string());
+        }
+        if pos >= self.buf.len() {
+            self.buf.resize(pos + 1, 0);
+        }
         self.buf[pos] = val;
         Ok(())
     }
@@ -64,7 +64,7 @@ impl DnsHeader {
         self.opcode = (a >> 3) & 0x0F;
         self.response = (a & (1 << 7)) > 0;
 
-        self.rescode = ResultCode::from_num(b & 0x0F);
+        self.rescode = ResultCode::from_num((b & 0x0F) as u16);
         self.checking_disabled = (b & (1 << 4)) > 0;
         self.authed_data = (b & (1 << 5)) > 0;
         self.z = (b & (1 << 6)) > 0;
@@ -90,7 +90,7 @@ impl DnsHeader {
         buffer.write_u8(flags)?;
 
         let mut b: u8 = 0;
-        b |= self.rescode as u8;
+        b |= (self.rescode.to_num() as u8) & 0x0F;
         b |= (self.checking_disabled as u8) << 4;
         b |= (self.authed_data as u8) << 5;
         b |= (self.z as u8) << 6;
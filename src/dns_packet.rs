@@ -1,8 +1,10 @@
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
+
+use rand::seq::SliceRandom;
 
 use crate::{
     byte_packet_buffer::BytePacketBuffer, dns_header::DnsHeader, dns_question::DnsQuestion,
-    dns_record::DnsRecord, query_type::QueryType,
+    dns_record::DnsRecord, dnssec::is_subdomain, query_type::QueryType, res_code::ResultCode,
 };
 
 #[derive(Clone, Debug)]
@@ -54,6 +56,14 @@ impl DnsPacket {
             result.resources.push(rec);
         }
 
+        // The OPT record carries the upper 8 bits of a 12 bit rcode
+        if let Some(DnsRecord::OPT { extended_rcode, .. }) = result.get_edns() {
+            if *extended_rcode > 0 {
+                let rescode = ((*extended_rcode as u16) << 4) | result.header.rescode.to_num();
+                result.header.rescode = ResultCode::from_num(rescode);
+            }
+        }
+
         Ok(result)
     }
 
@@ -79,41 +89,160 @@ impl DnsPacket {
         Ok(())
     }
 
+    pub fn get_edns(&self) -> Option<&DnsRecord> {
+        self.resources
+            .iter()
+            .find(|record| matches!(record, DnsRecord::OPT { .. }))
+    }
+
+    pub fn set_edns(&mut self, packet_len: u16, dnssec_ok: bool) {
+        self.resources
+            .retain(|record| !matches!(record, DnsRecord::OPT { .. }));
+        self.resources.push(DnsRecord::OPT {
+            packet_len,
+            extended_rcode: (self.header.rescode.to_num() >> 4) as u8,
+            version: 0,
+            dnssec_ok,
+            options: Vec::new(),
+        });
+        self.header.resource_entries = self.resources.len() as u16;
+    }
+
+    pub fn max_udp_size(&self) -> usize {
+        match self.get_edns() {
+            Some(DnsRecord::OPT { packet_len, .. }) => (*packet_len).max(512) as usize,
+            _ => 512,
+        }
+    }
+
+    pub fn dnssec_ok(&self) -> bool {
+        matches!(
+            self.get_edns(),
+            Some(DnsRecord::OPT {
+                dnssec_ok: true,
+                ..
+            })
+        )
+    }
+
+    // RFC 2308: negative answers are cached for the lesser of the SOA ttl and its MINIMUM
+    pub fn get_negative_ttl(&self) -> Option<u32> {
+        let negative = self.header.rescode == ResultCode::NXDOMAIN
+            || (self.header.rescode == ResultCode::NOERROR && self.answers.is_empty());
+        if !negative {
+            return None;
+        }
+        self.authorities.iter().find_map(|record| match record {
+            DnsRecord::SOA { minimum, ttl, .. } => Some((*minimum).min(*ttl)),
+            _ => None,
+        })
+    }
+
     pub fn get_random_a(&self) -> Option<Ipv4Addr> {
+        self.get_all_a().choose(&mut rand::thread_rng()).copied()
+    }
+
+    pub fn get_all_a(&self) -> Vec<Ipv4Addr> {
         self.answers
             .iter()
             .filter_map(|record| match record {
                 DnsRecord::A { addr, .. } => Some(*addr),
                 _ => None,
             })
-            .next()
+            .collect()
     }
 
-    fn get_ns<'a>(&'a self, qname: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> {
-        self.authorities
-            .iter()
+    // Every A and AAAA address in the answers
+    pub fn get_all_ip(&self) -> Vec<IpAddr> {
+        self.answers.iter().filter_map(address).collect()
+    }
+
+    // The NS records of a referral from the servers for zone, those for a zone that isn't
+    // both below it and above qname are out of bailiwick and ignored
+    pub fn get_delegation<'a>(
+        &'a self,
+        qname: &'a str,
+        zone: &'a str,
+    ) -> impl Iterator<Item = &'a DnsRecord> {
+        self.authorities.iter().filter(move |record| {
+            let domain = record.domain();
+            record.qtype() == QueryType::NS
+                && is_subdomain(qname, domain)
+                && is_subdomain(domain, zone)
+                && !domain.eq_ignore_ascii_case(zone)
+        })
+    }
+
+    // Addresses for the nameservers of a referral, as long as zone's servers are
+    // responsible for them
+    pub fn get_glue<'a>(
+        &'a self,
+        qname: &'a str,
+        zone: &'a str,
+    ) -> impl Iterator<Item = &'a DnsRecord> {
+        self.resources.iter().filter(move |record| {
+            address(record).is_some()
+                && self
+                    .get_ns(qname, zone)
+                    .any(|(_, host)| host.eq_ignore_ascii_case(record.domain()))
+                && is_subdomain(record.domain(), zone)
+        })
+    }
+
+    fn get_ns<'a>(
+        &'a self,
+        qname: &'a str,
+        zone: &'a str,
+    ) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.get_delegation(qname, zone)
             .filter_map(|record| match record {
                 DnsRecord::NS { domain, host, .. } => Some((domain.as_str(), host.as_str())),
                 _ => None,
             })
-            .filter(move |(domain, _)| qname.ends_with(*domain))
-    }
-
-    pub fn get_resolved_ns(&self, qname: &str) -> Option<Ipv4Addr> {
-        self.get_ns(qname)
-            .flat_map(|(_, host)| {
-                self.resources
-                    .iter()
-                    .filter_map(move |record| match record {
-                        DnsRecord::A { domain, addr, .. } if domain == host => Some(addr),
-                        _ => None,
-                    })
-            })
-            .copied()
+    }
+
+    pub fn get_resolved_ns(&self, qname: &str, zone: &str) -> Option<IpAddr> {
+        self.get_ns(qname, zone)
+            .flat_map(|(_, host)| self.glue(host, zone))
             .next()
     }
 
-    pub fn get_unresolved_ns<'a>(&'a self, qname: &'a str) -> Option<&'a str> {
-        self.get_ns(qname).map(|(_, host)| host).next()
+    pub fn get_unresolved_ns<'a>(&'a self, qname: &'a str, zone: &'a str) -> Option<&'a str> {
+        self.get_ns(qname, zone).map(|(_, host)| host).next()
+    }
+
+    // The glue addresses of every nameserver in a referral
+    pub fn get_all_resolved_ns(&self, qname: &str, zone: &str) -> Vec<IpAddr> {
+        self.get_ns(qname, zone)
+            .flat_map(|(_, host)| self.glue(host, zone))
+            .collect()
+    }
+
+    // The nameservers in a referral that came without glue, with the zone they serve
+    pub fn get_all_unresolved_ns<'a>(
+        &'a self,
+        qname: &'a str,
+        zone: &'a str,
+    ) -> Vec<(&'a str, &'a str)> {
+        self.get_ns(qname, zone)
+            .filter(|(_, host)| self.glue(host, zone).next().is_none())
+            .collect()
+    }
+
+    fn glue<'a>(&'a self, host: &'a str, zone: &'a str) -> impl Iterator<Item = IpAddr> + 'a {
+        self.resources
+            .iter()
+            .filter(move |record| {
+                record.domain().eq_ignore_ascii_case(host) && is_subdomain(host, zone)
+            })
+            .filter_map(address)
+    }
+}
+
+fn address(record: &DnsRecord) -> Option<IpAddr> {
+    match record {
+        DnsRecord::A { addr, .. } => Some(IpAddr::V4(*addr)),
+        DnsRecord::AAAA { addr, .. } => Some(IpAddr::V6(*addr)),
+        _ => None,
     }
 }
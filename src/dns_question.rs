@@ -1,20 +1,25 @@
-use crate::{byte_packet_buffer::BytePacketBuffer, query_type::QueryType};
+use crate::{byte_packet_buffer::BytePacketBuffer, query_class::QueryClass, query_type::QueryType};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DnsQuestion {
     pub name: String,
     pub qtype: QueryType,
+    pub qclass: QueryClass,
 }
 
 impl DnsQuestion {
     pub fn new(name: String, qtype: QueryType) -> DnsQuestion {
-        DnsQuestion { name, qtype }
+        DnsQuestion {
+            name,
+            qtype,
+            qclass: QueryClass::IN,
+        }
     }
 
     pub fn read(&mut self, buffer: &mut BytePacketBuffer) -> Result<(), String> {
         buffer.read_qname(&mut self.name)?;
         self.qtype = QueryType::from_num(buffer.read_u16()?); // qtype
-        let _ = buffer.read_u16()?; // class
+        self.qclass = QueryClass::from_num(buffer.read_u16()?); // class
 
         Ok(())
     }
@@ -22,7 +27,7 @@ impl DnsQuestion {
     pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<(), String> {
         buffer.write_qname(&self.name)?;
         buffer.write_u16(self.qtype.to_num())?;
-        buffer.write_u16(1)?; // class
+        buffer.write_u16(self.qclass.to_num())?;
 
         Ok(())
     }
@@ -1,6 +1,9 @@
 use std::net::{Ipv4Addr, Ipv6Addr};
 
-use crate::{byte_packet_buffer::BytePacketBuffer, query_type::QueryType};
+use crate::{
+    byte_packet_buffer::BytePacketBuffer, edns_option::EdnsOption, query_class::QueryClass,
+    query_type::QueryType, svc_param::SvcParam,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[allow(dead_code)]
@@ -8,35 +11,170 @@ pub enum DnsRecord {
     UNKNOWN {
         domain: String,
         qtype: u16,
-        data_len: u16,
+        data: Vec<u8>,
+        class: QueryClass,
         ttl: u32,
     }, // 0
     A {
         domain: String,
         addr: Ipv4Addr,
+        class: QueryClass,
         ttl: u32,
     }, // 1
     NS {
         domain: String,
         host: String,
+        class: QueryClass,
         ttl: u32,
     },
     CNAME {
         domain: String,
         host: String,
+        class: QueryClass,
+        ttl: u32,
+    },
+    SOA {
+        domain: String,
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+        class: QueryClass,
+        ttl: u32,
+    },
+    PTR {
+        domain: String,
+        host: String,
+        class: QueryClass,
         ttl: u32,
     },
     MX {
         domain: String,
         priority: u16,
         host: String,
+        class: QueryClass,
+        ttl: u32,
+    },
+    TXT {
+        domain: String,
+        data: Vec<Vec<u8>>,
+        class: QueryClass,
         ttl: u32,
     },
     AAAA {
         domain: String,
         addr: Ipv6Addr,
+        class: QueryClass,
+        ttl: u32,
+    },
+    SRV {
+        domain: String,
+        priority: u16,
+        weight: u16,
+        port: u16,
+        host: String,
+        class: QueryClass,
+        ttl: u32,
+    },
+    DNAME {
+        domain: String,
+        target: String,
+        class: QueryClass,
+        ttl: u32,
+    },
+    DS {
+        domain: String,
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Vec<u8>,
+        class: QueryClass,
+        ttl: u32,
+    },
+    RRSIG {
+        domain: String,
+        type_covered: QueryType,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer_name: String,
+        signature: Vec<u8>,
+        class: QueryClass,
+        ttl: u32,
+    },
+    NSEC {
+        domain: String,
+        next_domain: String,
+        types: Vec<QueryType>,
+        class: QueryClass,
+        ttl: u32,
+    },
+    DNSKEY {
+        domain: String,
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+        class: QueryClass,
+        ttl: u32,
+    },
+    NSEC3 {
+        domain: String,
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+        next_hashed_owner: Vec<u8>,
+        types: Vec<QueryType>,
+        class: QueryClass,
+        ttl: u32,
+    },
+    NSEC3PARAM {
+        domain: String,
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+        class: QueryClass,
+        ttl: u32,
+    },
+    SVCB {
+        domain: String,
+        priority: u16,
+        target: String,
+        params: Vec<SvcParam>,
+        class: QueryClass,
+        ttl: u32,
+    },
+    HTTPS {
+        domain: String,
+        priority: u16,
+        target: String,
+        params: Vec<SvcParam>,
+        class: QueryClass,
         ttl: u32,
     },
+    CAA {
+        domain: String,
+        flags: u8,
+        tag: String,
+        value: Vec<u8>,
+        class: QueryClass,
+        ttl: u32,
+    },
+    OPT {
+        packet_len: u16,
+        extended_rcode: u8,
+        version: u8,
+        dnssec_ok: bool,
+        options: Vec<EdnsOption>,
+    },
 }
 
 impl DnsRecord {
@@ -46,7 +184,8 @@ impl DnsRecord {
 
         let qtype_num = buffer.read_u16()?;
         let qtype = QueryType::from_num(qtype_num);
-        let _ = buffer.read_u16()?;
+        let class_num = buffer.read_u16()?;
+        let class = QueryClass::from_num(class_num);
         let ttl = buffer.read_u32()?;
         let data_len = buffer.read_u16()?;
 
@@ -60,7 +199,12 @@ impl DnsRecord {
                     (raw_addr & 0xFF) as u8,
                 );
 
-                Ok(DnsRecord::A { domain, addr, ttl })
+                Ok(DnsRecord::A {
+                    domain,
+                    addr,
+                    class,
+                    ttl,
+                })
             }
             QueryType::AAAA => {
                 let a = buffer.read_u16()?;
@@ -73,19 +217,69 @@ impl DnsRecord {
                 let h = buffer.read_u16()?;
                 let addr = Ipv6Addr::new(a, b, c, d, e, f, g, h);
 
-                Ok(DnsRecord::AAAA { domain, addr, ttl })
+                Ok(DnsRecord::AAAA {
+                    domain,
+                    addr,
+                    class,
+                    ttl,
+                })
             }
             QueryType::NS => {
                 let mut host = String::new();
                 buffer.read_qname(&mut host)?;
 
-                Ok(DnsRecord::NS { domain, host, ttl })
+                Ok(DnsRecord::NS {
+                    domain,
+                    host,
+                    class,
+                    ttl,
+                })
             }
             QueryType::CNAME => {
                 let mut host = String::new();
                 buffer.read_qname(&mut host)?;
 
-                Ok(DnsRecord::CNAME { domain, host, ttl })
+                Ok(DnsRecord::CNAME {
+                    domain,
+                    host,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::SOA => {
+                let mut mname = String::new();
+                buffer.read_qname(&mut mname)?;
+                let mut rname = String::new();
+                buffer.read_qname(&mut rname)?;
+                let serial = buffer.read_u32()?;
+                let refresh = buffer.read_u32()?;
+                let retry = buffer.read_u32()?;
+                let expire = buffer.read_u32()?;
+                let minimum = buffer.read_u32()?;
+
+                Ok(DnsRecord::SOA {
+                    domain,
+                    mname,
+                    rname,
+                    serial,
+                    refresh,
+                    retry,
+                    expire,
+                    minimum,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::PTR => {
+                let mut host = String::new();
+                buffer.read_qname(&mut host)?;
+
+                Ok(DnsRecord::PTR {
+                    domain,
+                    host,
+                    class,
+                    ttl,
+                })
             }
             QueryType::MX => {
                 let priority = buffer.read_u16()?;
@@ -96,16 +290,256 @@ impl DnsRecord {
                     domain,
                     priority,
                     host,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::TXT => {
+                // One or more length prefixed character strings
+                let end = buffer.pos + data_len as usize;
+                let mut data = Vec::new();
+                while buffer.pos < end {
+                    let len = buffer.read()? as usize;
+                    data.push(buffer.get_range(buffer.pos, len)?.to_vec());
+                    buffer.step(len)?;
+                }
+
+                Ok(DnsRecord::TXT {
+                    domain,
+                    data,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::SRV => {
+                let priority = buffer.read_u16()?;
+                let weight = buffer.read_u16()?;
+                let port = buffer.read_u16()?;
+                let mut host = String::new();
+                buffer.read_qname(&mut host)?;
+
+                Ok(DnsRecord::SRV {
+                    domain,
+                    priority,
+                    weight,
+                    port,
+                    host,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::DNAME => {
+                let mut target = String::new();
+                buffer.read_qname(&mut target)?;
+
+                Ok(DnsRecord::DNAME {
+                    domain,
+                    target,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::DS => {
+                let end = buffer.pos + data_len as usize;
+                let key_tag = buffer.read_u16()?;
+                let algorithm = buffer.read()?;
+                let digest_type = buffer.read()?;
+                let digest = read_until(buffer, end)?;
+
+                Ok(DnsRecord::DS {
+                    domain,
+                    key_tag,
+                    algorithm,
+                    digest_type,
+                    digest,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::RRSIG => {
+                let end = buffer.pos + data_len as usize;
+                let type_covered = QueryType::from_num(buffer.read_u16()?);
+                let algorithm = buffer.read()?;
+                let labels = buffer.read()?;
+                let original_ttl = buffer.read_u32()?;
+                let expiration = buffer.read_u32()?;
+                let inception = buffer.read_u32()?;
+                let key_tag = buffer.read_u16()?;
+                let mut signer_name = String::new();
+                buffer.read_qname(&mut signer_name)?;
+                let signature = read_until(buffer, end)?;
+
+                Ok(DnsRecord::RRSIG {
+                    domain,
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    expiration,
+                    inception,
+                    key_tag,
+                    signer_name,
+                    signature,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::NSEC => {
+                let end = buffer.pos + data_len as usize;
+                let mut next_domain = String::new();
+                buffer.read_qname(&mut next_domain)?;
+                let types = read_type_bitmap(buffer, end)?;
+
+                Ok(DnsRecord::NSEC {
+                    domain,
+                    next_domain,
+                    types,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::DNSKEY => {
+                let end = buffer.pos + data_len as usize;
+                let flags = buffer.read_u16()?;
+                let protocol = buffer.read()?;
+                let algorithm = buffer.read()?;
+                let public_key = read_until(buffer, end)?;
+
+                Ok(DnsRecord::DNSKEY {
+                    domain,
+                    flags,
+                    protocol,
+                    algorithm,
+                    public_key,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::NSEC3 => {
+                let end = buffer.pos + data_len as usize;
+                let hash_algorithm = buffer.read()?;
+                let flags = buffer.read()?;
+                let iterations = buffer.read_u16()?;
+                let salt_len = buffer.read()? as usize;
+                let salt = read_until(buffer, buffer.pos + salt_len)?;
+                let hash_len = buffer.read()? as usize;
+                let next_hashed_owner = read_until(buffer, buffer.pos + hash_len)?;
+                let types = read_type_bitmap(buffer, end)?;
+
+                Ok(DnsRecord::NSEC3 {
+                    domain,
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                    next_hashed_owner,
+                    types,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::NSEC3PARAM => {
+                let hash_algorithm = buffer.read()?;
+                let flags = buffer.read()?;
+                let iterations = buffer.read_u16()?;
+                let salt_len = buffer.read()? as usize;
+                let salt = read_until(buffer, buffer.pos + salt_len)?;
+
+                Ok(DnsRecord::NSEC3PARAM {
+                    domain,
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                    class,
                     ttl,
                 })
             }
+            QueryType::SVCB | QueryType::HTTPS => {
+                let end = buffer.pos + data_len as usize;
+                let priority = buffer.read_u16()?;
+                let mut target = String::new();
+                buffer.read_qname(&mut target)?;
+                let mut params = Vec::new();
+                while buffer.pos < end {
+                    params.push(SvcParam::read(buffer)?);
+                }
+
+                if qtype == QueryType::SVCB {
+                    Ok(DnsRecord::SVCB {
+                        domain,
+                        priority,
+                        target,
+                        params,
+                        class,
+                        ttl,
+                    })
+                } else {
+                    Ok(DnsRecord::HTTPS {
+                        domain,
+                        priority,
+                        target,
+                        params,
+                        class,
+                        ttl,
+                    })
+                }
+            }
+            QueryType::CAA => {
+                let flags = buffer.read()?;
+                let tag_len = buffer.read()? as usize;
+                // RFC 8659 4.1 requires a tag of at least one character
+                if tag_len == 0 {
+                    return Err("Empty CAA tag".to_string());
+                }
+                let tag =
+                    String::from_utf8_lossy(buffer.get_range(buffer.pos, tag_len)?).to_string();
+                buffer.step(tag_len)?;
+                let value_len = (data_len as usize)
+                    .checked_sub(2 + tag_len)
+                    .ok_or("CAA tag longer than its record")?;
+                let value = buffer.get_range(buffer.pos, value_len)?.to_vec();
+                buffer.step(value_len)?;
+
+                Ok(DnsRecord::CAA {
+                    domain,
+                    flags,
+                    tag,
+                    value,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::OPT => {
+                // The class and ttl fields are reused for the EDNS(0) header
+                let end = buffer.pos + data_len as usize;
+                let mut options = Vec::new();
+                while buffer.pos < end {
+                    let code = buffer.read_u16()?;
+                    let len = buffer.read_u16()? as usize;
+                    let data = buffer.get_range(buffer.pos, len)?.to_vec();
+                    buffer.step(len)?;
+                    options.push(EdnsOption::new(code, data));
+                }
+
+                Ok(DnsRecord::OPT {
+                    packet_len: class_num,
+                    extended_rcode: (ttl >> 24) as u8,
+                    version: (ttl >> 16) as u8,
+                    dnssec_ok: (ttl & 0x8000) > 0,
+                    options,
+                })
+            }
             QueryType::UNKNOWN(_) => {
+                // Keep the rdata as is so it can be passed through untouched
+                let data = buffer.get_range(buffer.pos, data_len as usize)?.to_vec();
                 buffer.step(data_len as usize)?;
 
                 Ok(DnsRecord::UNKNOWN {
                     domain,
                     qtype: qtype_num,
-                    data_len,
+                    data,
+                    class,
                     ttl,
                 })
             }
@@ -114,10 +548,15 @@ impl DnsRecord {
 
     pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<(), String> {
         match self {
-            DnsRecord::A { domain, addr, ttl } => {
+            DnsRecord::A {
+                domain,
+                addr,
+                class,
+                ttl,
+            } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::A.to_num())?;
-                buffer.write_u16(1)?; // class
+                buffer.write_u16(class.to_num())?;
                 buffer.write_u32(*ttl)?;
                 buffer.write_u16(4)?; // data length
                 buffer.write_u8(addr.octets()[0])?;
@@ -125,20 +564,30 @@ impl DnsRecord {
                 buffer.write_u8(addr.octets()[2])?;
                 buffer.write_u8(addr.octets()[3])?;
             }
-            DnsRecord::AAAA { domain, addr, ttl } => {
+            DnsRecord::AAAA {
+                domain,
+                addr,
+                class,
+                ttl,
+            } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::AAAA.to_num())?;
-                buffer.write_u16(1)?; // class
+                buffer.write_u16(class.to_num())?;
                 buffer.write_u32(*ttl)?;
                 buffer.write_u16(16)?; // data length
                 for octet in &addr.segments() {
                     buffer.write_u16(*octet)?;
                 }
             }
-            DnsRecord::NS { domain, host, ttl } => {
+            DnsRecord::NS {
+                domain,
+                host,
+                class,
+                ttl,
+            } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::NS.to_num())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_num())?;
                 buffer.write_u32(*ttl)?;
                 let pos = buffer.pos;
                 buffer.write_u16(0)?;
@@ -146,10 +595,15 @@ impl DnsRecord {
                 let size = buffer.pos - (pos + 2);
                 buffer.set_u16(pos, size as u16)?;
             }
-            DnsRecord::CNAME { domain, host, ttl } => {
+            DnsRecord::CNAME {
+                domain,
+                host,
+                class,
+                ttl,
+            } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::CNAME.to_num())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_num())?;
                 buffer.write_u32(*ttl)?;
                 let pos = buffer.pos;
                 buffer.write_u16(0)?;
@@ -157,33 +611,616 @@ impl DnsRecord {
                 let size = buffer.pos - (pos + 2);
                 buffer.set_u16(pos, size as u16)?;
             }
+            DnsRecord::SOA {
+                domain,
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                class,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::SOA.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+                buffer.write_qname(mname)?;
+                buffer.write_qname(rname)?;
+                buffer.write_u32(*serial)?;
+                buffer.write_u32(*refresh)?;
+                buffer.write_u32(*retry)?;
+                buffer.write_u32(*expire)?;
+                buffer.write_u32(*minimum)?;
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
             DnsRecord::MX {
                 domain,
                 priority,
                 host,
+                class,
                 ttl,
             } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::MX.to_num())?;
-                buffer.write_u16(1)?; // class
+                buffer.write_u16(class.to_num())?;
                 buffer.write_u32(*ttl)?;
-                buffer.write_u16(2 + host.len() as u16)?;
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
                 buffer.write_u16(*priority)?;
                 buffer.write_qname(host)?;
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::PTR {
+                domain,
+                host,
+                class,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::PTR.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+                buffer.write_qname(host)?;
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::TXT {
+                domain,
+                data,
+                class,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::TXT.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+                for string in data {
+                    if string.len() > 255 {
+                        return Err("TXT string too long".to_string());
+                    }
+                    buffer.write_u8(string.len() as u8)?;
+                    for &byte in string {
+                        buffer.write(byte)?;
+                    }
+                }
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::SRV {
+                domain,
+                priority,
+                weight,
+                port,
+                host,
+                class,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::SRV.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+                buffer.write_u16(*priority)?;
+                buffer.write_u16(*weight)?;
+                buffer.write_u16(*port)?;
+                buffer.write_qname_uncompressed(host)?;
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::DNAME {
+                domain,
+                target,
+                class,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::DNAME.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+                // The target may not be compressed (RFC 6672 2.5)
+                buffer.write_qname_uncompressed(target)?;
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::DS {
+                domain,
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+                class,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::DS.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+                buffer.write_u16(4 + digest.len() as u16)?;
+                buffer.write_u16(*key_tag)?;
+                buffer.write_u8(*algorithm)?;
+                buffer.write_u8(*digest_type)?;
+                write_bytes(buffer, digest)?;
+            }
+            DnsRecord::RRSIG {
+                domain,
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer_name,
+                signature,
+                class,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::RRSIG.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+                buffer.write_u16(type_covered.to_num())?;
+                buffer.write_u8(*algorithm)?;
+                buffer.write_u8(*labels)?;
+                buffer.write_u32(*original_ttl)?;
+                buffer.write_u32(*expiration)?;
+                buffer.write_u32(*inception)?;
+                buffer.write_u16(*key_tag)?;
+                // RFC 4034 forbids compressing the signer name
+                buffer.write_qname_uncompressed(signer_name)?;
+                write_bytes(buffer, signature)?;
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::NSEC {
+                domain,
+                next_domain,
+                types,
+                class,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::NSEC.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+                buffer.write_qname_uncompressed(next_domain)?;
+                write_type_bitmap(buffer, types)?;
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::DNSKEY {
+                domain,
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+                class,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::DNSKEY.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+                buffer.write_u16(4 + public_key.len() as u16)?;
+                buffer.write_u16(*flags)?;
+                buffer.write_u8(*protocol)?;
+                buffer.write_u8(*algorithm)?;
+                write_bytes(buffer, public_key)?;
+            }
+            DnsRecord::NSEC3 {
+                domain,
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed_owner,
+                types,
+                class,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::NSEC3.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+                buffer.write_u8(*hash_algorithm)?;
+                buffer.write_u8(*flags)?;
+                buffer.write_u16(*iterations)?;
+                buffer.write_u8(salt.len() as u8)?;
+                write_bytes(buffer, salt)?;
+                buffer.write_u8(next_hashed_owner.len() as u8)?;
+                write_bytes(buffer, next_hashed_owner)?;
+                write_type_bitmap(buffer, types)?;
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::NSEC3PARAM {
+                domain,
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                class,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::NSEC3PARAM.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+                buffer.write_u16(5 + salt.len() as u16)?;
+                buffer.write_u8(*hash_algorithm)?;
+                buffer.write_u8(*flags)?;
+                buffer.write_u16(*iterations)?;
+                buffer.write_u8(salt.len() as u8)?;
+                write_bytes(buffer, salt)?;
+            }
+            DnsRecord::SVCB {
+                domain,
+                priority,
+                target,
+                params,
+                class,
+                ttl,
+            }
+            | DnsRecord::HTTPS {
+                domain,
+                priority,
+                target,
+                params,
+                class,
+                ttl,
+            } => {
+                let qtype = match self {
+                    DnsRecord::SVCB { .. } => QueryType::SVCB,
+                    _ => QueryType::HTTPS,
+                };
+                buffer.write_qname(domain)?;
+                buffer.write_u16(qtype.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+                buffer.write_u16(*priority)?;
+                // RFC 9460 forbids compressing the target name
+                buffer.write_qname_uncompressed(target)?;
+                for param in params {
+                    param.write(buffer)?;
+                }
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::CAA {
+                domain,
+                flags,
+                tag,
+                value,
+                class,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::CAA.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+                if tag.is_empty() || tag.len() > 255 {
+                    return Err("Invalid CAA tag length".to_string());
+                }
+                buffer.write_u16((2 + tag.len() + value.len()) as u16)?;
+                buffer.write_u8(*flags)?;
+                buffer.write_u8(tag.len() as u8)?;
+                for &byte in tag.as_bytes().iter().chain(value) {
+                    buffer.write(byte)?;
+                }
+            }
+            DnsRecord::OPT {
+                packet_len,
+                extended_rcode,
+                version,
+                dnssec_ok,
+                options,
+            } => {
+                buffer.write_u8(0)?; // root domain
+                buffer.write_u16(QueryType::OPT.to_num())?;
+                buffer.write_u16(*packet_len)?;
+                buffer.write_u8(*extended_rcode)?;
+                buffer.write_u8(*version)?;
+                buffer.write_u16((*dnssec_ok as u16) << 15)?;
+                let pos = buffer.pos;
+                buffer.write_u16(0)?;
+                for option in options {
+                    buffer.write_u16(option.code)?;
+                    buffer.write_u16(option.data.len() as u16)?;
+                    for &byte in &option.data {
+                        buffer.write(byte)?;
+                    }
+                }
+                let size = buffer.pos - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
             }
             DnsRecord::UNKNOWN {
                 domain,
                 qtype,
-                data_len,
+                data,
+                class,
                 ttl,
             } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(*qtype)?;
-                buffer.write_u16(1)?; // class
+                buffer.write_u16(class.to_num())?;
                 buffer.write_u32(*ttl)?;
-                buffer.write_u16(*data_len)?;
+                buffer.write_u16(data.len() as u16)?;
+                for &byte in data {
+                    buffer.write(byte)?;
+                }
             }
         }
         Ok(())
     }
+
+    // RFC 3597 generic presentation of the rdata, e.g. \# 4 0a000001
+    pub fn to_generic_rdata(&self) -> Option<String> {
+        match self {
+            DnsRecord::UNKNOWN { data, .. } => {
+                let mut text = format!("\\# {}", data.len());
+                if !data.is_empty() {
+                    text.push(' ');
+                    for byte in data {
+                        text.push_str(&format!("{:02x}", byte));
+                    }
+                }
+                Some(text)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn from_generic_rdata(
+        domain: String,
+        qtype: u16,
+        class: QueryClass,
+        ttl: u32,
+        text: &str,
+    ) -> Result<DnsRecord, String> {
+        let mut tokens = text.split_whitespace();
+        if tokens.next() != Some("\\#") {
+            return Err("Generic rdata must start with \\#".to_string());
+        }
+        let len = tokens
+            .next()
+            .and_then(|len| len.parse::<usize>().ok())
+            .ok_or("Generic rdata is missing its length")?;
+        // The hex may be split into any number of whitespace separated chunks
+        let hex: String = tokens.collect();
+        if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+            return Err("Generic rdata must be an even number of hex digits".to_string());
+        }
+        let data = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|e| e.to_string())?;
+        if data.len() != len {
+            return Err(format!(
+                "Generic rdata length {} doesn't match {} bytes of data",
+                len,
+                data.len()
+            ));
+        }
+
+        Ok(DnsRecord::UNKNOWN {
+            domain,
+            qtype,
+            data,
+            class,
+            ttl,
+        })
+    }
+
+    pub fn ttl(&self) -> u32 {
+        match self {
+            DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::DNAME { ttl, .. }
+            | DnsRecord::DS { ttl, .. }
+            | DnsRecord::RRSIG { ttl, .. }
+            | DnsRecord::NSEC { ttl, .. }
+            | DnsRecord::DNSKEY { ttl, .. }
+            | DnsRecord::NSEC3 { ttl, .. }
+            | DnsRecord::NSEC3PARAM { ttl, .. }
+            | DnsRecord::SVCB { ttl, .. }
+            | DnsRecord::HTTPS { ttl, .. }
+            | DnsRecord::CAA { ttl, .. }
+            | DnsRecord::UNKNOWN { ttl, .. } => *ttl,
+            DnsRecord::OPT { .. } => 0,
+        }
+    }
+
+    pub fn set_ttl(&mut self, new_ttl: u32) {
+        match self {
+            DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::DNAME { ttl, .. }
+            | DnsRecord::DS { ttl, .. }
+            | DnsRecord::RRSIG { ttl, .. }
+            | DnsRecord::NSEC { ttl, .. }
+            | DnsRecord::DNSKEY { ttl, .. }
+            | DnsRecord::NSEC3 { ttl, .. }
+            | DnsRecord::NSEC3PARAM { ttl, .. }
+            | DnsRecord::SVCB { ttl, .. }
+            | DnsRecord::HTTPS { ttl, .. }
+            | DnsRecord::CAA { ttl, .. }
+            | DnsRecord::UNKNOWN { ttl, .. } => *ttl = new_ttl,
+            DnsRecord::OPT { .. } => {}
+        }
+    }
+
+    pub fn domain(&self) -> &str {
+        match self {
+            DnsRecord::A { domain, .. }
+            | DnsRecord::NS { domain, .. }
+            | DnsRecord::CNAME { domain, .. }
+            | DnsRecord::SOA { domain, .. }
+            | DnsRecord::PTR { domain, .. }
+            | DnsRecord::MX { domain, .. }
+            | DnsRecord::TXT { domain, .. }
+            | DnsRecord::AAAA { domain, .. }
+            | DnsRecord::SRV { domain, .. }
+            | DnsRecord::DNAME { domain, .. }
+            | DnsRecord::DS { domain, .. }
+            | DnsRecord::RRSIG { domain, .. }
+            | DnsRecord::NSEC { domain, .. }
+            | DnsRecord::DNSKEY { domain, .. }
+            | DnsRecord::NSEC3 { domain, .. }
+            | DnsRecord::NSEC3PARAM { domain, .. }
+            | DnsRecord::SVCB { domain, .. }
+            | DnsRecord::HTTPS { domain, .. }
+            | DnsRecord::CAA { domain, .. }
+            | DnsRecord::UNKNOWN { domain, .. } => domain,
+            DnsRecord::OPT { .. } => "",
+        }
+    }
+
+    pub fn qtype(&self) -> QueryType {
+        match self {
+            DnsRecord::UNKNOWN { qtype, .. } => QueryType::from_num(*qtype),
+            DnsRecord::A { .. } => QueryType::A,
+            DnsRecord::NS { .. } => QueryType::NS,
+            DnsRecord::CNAME { .. } => QueryType::CNAME,
+            DnsRecord::SOA { .. } => QueryType::SOA,
+            DnsRecord::PTR { .. } => QueryType::PTR,
+            DnsRecord::MX { .. } => QueryType::MX,
+            DnsRecord::TXT { .. } => QueryType::TXT,
+            DnsRecord::AAAA { .. } => QueryType::AAAA,
+            DnsRecord::SRV { .. } => QueryType::SRV,
+            DnsRecord::DNAME { .. } => QueryType::DNAME,
+            DnsRecord::DS { .. } => QueryType::DS,
+            DnsRecord::RRSIG { .. } => QueryType::RRSIG,
+            DnsRecord::NSEC { .. } => QueryType::NSEC,
+            DnsRecord::DNSKEY { .. } => QueryType::DNSKEY,
+            DnsRecord::NSEC3 { .. } => QueryType::NSEC3,
+            DnsRecord::NSEC3PARAM { .. } => QueryType::NSEC3PARAM,
+            DnsRecord::SVCB { .. } => QueryType::SVCB,
+            DnsRecord::HTTPS { .. } => QueryType::HTTPS,
+            DnsRecord::CAA { .. } => QueryType::CAA,
+            DnsRecord::OPT { .. } => QueryType::OPT,
+        }
+    }
+
+    // OPT reuses the class field for the UDP payload size
+    pub fn class(&self) -> QueryClass {
+        match self {
+            DnsRecord::A { class, .. }
+            | DnsRecord::NS { class, .. }
+            | DnsRecord::CNAME { class, .. }
+            | DnsRecord::SOA { class, .. }
+            | DnsRecord::PTR { class, .. }
+            | DnsRecord::MX { class, .. }
+            | DnsRecord::TXT { class, .. }
+            | DnsRecord::AAAA { class, .. }
+            | DnsRecord::SRV { class, .. }
+            | DnsRecord::DNAME { class, .. }
+            | DnsRecord::DS { class, .. }
+            | DnsRecord::RRSIG { class, .. }
+            | DnsRecord::NSEC { class, .. }
+            | DnsRecord::DNSKEY { class, .. }
+            | DnsRecord::NSEC3 { class, .. }
+            | DnsRecord::NSEC3PARAM { class, .. }
+            | DnsRecord::SVCB { class, .. }
+            | DnsRecord::HTTPS { class, .. }
+            | DnsRecord::CAA { class, .. }
+            | DnsRecord::UNKNOWN { class, .. } => *class,
+            DnsRecord::OPT { packet_len, .. } => QueryClass::UNKNOWN(*packet_len),
+        }
+    }
+}
+
+fn read_until(buffer: &mut BytePacketBuffer, end: usize) -> Result<Vec<u8>, String> {
+    let len = end
+        .checked_sub(buffer.pos)
+        .ok_or("Record data overruns its length")?;
+    let data = buffer.get_range(buffer.pos, len)?.to_vec();
+    buffer.step(len)?;
+    Ok(data)
+}
+
+fn write_bytes(buffer: &mut BytePacketBuffer, data: &[u8]) -> Result<(), String> {
+    for &byte in data {
+        buffer.write(byte)?;
+    }
+    Ok(())
+}
+
+// RFC 4034 4.1.2: the types are split into windows of 256, each a bitmap of up to 32 bytes
+fn read_type_bitmap(buffer: &mut BytePacketBuffer, end: usize) -> Result<Vec<QueryType>, String> {
+    let mut types = Vec::new();
+    while buffer.pos < end {
+        let window = buffer.read()? as u16;
+        let len = buffer.read()? as usize;
+        if len == 0 || len > 32 {
+            return Err("Invalid type bitmap length".to_string());
+        }
+        for i in 0..len {
+            let bits = buffer.read()?;
+            for bit in 0..8 {
+                if bits & (0x80 >> bit) > 0 {
+                    let num = (window << 8) | (i as u16 * 8 + bit);
+                    types.push(QueryType::from_num(num));
+                }
+            }
+        }
+    }
+    Ok(types)
+}
+
+fn write_type_bitmap(buffer: &mut BytePacketBuffer, types: &[QueryType]) -> Result<(), String> {
+    let mut nums: Vec<u16> = types.iter().map(QueryType::to_num).collect();
+    nums.sort_unstable();
+    nums.dedup();
+    let mut nums = nums.into_iter().peekable();
+    while let Some(&first) = nums.peek() {
+        let window = first >> 8;
+        let mut bitmap = [0u8; 32];
+        let mut len = 0;
+        while let Some(num) = nums.next_if(|num| num >> 8 == window) {
+            let offset = (num & 0xFF) as usize;
+            bitmap[offset / 8] |= 0x80 >> (offset % 8);
+            len = offset / 8 + 1;
+        }
+        buffer.write_u8(window as u8)?;
+        buffer.write_u8(len as u8)?;
+        write_bytes(buffer, &bitmap[..len])?;
+    }
+    Ok(())
 }
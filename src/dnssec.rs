@@ -0,0 +1,611 @@
+use std::cmp::Ordering;
+
+use ring::{digest, signature};
+
+use crate::{byte_packet_buffer::BytePacketBuffer, dns_record::DnsRecord, query_type::QueryType};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Security {
+    Secure,
+    Insecure,
+    Bogus(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Denial {
+    // The name does not exist
+    NoName,
+    // The name exists without the type; delegation means it is an unsigned zone cut
+    NoData { delegation: bool },
+    // Opt-out or too many NSEC3 iterations, nothing can be proven either way
+    Insecure,
+}
+
+// RSASHA256, ECDSAP256SHA256, ECDSAP384SHA384 and ED25519
+pub fn supported_algorithm(algorithm: u8) -> bool {
+    matches!(algorithm, 8 | 13 | 14 | 15)
+}
+
+// SHA-1, SHA-256 and SHA-384
+pub fn supported_digest(digest_type: u8) -> bool {
+    matches!(digest_type, 1 | 2 | 4)
+}
+
+pub fn canonical_name(name: &str) -> Result<Vec<u8>, String> {
+    let mut buffer = BytePacketBuffer::with_max_size(255);
+    buffer.write_qname_uncompressed(&name.to_lowercase())?;
+    Ok(buffer.buf[0..buffer.pos].to_vec())
+}
+
+// RDATA with its names uncompressed (RFC 4034 6.2), names are lowercased when read
+pub fn canonical_rdata(record: &DnsRecord) -> Result<Vec<u8>, String> {
+    let mut buffer = BytePacketBuffer::with_max_size(u16::MAX as usize);
+    buffer.compression = false;
+    record.write(&mut buffer)?;
+    // Skip the owner name, type, class, ttl and rdata length
+    let start = canonical_name(record.domain())?.len() + 10;
+    Ok(buffer.buf[start..buffer.pos].to_vec())
+}
+
+// Labels compared right to left, case-insensitively (RFC 4034 6.1)
+pub fn canonical_cmp(a: &str, b: &str) -> Ordering {
+    let a = a.to_lowercase();
+    let b = b.to_lowercase();
+    let a_labels = a.split('.').filter(|l| !l.is_empty()).rev();
+    let b_labels = b.split('.').filter(|l| !l.is_empty()).rev();
+    a_labels.map(str::as_bytes).cmp(b_labels.map(str::as_bytes))
+}
+
+pub fn is_subdomain(name: &str, zone: &str) -> bool {
+    let name = name.to_lowercase();
+    let zone = zone.to_lowercase();
+    zone.is_empty() || name == zone || name.ends_with(&format!(".{}", zone))
+}
+
+// The wildcard label isn't counted, like the RRSIG labels field
+pub fn label_count(name: &str) -> u8 {
+    let labels = name.split('.').filter(|l| !l.is_empty());
+    let count = labels.clone().count();
+    match labels.clone().next() {
+        Some("*") => (count - 1) as u8,
+        _ => count as u8,
+    }
+}
+
+pub fn key_tag(dnskey: &DnsRecord) -> Result<u16, String> {
+    let rdata = canonical_rdata(dnskey)?;
+    let mut acc: u32 = 0;
+    for (i, byte) in rdata.iter().enumerate() {
+        acc += if i & 1 == 0 {
+            (*byte as u32) << 8
+        } else {
+            *byte as u32
+        };
+    }
+    acc += (acc >> 16) & 0xFFFF;
+    Ok((acc & 0xFFFF) as u16)
+}
+
+pub fn ds_digest(dnskey: &DnsRecord, digest_type: u8) -> Result<Vec<u8>, String> {
+    let algorithm = match digest_type {
+        1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        2 => &digest::SHA256,
+        4 => &digest::SHA384,
+        _ => return Err(format!("Unsupported DS digest type {}", digest_type)),
+    };
+    let mut data = canonical_name(dnskey.domain())?;
+    data.extend(canonical_rdata(dnskey)?);
+    Ok(digest::digest(algorithm, &data).as_ref().to_vec())
+}
+
+pub fn verify_ds(ds: &DnsRecord, dnskey: &DnsRecord) -> bool {
+    let (
+        DnsRecord::DS {
+            key_tag: tag,
+            algorithm,
+            digest_type,
+            digest,
+            ..
+        },
+        DnsRecord::DNSKEY {
+            algorithm: key_algorithm,
+            ..
+        },
+    ) = (ds, dnskey)
+    else {
+        return false;
+    };
+    ds.domain().eq_ignore_ascii_case(dnskey.domain())
+        && algorithm == key_algorithm
+        && key_tag(dnskey) == Ok(*tag)
+        && ds_digest(dnskey, *digest_type).is_ok_and(|d| &d == digest)
+}
+
+// The data an RRSIG signs (RFC 4034 3.1.8.1)
+pub fn signed_data(rrset: &[DnsRecord], rrsig: &DnsRecord) -> Result<Vec<u8>, String> {
+    let DnsRecord::RRSIG {
+        labels,
+        original_ttl,
+        ..
+    } = rrsig
+    else {
+        return Err("Not an RRSIG record".to_string());
+    };
+    let first = rrset.first().ok_or("Empty RRset")?;
+    let mut unsigned = rrsig.clone();
+    if let DnsRecord::RRSIG { signature, .. } = &mut unsigned {
+        signature.clear();
+    }
+    let mut data = canonical_rdata(&unsigned)?;
+
+    let mut rdatas = Vec::new();
+    for record in rrset {
+        rdatas.push(canonical_rdata(record)?);
+    }
+    rdatas.sort();
+    rdatas.dedup();
+
+    let owner = canonical_name(&signed_owner(first.domain(), *labels))?;
+    for rdata in rdatas {
+        data.extend(&owner);
+        data.extend(first.qtype().to_num().to_be_bytes());
+        data.extend(first.class().to_num().to_be_bytes());
+        data.extend(original_ttl.to_be_bytes());
+        data.extend((rdata.len() as u16).to_be_bytes());
+        data.extend(rdata);
+    }
+    Ok(data)
+}
+
+// Wildcard expansions are signed under the wildcard name (RFC 4035 5.3.2)
+fn signed_owner(domain: &str, labels: u8) -> String {
+    let parts: Vec<&str> = domain.split('.').filter(|l| !l.is_empty()).collect();
+    if labels as usize >= parts.len() {
+        return domain.to_string();
+    }
+    let suffix = parts[parts.len() - labels as usize..].join(".");
+    if suffix.is_empty() {
+        "*".to_string()
+    } else {
+        format!("*.{}", suffix)
+    }
+}
+
+pub fn verify_rrsig(
+    rrset: &[DnsRecord],
+    rrsig: &DnsRecord,
+    dnskey: &DnsRecord,
+    now: u32,
+) -> Result<(), String> {
+    let DnsRecord::RRSIG {
+        type_covered,
+        algorithm,
+        labels,
+        expiration,
+        inception,
+        key_tag: tag,
+        signer_name,
+        signature,
+        ..
+    } = rrsig
+    else {
+        return Err("Not an RRSIG record".to_string());
+    };
+    let DnsRecord::DNSKEY {
+        flags,
+        protocol,
+        algorithm: key_algorithm,
+        public_key,
+        ..
+    } = dnskey
+    else {
+        return Err("Not a DNSKEY record".to_string());
+    };
+    let first = rrset.first().ok_or("Empty RRset")?;
+    if first.qtype() != *type_covered {
+        return Err("RRSIG covers a different type".to_string());
+    }
+    if !signer_name.eq_ignore_ascii_case(dnskey.domain())
+        || !is_subdomain(first.domain(), signer_name)
+    {
+        return Err(format!(
+            "{} can't be signed by {}",
+            first.domain(),
+            signer_name
+        ));
+    }
+    if flags & 0x0100 == 0 || *protocol != 3 {
+        return Err("DNSKEY is not a zone key".to_string());
+    }
+    if algorithm != key_algorithm || key_tag(dnskey)? != *tag {
+        return Err("RRSIG was made with a different key".to_string());
+    }
+    if *labels > label_count(first.domain()) {
+        return Err("RRSIG has more labels than its owner".to_string());
+    }
+    // Serial number arithmetic, so the window survives the wrap in 2106 (RFC 4034 3.1.5)
+    if (now.wrapping_sub(*inception) as i32) < 0 {
+        return Err("RRSIG is not yet valid".to_string());
+    }
+    if (expiration.wrapping_sub(now) as i32) < 0 {
+        return Err("RRSIG has expired".to_string());
+    }
+    let data = signed_data(rrset, rrsig)?;
+    verify_signature(*algorithm, public_key, &data, signature)
+}
+
+// Succeeds if any of the signatures verifies with any of the keys
+pub fn verify_rrset(
+    rrset: &[DnsRecord],
+    rrsigs: &[DnsRecord],
+    keys: &[DnsRecord],
+    now: u32,
+) -> Result<(), String> {
+    let mut error = "No signatures for the RRset".to_string();
+    for rrsig in rrsigs {
+        for key in keys {
+            match verify_rrsig(rrset, rrsig, key, now) {
+                Ok(()) => return Ok(()),
+                Err(e) => error = e,
+            }
+        }
+    }
+    Err(error)
+}
+
+// Records grouped into RRsets, each with the signatures covering it
+pub fn rrsets(records: &[DnsRecord]) -> Vec<(Vec<DnsRecord>, Vec<DnsRecord>)> {
+    let mut sets: Vec<(Vec<DnsRecord>, Vec<DnsRecord>)> = Vec::new();
+    for record in records {
+        if matches!(record, DnsRecord::RRSIG { .. } | DnsRecord::OPT { .. }) {
+            continue;
+        }
+        let qtype = record.qtype();
+        match sets.iter_mut().find(|(set, _)| {
+            set[0].qtype() == qtype && set[0].domain().eq_ignore_ascii_case(record.domain())
+        }) {
+            Some((set, _)) => set.push(record.clone()),
+            None => {
+                let (_, rrsigs) = rrset_of(records, record.domain(), qtype);
+                sets.push((vec![record.clone()], rrsigs));
+            }
+        }
+    }
+    sets
+}
+
+pub fn rrset_of(
+    records: &[DnsRecord],
+    name: &str,
+    qtype: QueryType,
+) -> (Vec<DnsRecord>, Vec<DnsRecord>) {
+    let owned = |r: &&DnsRecord| r.domain().eq_ignore_ascii_case(name);
+    let rrset = records
+        .iter()
+        .filter(owned)
+        .filter(|r| r.qtype() == qtype)
+        .cloned()
+        .collect();
+    let rrsigs = records
+        .iter()
+        .filter(owned)
+        .filter(|r| matches!(r, DnsRecord::RRSIG { type_covered, .. } if *type_covered == qtype))
+        .cloned()
+        .collect();
+    (rrset, rrsigs)
+}
+
+fn verify_signature(
+    algorithm: u8,
+    public_key: &[u8],
+    data: &[u8],
+    sig: &[u8],
+) -> Result<(), String> {
+    let result = match algorithm {
+        8 => {
+            let (e, n) = rsa_components(public_key)?;
+            signature::RsaPublicKeyComponents { n, e }.verify(
+                &signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+                data,
+                sig,
+            )
+        }
+        13 | 14 => {
+            // DNSKEY holds the bare point, ring wants it uncompressed-tagged
+            let mut key = vec![4];
+            key.extend(public_key);
+            let params = if algorithm == 13 {
+                &signature::ECDSA_P256_SHA256_FIXED
+            } else {
+                &signature::ECDSA_P384_SHA384_FIXED
+            };
+            signature::UnparsedPublicKey::new(params, key).verify(data, sig)
+        }
+        15 => signature::UnparsedPublicKey::new(&signature::ED25519, public_key).verify(data, sig),
+        _ => return Err(format!("Unsupported DNSSEC algorithm {}", algorithm)),
+    };
+    result.map_err(|_| "Signature verification failed".to_string())
+}
+
+// RFC 3110: exponent length, exponent, modulus
+fn rsa_components(public_key: &[u8]) -> Result<(&[u8], &[u8]), String> {
+    let (len, start) = match public_key.first() {
+        Some(0) if public_key.len() > 3 => (
+            u16::from_be_bytes([public_key[1], public_key[2]]) as usize,
+            3,
+        ),
+        Some(len) => (*len as usize, 1),
+        None => return Err("Empty RSA key".to_string()),
+    };
+    if public_key.len() <= start + len {
+        return Err("Truncated RSA key".to_string());
+    }
+    Ok((&public_key[start..start + len], &public_key[start + len..]))
+}
+
+pub fn nsec3_hash(name: &str, salt: &[u8], iterations: u16) -> Result<Vec<u8>, String> {
+    let mut data = canonical_name(name)?;
+    data.extend(salt);
+    let mut hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, &data);
+    for _ in 0..iterations {
+        let mut data = hash.as_ref().to_vec();
+        data.extend(salt);
+        hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, &data);
+    }
+    Ok(hash.as_ref().to_vec())
+}
+
+const BASE32HEX: &[u8] = b"0123456789abcdefghijklmnopqrstuv";
+
+// Unpadded, as used for NSEC3 owner names (RFC 5155 3.3)
+pub fn base32hex_encode(data: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in data.chunks(5) {
+        let mut bytes = [0u8; 5];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        let bits = bytes.iter().fold(0u64, |acc, b| acc << 8 | *b as u64);
+        let chars = (chunk.len() * 8).div_ceil(5);
+        for i in 0..chars {
+            out.push(BASE32HEX[((bits >> (35 - i * 5)) & 0x1F) as usize] as char);
+        }
+    }
+    out
+}
+
+pub fn base32hex_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut bits: u64 = 0;
+    let mut count = 0;
+    for c in text.to_lowercase().bytes() {
+        let value = BASE32HEX.iter().position(|b| *b == c)?;
+        bits = bits << 5 | value as u64;
+        count += 5;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Some(out)
+}
+
+// Proves a negative answer for qname/qtype with the NSEC or NSEC3 records given
+pub fn prove_denial(
+    qname: &str,
+    qtype: QueryType,
+    records: &[DnsRecord],
+) -> Result<Denial, String> {
+    if records.iter().any(|r| matches!(r, DnsRecord::NSEC3 { .. })) {
+        return prove_nsec3_denial(qname, qtype, records);
+    }
+    let nsecs: Vec<(&str, &str, &Vec<QueryType>)> = records
+        .iter()
+        .filter_map(|r| match r {
+            DnsRecord::NSEC {
+                domain,
+                next_domain,
+                types,
+                ..
+            } => Some((domain.as_str(), next_domain.as_str(), types)),
+            _ => None,
+        })
+        .collect();
+    if nsecs.is_empty() {
+        return Err("No NSEC or NSEC3 records to prove the denial".to_string());
+    }
+    let matching = |name: &str| {
+        nsecs
+            .iter()
+            .find(|(owner, _, _)| owner.eq_ignore_ascii_case(name))
+    };
+    let covering = |name: &str| {
+        nsecs
+            .iter()
+            .find(|(owner, next, _)| nsec_covers(owner, next, name))
+    };
+
+    if let Some((_, _, types)) = matching(qname) {
+        return no_data(types, qtype);
+    }
+    let Some((owner, next, _)) = covering(qname) else {
+        return Err(format!("No NSEC record covers {}", qname));
+    };
+    // The wildcard that could have matched lives at the closest encloser
+    let encloser = [common_ancestor(qname, owner), common_ancestor(qname, next)]
+        .into_iter()
+        .max_by_key(|name| label_count(name))
+        .unwrap_or_default();
+    let wildcard = child_name("*", &encloser);
+    if let Some((_, _, types)) = matching(&wildcard) {
+        return no_data(types, qtype);
+    }
+    if covering(&wildcard).is_none() {
+        return Err(format!("No NSEC record covers {}", wildcard));
+    }
+    Ok(Denial::NoName)
+}
+
+// Proves that qname has no closer match than the wildcard a signature was made for
+pub fn prove_wildcard(qname: &str, labels: u8, records: &[DnsRecord]) -> Result<(), String> {
+    if records.iter().any(|r| matches!(r, DnsRecord::NSEC3 { .. })) {
+        let next_closer = ancestor(qname, labels + 1);
+        return match nsec3_find(&next_closer, records, true)? {
+            Some(_) => Ok(()),
+            None => Err(format!("No NSEC3 record covers {}", next_closer)),
+        };
+    }
+    let covered = records.iter().any(|r| match r {
+        DnsRecord::NSEC {
+            domain,
+            next_domain,
+            ..
+        } => nsec_covers(domain, next_domain, qname),
+        _ => false,
+    });
+    if covered {
+        Ok(())
+    } else {
+        Err(format!("No NSEC record covers {}", qname))
+    }
+}
+
+fn prove_nsec3_denial(
+    qname: &str,
+    qtype: QueryType,
+    records: &[DnsRecord],
+) -> Result<Denial, String> {
+    // Validators may give up on expensive hashes (RFC 9276 3.2)
+    let too_costly = records.iter().any(|r| match r {
+        DnsRecord::NSEC3 {
+            hash_algorithm,
+            iterations,
+            ..
+        } => *hash_algorithm != 1 || *iterations > 150,
+        _ => false,
+    });
+    if too_costly {
+        return Ok(Denial::Insecure);
+    }
+    if let Some(DnsRecord::NSEC3 { types, .. }) = nsec3_find(qname, records, false)? {
+        return no_data(types, qtype);
+    }
+
+    // Closest encloser proof (RFC 5155 8.3)
+    let total = label_count(qname);
+    let mut proof = None;
+    for labels in (0..total).rev() {
+        let encloser = ancestor(qname, labels);
+        if nsec3_find(&encloser, records, false)?.is_some() {
+            proof = Some((encloser, ancestor(qname, labels + 1)));
+            break;
+        }
+    }
+    let Some((encloser, next_closer)) = proof else {
+        return Err(format!("No closest encloser proof for {}", qname));
+    };
+    let Some(DnsRecord::NSEC3 { flags, .. }) = nsec3_find(&next_closer, records, true)? else {
+        return Err(format!("No NSEC3 record covers {}", next_closer));
+    };
+    // An opt-out span may hide unsigned delegations
+    if flags & 1 == 1 {
+        return Ok(Denial::Insecure);
+    }
+    let wildcard = child_name("*", &encloser);
+    if let Some(DnsRecord::NSEC3 { types, .. }) = nsec3_find(&wildcard, records, false)? {
+        return no_data(types, qtype);
+    }
+    if nsec3_find(&wildcard, records, true)?.is_none() {
+        return Err(format!("No NSEC3 record covers {}", wildcard));
+    }
+    Ok(Denial::NoName)
+}
+
+// The NSEC3 record whose hash matches name, or with cover set the one whose span covers it
+fn nsec3_find<'a>(
+    name: &str,
+    records: &'a [DnsRecord],
+    cover: bool,
+) -> Result<Option<&'a DnsRecord>, String> {
+    for record in records {
+        let DnsRecord::NSEC3 {
+            domain,
+            iterations,
+            salt,
+            next_hashed_owner,
+            ..
+        } = record
+        else {
+            continue;
+        };
+        let (label, zone) = domain.split_once('.').unwrap_or((domain, ""));
+        if !is_subdomain(name, zone) {
+            continue;
+        }
+        let Some(owner_hash) = base32hex_decode(label) else {
+            continue;
+        };
+        let hash = nsec3_hash(name, salt, *iterations)?;
+        let found = if cover {
+            if owner_hash < *next_hashed_owner {
+                owner_hash < hash && hash < *next_hashed_owner
+            } else {
+                owner_hash < hash || hash < *next_hashed_owner
+            }
+        } else {
+            owner_hash == hash
+        };
+        if found {
+            return Ok(Some(record));
+        }
+    }
+    Ok(None)
+}
+
+fn no_data(types: &[QueryType], qtype: QueryType) -> Result<Denial, String> {
+    if types.contains(&qtype) || types.contains(&QueryType::CNAME) {
+        return Err(format!("Denial proof shows {:?} exists", qtype));
+    }
+    // The parent side of a zone cut has NS but no SOA
+    let delegation = types.contains(&QueryType::NS) && !types.contains(&QueryType::SOA);
+    Ok(Denial::NoData { delegation })
+}
+
+// Whether name falls strictly between owner and next, the last NSEC wraps to the apex
+fn nsec_covers(owner: &str, next: &str, name: &str) -> bool {
+    let after_owner = canonical_cmp(owner, name) == Ordering::Less;
+    let before_next = canonical_cmp(name, next) == Ordering::Less;
+    if canonical_cmp(owner, next) == Ordering::Less {
+        after_owner && before_next
+    } else {
+        after_owner || before_next
+    }
+}
+
+fn common_ancestor(a: &str, b: &str) -> String {
+    let a = a.to_lowercase();
+    let b = b.to_lowercase();
+    let mut common: Vec<&str> = a
+        .split('.')
+        .filter(|l| !l.is_empty())
+        .rev()
+        .zip(b.split('.').filter(|l| !l.is_empty()).rev())
+        .take_while(|(x, y)| x == y)
+        .map(|(x, _)| x)
+        .collect();
+    common.reverse();
+    common.join(".")
+}
+
+// The rightmost labels of name
+fn ancestor(name: &str, labels: u8) -> String {
+    let parts: Vec<&str> = name.split('.').filter(|l| !l.is_empty()).collect();
+    let start = parts.len().saturating_sub(labels as usize);
+    parts[start..].join(".").to_lowercase()
+}
+
+fn child_name(label: &str, parent: &str) -> String {
+    if parent.is_empty() {
+        label.to_string()
+    } else {
+        format!("{}.{}", label, parent)
+    }
+}
@@ -0,0 +1,11 @@
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+impl EdnsOption {
+    pub fn new(code: u16, data: Vec<u8>) -> EdnsOption {
+        EdnsOption { code, data }
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::dns_packet::DnsPacket;
+use crate::dns_question::DnsQuestion;
+use crate::lru_cache::{cache_key, CacheKey};
+
+// Lets concurrent identical questions wait on a single upstream resolution
+#[derive(Debug, Default)]
+pub struct InFlight {
+    queries: Mutex<HashMap<CacheKey, Arc<Pending>>>,
+    resolutions: AtomicU64,
+    coalesced: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InFlightStats {
+    // Questions that went upstream
+    pub resolutions: u64,
+    // Questions that were answered by another thread's resolution
+    pub coalesced: u64,
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    result: Mutex<Option<Result<DnsPacket, String>>>,
+    done: Condvar,
+}
+
+// Publishes the outcome when the resolving thread is done with it, even if it panicked
+struct Leader<'a> {
+    in_flight: &'a InFlight,
+    key: CacheKey,
+    pending: Arc<Pending>,
+}
+
+impl InFlight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Runs resolve unless the same question is already being resolved, in which case
+    // this waits for that resolution and returns a copy of its result
+    pub fn resolve<F>(&self, question: &DnsQuestion, resolve: F) -> Result<DnsPacket, String>
+    where
+        F: FnOnce() -> Result<DnsPacket, String>,
+    {
+        let key = cache_key(question);
+        let mut queries = self.queries.lock().unwrap();
+        if let Some(pending) = queries.get(&key).cloned() {
+            drop(queries);
+            self.coalesced.fetch_add(1, Ordering::Relaxed);
+            let result = pending.result.lock().unwrap();
+            let result = pending.done.wait_while(result, |r| r.is_none()).unwrap();
+            return result.clone().unwrap();
+        }
+        let pending = Arc::new(Pending::default());
+        queries.insert(key.clone(), pending.clone());
+        drop(queries);
+        self.resolutions.fetch_add(1, Ordering::Relaxed);
+
+        let leader = Leader {
+            in_flight: self,
+            key,
+            pending,
+        };
+        let result = resolve();
+        *leader.pending.result.lock().unwrap() = Some(result.clone());
+        result
+    }
+
+    pub fn stats(&self) -> InFlightStats {
+        InFlightStats {
+            resolutions: self.resolutions.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        self.in_flight.queries.lock().unwrap().remove(&self.key);
+        let mut result = self.pending.result.lock().unwrap();
+        if result.is_none() {
+            *result = Some(Err("Resolution was abandoned".to_string()));
+        }
+        self.pending.done.notify_all();
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rand::{seq::SliceRandom, Rng};
+
+// Servers whose SRTT is this close to the best one's share the load between them
+const RTT_BAND: Duration = Duration::from_millis(25);
+
+// Weight of the previous SRTT when a new round trip time is measured
+const SRTT_WEIGHT: f64 = 0.7;
+
+// Servers that were passed over look a little faster every time, so they get retried
+const SRTT_DECAY: f64 = 0.98;
+
+// A timeout at least pushes a server this far back, and never further than MAX_SRTT
+const TIMEOUT_SRTT: Duration = Duration::from_millis(400);
+const MAX_SRTT: Duration = Duration::from_secs(10);
+
+// How long a server that gave a lame answer is only asked as a last resort, or one that
+// changed the case of a name is asked without 0x20
+const INFRA_TTL: Duration = Duration::from_secs(600);
+
+// What we know about the upstream servers we have talked to
+#[derive(Debug)]
+pub struct InfraCache {
+    servers: Mutex<HashMap<IpAddr, ServerStats>>,
+    ttl: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ServerStats {
+    // Smoothed round trip time
+    pub srtt: Duration,
+    pub queries: u64,
+    pub timeouts: u64,
+    // Answers that were SERVFAIL, REFUSED or otherwise useless
+    pub lame_answers: u64,
+    lame_until: Option<Instant>,
+    // Set once the server changed the case of a 0x20 randomized name
+    case_mismatch_until: Option<Instant>,
+}
+
+impl ServerStats {
+    fn new() -> Self {
+        // Unknown servers start out fast enough to be tried soon, in random order
+        ServerStats {
+            srtt: Duration::from_millis(rand::thread_rng().gen_range(1..=32)),
+            queries: 0,
+            timeouts: 0,
+            lame_answers: 0,
+            lame_until: None,
+            case_mismatch_until: None,
+        }
+    }
+
+    pub fn is_lame(&self) -> bool {
+        self.lame_until.is_some_and(|until| until > Instant::now())
+    }
+
+    // A mismatch may have been a one-off, or a spoofed reply, so it is given another try
+    // once it expires
+    pub fn preserves_case(&self) -> bool {
+        self.case_mismatch_until
+            .is_none_or(|until| until <= Instant::now())
+    }
+}
+
+impl Default for InfraCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InfraCache {
+    pub fn new() -> Self {
+        Self::with_ttl(INFRA_TTL)
+    }
+
+    pub fn with_ttl(ttl: Duration) -> Self {
+        InfraCache {
+            servers: Mutex::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    // The servers in the order they should be asked, best first
+    pub fn order(&self, servers: &[IpAddr]) -> Vec<IpAddr> {
+        let mut table = self.servers.lock().unwrap();
+        let mut ranked: Vec<(bool, Duration, IpAddr)> = servers
+            .iter()
+            .map(|&server| {
+                let stats = table.entry(server).or_insert_with(ServerStats::new);
+                (stats.is_lame(), stats.srtt, server)
+            })
+            .collect();
+        ranked.sort();
+        ranked.dedup_by_key(|(_, _, server)| *server);
+
+        let mut rng = rand::thread_rng();
+        if let Some(&(lame, best, _)) = ranked.first() {
+            let band = ranked
+                .iter()
+                .take_while(|(l, srtt, _)| *l == lame && *srtt <= best + RTT_BAND)
+                .count();
+            ranked[..band].shuffle(&mut rng);
+        }
+        for (_, _, server) in ranked.iter().skip(1) {
+            if let Some(stats) = table.get_mut(server) {
+                stats.srtt = stats.srtt.mul_f64(SRTT_DECAY);
+            }
+        }
+        ranked.into_iter().map(|(_, _, server)| server).collect()
+    }
+
+    pub fn record_rtt(&self, server: IpAddr, rtt: Duration) {
+        let mut table = self.servers.lock().unwrap();
+        let stats = table.entry(server).or_insert_with(ServerStats::new);
+        stats.srtt = match stats.queries - stats.timeouts - stats.lame_answers {
+            // The first real measurement replaces the random starting point
+            0 => rtt,
+            _ => stats.srtt.mul_f64(SRTT_WEIGHT) + rtt.mul_f64(1.0 - SRTT_WEIGHT),
+        }
+        .min(MAX_SRTT);
+        stats.queries += 1;
+        stats.lame_until = None;
+    }
+
+    pub fn record_timeout(&self, server: IpAddr) {
+        let mut table = self.servers.lock().unwrap();
+        let stats = table.entry(server).or_insert_with(ServerStats::new);
+        stats.srtt = (stats.srtt * 2).clamp(TIMEOUT_SRTT, MAX_SRTT);
+        stats.queries += 1;
+        stats.timeouts += 1;
+    }
+
+    pub fn record_lame(&self, server: IpAddr) {
+        let mut table = self.servers.lock().unwrap();
+        let stats = table.entry(server).or_insert_with(ServerStats::new);
+        stats.queries += 1;
+        stats.lame_answers += 1;
+        stats.lame_until = Some(Instant::now() + self.ttl);
+    }
+
+    pub fn record_case_mismatch(&self, server: IpAddr) {
+        let mut table = self.servers.lock().unwrap();
+        let stats = table.entry(server).or_insert_with(ServerStats::new);
+        stats.case_mismatch_until = Some(Instant::now() + self.ttl);
+    }
+
+    pub fn preserves_case(&self, server: IpAddr) -> bool {
+        self.server_stats(server)
+            .is_none_or(|stats| stats.preserves_case())
+    }
+
+    pub fn server_stats(&self, server: IpAddr) -> Option<ServerStats> {
+        self.servers.lock().unwrap().get(&server).copied()
+    }
+
+    // Every server seen so far, fastest first
+    pub fn stats(&self) -> Vec<(IpAddr, ServerStats)> {
+        let mut stats: Vec<(IpAddr, ServerStats)> = self
+            .servers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(server, stats)| (*server, *stats))
+            .collect();
+        stats.sort_by_key(|(server, stats)| (stats.srtt, *server));
+        stats
+    }
+}
@@ -3,8 +3,16 @@ pub mod dns_header;
 pub mod dns_packet;
 pub mod dns_question;
 pub mod dns_record;
+pub mod dnssec;
+pub mod edns_option;
+pub mod in_flight;
+pub mod infra_cache;
 pub mod lookup;
 pub mod lru_cache;
+pub mod query_class;
 pub mod query_type;
 pub mod res_code;
+pub mod resolver_config;
+pub mod svc_param;
 pub mod utils;
+pub mod validator;
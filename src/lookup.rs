@@ -1,37 +1,68 @@
 use rand::Rng;
 use std::{
-    net::{Ipv4Addr, UdpSocket},
-    sync::{Arc, Mutex},
+    io::{ErrorKind, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     thread,
+    time::{Duration, Instant},
 };
 
 use crate::{
-    byte_packet_buffer::BytePacketBuffer, dns_packet::DnsPacket, dns_question::DnsQuestion,
-    lru_cache::LRUCache, query_type::QueryType, res_code::ResultCode,
-    utils::ROOT_NAME_SERVERS,
+    byte_packet_buffer::BytePacketBuffer,
+    dns_header::DnsHeader,
+    dns_packet::DnsPacket,
+    dns_question::DnsQuestion,
+    dns_record::DnsRecord,
+    dnssec::{canonical_name, is_subdomain, Security},
+    in_flight::InFlight,
+    infra_cache::InfraCache,
+    lru_cache::LRUCache,
+    query_class::QueryClass,
+    query_type::QueryType,
+    res_code::ResultCode,
+    resolver_config::ResolverConfig,
+    utils::{
+        EDNS_PAYLOAD_SIZE, MAX_GLUELESS_DEPTH, MAX_MINIMISE_COUNT, MAX_REFERRALS,
+        QUERY_PORT_ATTEMPTS, SERVER_ID, TCP_IDLE_TIMEOUT,
+    },
+    validator::Validator,
 };
 
+// Upstream responses that didn't match the query they arrived for
+static DISCARDED_RESPONSES: AtomicU64 = AtomicU64::new(0);
+
+const CASE_MISMATCH: &str = "Response didn't preserve the case of the name";
+
 pub fn handle_queries(
     req_socket: &UdpSocket,
-    query_socket: &UdpSocket,
-    cache: Arc<Mutex<LRUCache>>,
+    cache: Arc<LRUCache>,
+    in_flight: Arc<InFlight>,
+    infra: Arc<InfraCache>,
+    config: Arc<ResolverConfig>,
 ) -> Result<(), String> {
-    let mut req_buffer = BytePacketBuffer::default();
+    let mut req_buffer = BytePacketBuffer::with_size(EDNS_PAYLOAD_SIZE as usize);
     loop {
         if let Ok((_, src)) = req_socket.recv_from(&mut req_buffer.buf) {
             // Spawn a new thread to handle the query
             let req_socket = req_socket.try_clone().unwrap();
-            let query_socket = query_socket.try_clone().unwrap();
             let cache = Arc::clone(&cache);
+            let in_flight = Arc::clone(&in_flight);
+            let infra = Arc::clone(&infra);
+            let config = Arc::clone(&config);
             let mut req_buffer = req_buffer.clone();
             thread::spawn(move || {
-                let mut res_buffer = BytePacketBuffer::default();
-                let packet =
-                    handle_query(&query_socket, &mut req_buffer, &mut cache.lock().unwrap())
-                        .unwrap();
-                if packet.write(&mut res_buffer).is_err() {
-                    println!("Packet size overflow, truncated.");
-                }
+                let Ok(request) = DnsPacket::from_buffer(&mut req_buffer) else {
+                    return;
+                };
+                // Don't send more than the client said it can receive
+                let max_size = request.max_udp_size().min(EDNS_PAYLOAD_SIZE as usize);
+                let id = request.header.id;
+                let packet = handle_query(request, &cache, &in_flight, &infra, &config)
+                    .unwrap_or_else(|e| servfail(id, e));
+                let mut res_buffer = write_response(&packet, max_size);
                 let len = res_buffer.pos;
                 let data = res_buffer.get_range(0, len).unwrap();
                 req_socket.send_to(data, src).unwrap();
@@ -40,12 +71,73 @@ pub fn handle_queries(
     }
 }
 
+pub fn handle_tcp_queries(
+    listener: &TcpListener,
+    cache: Arc<LRUCache>,
+    in_flight: Arc<InFlight>,
+    infra: Arc<InfraCache>,
+    config: Arc<ResolverConfig>,
+) -> Result<(), String> {
+    for stream in listener.incoming() {
+        let mut stream = stream.map_err(|e| e.to_string())?;
+        // Silent or stalled clients would otherwise hold on to a thread for good, the
+        // connection is dropped once a read or write times out (RFC 7766 6.2.3)
+        if stream
+            .set_read_timeout(Some(TCP_IDLE_TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(TCP_IDLE_TIMEOUT)))
+            .is_err()
+        {
+            continue;
+        }
+        // Spawn a new thread to handle the connection
+        let cache = Arc::clone(&cache);
+        let in_flight = Arc::clone(&in_flight);
+        let infra = Arc::clone(&infra);
+        let config = Arc::clone(&config);
+        thread::spawn(move || {
+            // Clients may send several queries over the same connection
+            while let Ok(mut req_buffer) = read_tcp_message(&mut stream) {
+                let Ok(request) = DnsPacket::from_buffer(&mut req_buffer) else {
+                    break;
+                };
+                let id = request.header.id;
+                let packet = handle_query(request, &cache, &in_flight, &infra, &config)
+                    .unwrap_or_else(|e| servfail(id, e));
+                let mut res_buffer = write_response(&packet, u16::MAX as usize);
+                let len = res_buffer.pos;
+                let data = res_buffer.get_range(0, len).unwrap();
+                if write_tcp_message(&mut stream, data).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+// Queries that couldn't be handled still get an answer
+fn servfail(id: u16, error: String) -> DnsPacket {
+    println!("Query {} failed: {}", id, error);
+    let mut packet = DnsPacket::default();
+    packet.header.id = id;
+    packet.header.recursion_desired = true;
+    packet.header.recursion_available = true;
+    packet.header.response = true;
+    packet.header.rescode = ResultCode::SERVFAIL;
+    packet
+}
+
 pub fn handle_query(
-    query_socket: &UdpSocket,
-    req_buffer: &mut BytePacketBuffer,
-    cache: &mut LRUCache,
+    mut request: DnsPacket,
+    cache: &LRUCache,
+    in_flight: &InFlight,
+    infra: &InfraCache,
+    config: &ResolverConfig,
 ) -> Result<DnsPacket, String> {
-    let mut request = DnsPacket::from_buffer(req_buffer)?;
+    let edns_version = match request.get_edns() {
+        Some(DnsRecord::OPT { version, .. }) => Some(*version),
+        _ => None,
+    };
     let mut packet = DnsPacket::default();
     packet.header.id = request.header.id;
     packet.header.recursion_desired = true;
@@ -54,21 +146,81 @@ pub fn handle_query(
     packet.header.questions = 1;
     if let Some(question) = request.questions.pop() {
         println!("Received query: {:?}", question);
-        match cache.get(&question.name) {
-            Some(result) => {
-                populate_dns_packet(&mut packet, question, &result);
-            }
-            None => {
-                let root_name_server = &ROOT_NAME_SERVERS[0];
-                if let Ok(result) = recursive_lookup(
-                    query_socket,
-                    &question.name,
-                    question.qtype,
-                    root_name_server.a,
-                ) {
-                    cache.put(&question.name, &result);
-                    populate_dns_packet(&mut packet, question, &result);
-                } else {
+        if edns_version.is_some_and(|version| version > 0) {
+            packet.questions.push(question);
+            packet.header.rescode = ResultCode::BADVERS;
+        } else if question.qclass == QueryClass::CH {
+            if let Some(record) = chaos_answer(&question) {
+                packet.answers.push(record);
+                packet.header.answers = 1;
+                packet.header.authoritative_answer = true;
+            } else {
+                packet.header.rescode = ResultCode::REFUSED;
+            }
+            packet.questions.push(question);
+        } else if question.qclass != QueryClass::IN {
+            // Only the internet class can be resolved from the roots
+            packet.questions.push(question);
+            packet.header.rescode = ResultCode::REFUSED;
+        } else {
+            // Clients setting CD validate for themselves (RFC 4035 3.2.2)
+            let validate = config.dnssec_validation && !request.header.checking_disabled;
+            // How the lookup validated, if this thread was the one to make it
+            let mut validated = None;
+            let (result, cached) = match cache.get(&question) {
+                Some(result) => (Ok(result), true),
+                // The resolving thread validates and caches the answer before any waiting
+                // client is let go, so later questions find it in the cache
+                None => (
+                    in_flight.resolve(&question, || {
+                        let mut result =
+                            recursive_lookup(config, cache, infra, &question.name, question.qtype)?;
+                        if config.dnssec_validation {
+                            let security = Validator::new(config, cache, infra).validate(
+                                &question.name,
+                                question.qtype,
+                                &result,
+                            );
+                            result.header.authed_data = security == Security::Secure;
+                            validated = Some(security);
+                        }
+                        // Bogus answers mustn't be served to clients that rely on us
+                        if !matches!(validated, Some(Security::Bogus(_))) {
+                            cache.put(&question, &result);
+                        }
+                        Ok(result)
+                    }),
+                    false,
+                ),
+            };
+            let result = result.and_then(|mut result| {
+                if !validate {
+                    return Ok(result);
+                }
+                let security = match validated {
+                    Some(security) => security,
+                    // Everything served from the cache was validated before it was put
+                    None if cached || result.header.authed_data => return Ok(result),
+                    // Another client's lookup, which may have turned out bogus
+                    None => Validator::new(config, cache, infra).validate(
+                        &question.name,
+                        question.qtype,
+                        &result,
+                    ),
+                };
+                if let Security::Bogus(reason) = security {
+                    return Err(reason);
+                }
+                result.header.authed_data = security == Security::Secure;
+                Ok(result)
+            });
+            match result {
+                Ok(result) => {
+                    populate_dns_packet(&mut packet, question, &result, request.dnssec_ok());
+                }
+                Err(e) => {
+                    println!("Lookup of {} failed: {}", question.name, e);
+                    packet.questions.push(question);
                     packet.header.rescode = ResultCode::SERVFAIL;
                 }
             }
@@ -76,52 +228,412 @@ pub fn handle_query(
     } else {
         packet.header.rescode = ResultCode::FORMERR;
     }
+    // Only clients that show they understand AD get it (RFC 6840 5.7)
+    if !request.dnssec_ok() && !request.header.authed_data {
+        packet.header.authed_data = false;
+    }
+    if edns_version.is_some() {
+        packet.set_edns(EDNS_PAYLOAD_SIZE, request.dnssec_ok());
+    }
 
     Ok(packet)
 }
 
-fn recursive_lookup(
-    query_socket: &UdpSocket,
+fn chaos_answer(question: &DnsQuestion) -> Option<DnsRecord> {
+    if question.qtype != QueryType::TXT {
+        return None;
+    }
+    let text = match question.name.as_str() {
+        "version.bind" | "version.server" => {
+            format!("rdns-resolver-rs {}", env!("CARGO_PKG_VERSION"))
+        }
+        "id.server" | "hostname.bind" => SERVER_ID.to_string(),
+        _ => return None,
+    };
+    Some(DnsRecord::TXT {
+        domain: question.name.clone(),
+        data: vec![text.into_bytes()],
+        class: QueryClass::CH,
+        ttl: 0,
+    })
+}
+
+pub fn recursive_lookup(
+    config: &ResolverConfig,
+    cache: &LRUCache,
+    infra: &InfraCache,
+    qname: &str,
+    qtype: QueryType,
+) -> Result<DnsPacket, String> {
+    lookup_at_depth(config, cache, infra, qname, qtype, 0)
+}
+
+// depth counts the glueless nameserver lookups this one is nested in
+fn lookup_at_depth(
+    config: &ResolverConfig,
+    cache: &LRUCache,
+    infra: &InfraCache,
     qname: &str,
     qtype: QueryType,
-    mut ns: Ipv4Addr,
+    depth: usize,
 ) -> Result<DnsPacket, String> {
+    let mut response = iterate(config, cache, infra, qname, qtype, depth)?;
+    if qtype == QueryType::CNAME {
+        return Ok(response);
+    }
+    // Servers only follow aliases within their own zones, the rest of the chain is ours
+    let mut answers = Vec::new();
+    let mut chain = vec![qname.to_lowercase()];
     loop {
-        println!("attempting lookup of {:?} {} with ns {}", qtype, qname, ns);
-        let ns_copy = ns;
-        let server = (ns_copy, 53);
-        let response = lookup(query_socket, qname, qtype, server)?;
-        if !response.answers.is_empty() && response.header.rescode == ResultCode::NOERROR {
-            return Ok(response);
+        let asked = chain.len();
+        loop {
+            let name = chain[chain.len() - 1].clone();
+            let target = match dname_target(&response.answers, &name) {
+                Some(target) => {
+                    // The substituted name has to fit in a packet (RFC 6672 2.2)
+                    if canonical_name(&target).is_err() {
+                        response.header.rescode = ResultCode::YXDOMAIN;
+                        break;
+                    }
+                    synthesize_cname(&mut response.answers, &name, &target);
+                    target
+                }
+                None => match cname_target(&response.answers, &name) {
+                    Some(target) => target,
+                    None => break,
+                },
+            };
+            if chain.contains(&target) {
+                return Err(format!("CNAME loop at {}", target));
+            }
+            if chain.len() > config.max_cname_chain {
+                return Err(format!("CNAME chain from {} is too long", qname));
+            }
+            chain.push(target);
         }
-        if response.header.rescode == ResultCode::NXDOMAIN {
-            return Ok(response);
+        answers.append(&mut response.answers);
+        let name = &chain[chain.len() - 1];
+        let answered = answers
+            .iter()
+            .any(|r| r.qtype() == qtype && r.domain().eq_ignore_ascii_case(name));
+        if answered || chain.len() == asked || response.header.rescode != ResultCode::NOERROR {
+            break;
         }
-        if let Some(new_ns) = response.get_resolved_ns(qname) {
-            ns = new_ns;
+        response = iterate(config, cache, infra, name, qtype, depth)?;
+    }
+    response.answers = answers;
+    response.header.answers = response.answers.len() as u16;
+    Ok(response)
+}
 
-            continue;
+// Resolves qname from the closest known servers down, stopping at the first answer
+fn iterate(
+    config: &ResolverConfig,
+    cache: &LRUCache,
+    infra: &InfraCache,
+    qname: &str,
+    qtype: QueryType,
+    depth: usize,
+) -> Result<DnsPacket, String> {
+    // Start from the closest servers already known, DS records live on the parent side
+    let start = match qtype {
+        QueryType::DS => qname.split_once('.').map_or("", |(_, parent)| parent),
+        _ => qname,
+    };
+    let usable = |addr: &IpAddr| config.ip_mode.allows(addr);
+    // The zone the servers being asked are authoritative for, which bounds what they
+    // can tell us about
+    let (mut zone, mut servers) = cache.closest_nameservers(start);
+    servers.retain(usable);
+    if servers.is_empty() {
+        zone = String::new();
+        servers = config.root_servers.iter().copied().filter(usable).collect();
+    }
+    let mut glueless: Vec<String> = Vec::new();
+    let mut minimise = config.qname_minimisation;
+    let mut minimised = 0;
+    // The deepest name the current servers have been asked about
+    let mut known = zone.clone();
+    let mut referrals = 0;
+    loop {
+        // Servers above the zone of qname are only asked for the next label down, and
+        // for an A record rather than what the client wants
+        let name = match minimise && minimised < MAX_MINIMISE_COUNT {
+            true => child_name(qname, &known),
+            false => qname.to_string(),
+        };
+        let minimal = name.len() < qname.len();
+        let name_qtype = if minimal { QueryType::A } else { qtype };
+        let mut result = query_servers(config, infra, &servers, &name, name_qtype);
+        // Servers without glue are only looked up once those with addresses have failed
+        for host in &glueless {
+            if result.is_ok() {
+                break;
+            }
+            // Zones whose servers are only named inside each other would never bottom out
+            if depth >= MAX_GLUELESS_DEPTH {
+                return Err(format!("Too many nested nameserver lookups for {}", host));
+            }
+            result = nameserver_addresses(config, cache, infra, host, depth + 1)
+                .and_then(|servers| query_servers(config, infra, &servers, &name, name_qtype));
         }
-        let new_ns_name = match response.get_unresolved_ns(qname) {
-            Some(x) => x,
-            None => return Ok(response),
+        // Some servers deny that empty non-terminals exist or fail on them, they get
+        // the full name instead of being believed
+        let mut response = match result {
+            Ok(response) if minimal && response.header.rescode != ResultCode::NOERROR => {
+                minimise = false;
+                continue;
+            }
+            Err(_) if minimal => {
+                minimise = false;
+                continue;
+            }
+            result => result?,
         };
-        let recursive_response = recursive_lookup(query_socket, new_ns_name, QueryType::A, ns)?;
-        if let Some(new_ns) = recursive_response.get_random_a() {
-            ns = new_ns;
+        cache.put_delegation(&response, &name, &zone);
+        // Records outside the zone are for other servers to vouch for
+        response
+            .answers
+            .retain(|record| is_subdomain(record.domain(), &zone));
+        response.header.answers = response.answers.len() as u16;
+        if minimal {
+            minimised += 1;
         } else {
+            if !response.answers.is_empty() && response.header.rescode == ResultCode::NOERROR {
+                return Ok(response);
+            }
+            if response.header.rescode == ResultCode::NXDOMAIN {
+                return Ok(response);
+            }
+        }
+        let mut next: Vec<IpAddr> = response.get_all_resolved_ns(&name, &zone);
+        next.retain(usable);
+        // Servers inside the zone they serve can't be found without glue
+        let next_glueless: Vec<String> = response
+            .get_all_unresolved_ns(&name, &zone)
+            .into_iter()
+            .filter(|(zone, host)| !is_subdomain(host, zone))
+            .map(|(_, host)| host.to_string())
+            .collect();
+        if next.is_empty() && next_glueless.is_empty() {
+            // A referral to servers we can't reach is a failure
+            if response.get_unresolved_ns(&name, &zone).is_some() {
+                return Err(format!("No usable nameservers for {}", qname));
+            }
+            // No zone cut at a minimised name, the same servers get the next label
+            if minimal {
+                known = name;
+                continue;
+            }
             return Ok(response);
         }
+        referrals += 1;
+        if referrals >= MAX_REFERRALS {
+            return Err(format!("Too many referrals for {}", qname));
+        }
+        servers = next;
+        glueless = next_glueless;
+        let cut = response
+            .get_delegation(&name, &zone)
+            .map(|record| record.domain().to_lowercase())
+            .next();
+        if let Some(cut) = cut {
+            zone = cut;
+            known = zone.clone();
+        }
     }
 }
 
-fn lookup(
-    query_socket: &UdpSocket,
+// The name one label below ancestor on the way down to qname
+fn child_name(qname: &str, ancestor: &str) -> String {
+    let labels: Vec<&str> = qname.split('.').filter(|l| !l.is_empty()).collect();
+    let depth = ancestor.split('.').filter(|l| !l.is_empty()).count() + 1;
+    labels[labels.len().saturating_sub(depth)..].join(".")
+}
+
+// Where a CNAME owned by name in answers points
+fn cname_target(answers: &[DnsRecord], name: &str) -> Option<String> {
+    answers.iter().find_map(|record| match record {
+        DnsRecord::CNAME { domain, host, .. } if domain.eq_ignore_ascii_case(name) => {
+            Some(host.to_lowercase())
+        }
+        _ => None,
+    })
+}
+
+// The DNAME in answers that redirects name, with the name it is redirected to
+fn dname_target(answers: &[DnsRecord], name: &str) -> Option<String> {
+    answers
+        .iter()
+        .find_map(|record| substitute_dname(record, name))
+}
+
+// Replaces the owner of a DNAME at the end of name with its target (RFC 6672 2.2),
+// names that are the owner itself or outside its subtree aren't redirected
+pub fn substitute_dname(record: &DnsRecord, name: &str) -> Option<String> {
+    let DnsRecord::DNAME { domain, target, .. } = record else {
+        return None;
+    };
+    if domain.eq_ignore_ascii_case(name) || !is_subdomain(name, domain) {
+        return None;
+    }
+    let prefix = match domain.len() {
+        0 => name,
+        len => &name[..name.len() - len - 1],
+    };
+    Some(match target.as_str() {
+        "" => prefix.to_lowercase(),
+        target => format!("{}.{}", prefix, target).to_lowercase(),
+    })
+}
+
+// Clients that don't know DNAME follow the CNAME it stands for (RFC 6672 3.1), it
+// replaces any the server sent so a forged one can't point somewhere else
+fn synthesize_cname(answers: &mut Vec<DnsRecord>, name: &str, target: &str) {
+    answers.retain(|r| {
+        !(r.domain().eq_ignore_ascii_case(name)
+            && matches!(
+                r,
+                DnsRecord::CNAME { .. }
+                    | DnsRecord::RRSIG {
+                        type_covered: QueryType::CNAME,
+                        ..
+                    }
+            ))
+    });
+    let Some(dname) = answers
+        .iter()
+        .position(|r| substitute_dname(r, name).is_some())
+    else {
+        return;
+    };
+    let cname = DnsRecord::CNAME {
+        domain: name.to_string(),
+        host: target.to_string(),
+        class: answers[dname].class(),
+        ttl: answers[dname].ttl(),
+    };
+    answers.insert(dname + 1, cname);
+}
+
+// The addresses of a nameserver that came without glue, in the families we can use
+fn nameserver_addresses(
+    config: &ResolverConfig,
+    cache: &LRUCache,
+    infra: &InfraCache,
+    host: &str,
+    depth: usize,
+) -> Result<Vec<IpAddr>, String> {
+    let mut addrs = Vec::new();
+    let mut error = None;
+    for &qtype in config.ip_mode.address_types() {
+        match lookup_at_depth(config, cache, infra, host, qtype, depth) {
+            Ok(response) => {
+                cache.put_nameserver(host, &response);
+                addrs.extend(
+                    response
+                        .get_all_ip()
+                        .into_iter()
+                        .filter(|addr| config.ip_mode.allows(addr)),
+                )
+            }
+            Err(e) => error = Some(e),
+        }
+    }
+    match error {
+        Some(e) if addrs.is_empty() => Err(e),
+        _ => Ok(addrs),
+    }
+}
+
+// Gives every server a chance to answer before any of them is retried
+fn query_servers(
+    config: &ResolverConfig,
+    infra: &InfraCache,
+    servers: &[IpAddr],
+    qname: &str,
+    qtype: QueryType,
+) -> Result<DnsPacket, String> {
+    let mut error = format!("No nameservers to ask for {}", qname);
+    for _ in 0..=config.retries {
+        for ns in infra.order(servers) {
+            println!("attempting lookup of {:?} {} with ns {}", qtype, qname, ns);
+            let start = Instant::now();
+            let randomize = config.use_0x20
+                && !config.no_0x20_servers.contains(&ns)
+                && infra.preserves_case(ns);
+            let name = if randomize {
+                randomize_case(qname)
+            } else {
+                qname.to_string()
+            };
+            let mut result = lookup(config, &name, qtype, (ns, config.port), randomize);
+            // Servers that don't preserve case are asked again, and from now on, without it
+            if randomize && result.as_ref().is_err_and(|e| e == CASE_MISMATCH) {
+                infra.record_case_mismatch(ns);
+                result = lookup(config, qname, qtype, (ns, config.port), false);
+            }
+            match result {
+                // Lame or broken servers are skipped like unreachable ones
+                Ok(response)
+                    if matches!(
+                        response.header.rescode,
+                        ResultCode::SERVFAIL
+                            | ResultCode::REFUSED
+                            | ResultCode::NOTIMP
+                            | ResultCode::FORMERR
+                            | ResultCode::UNKNOWN(_)
+                    ) =>
+                {
+                    infra.record_lame(ns);
+                    error = format!("{} answered {:?}", ns, response.header.rescode);
+                }
+                Ok(response) => {
+                    infra.record_rtt(ns, start.elapsed());
+                    return Ok(response);
+                }
+                Err(e) => {
+                    infra.record_timeout(ns);
+                    error = format!("{}: {}", ns, e);
+                }
+            }
+        }
+    }
+    Err(error)
+}
+
+pub fn lookup(
+    config: &ResolverConfig,
+    qname: &str,
+    qtype: QueryType,
+    server: (IpAddr, u16),
+    randomized: bool,
+) -> Result<DnsPacket, String> {
+    let mut response = send_query(config, qname, qtype, server, true, randomized)?;
+    // Servers that don't speak EDNS(0) reject the OPT record, so ask again without it
+    if response.get_edns().is_none()
+        && matches!(
+            response.header.rescode,
+            ResultCode::FORMERR | ResultCode::NOTIMP
+        )
+    {
+        response = send_query(config, qname, qtype, server, false, randomized)?;
+    }
+    // Only our own validation decides what is authenticated
+    response.header.authed_data = false;
+    Ok(response)
+}
+
+fn send_query(
+    config: &ResolverConfig,
     qname: &str,
     qtype: QueryType,
-    server: (Ipv4Addr, u16),
+    server: (IpAddr, u16),
+    edns: bool,
+    randomized: bool,
 ) -> Result<DnsPacket, String> {
     let mut packet = DnsPacket::default();
+    let mut res_buffer = BytePacketBuffer::with_size(EDNS_PAYLOAD_SIZE as usize);
 
     let mut rng = rand::thread_rng();
     packet.header.id = rng.gen::<u16>();
@@ -130,35 +642,248 @@ fn lookup(
     packet
         .questions
         .push(DnsQuestion::new(qname.to_string(), qtype));
+    if edns {
+        // Always ask for DNSSEC records so they can be handed to clients that want them
+        packet.set_edns(res_buffer.buf.len() as u16, true);
+    }
 
     let mut req_buffer = BytePacketBuffer::default();
     packet.write(&mut req_buffer)?;
+
+    let query_socket = bind_query_socket(config, server.0)?;
     query_socket
         .send_to(&req_buffer.buf[0..req_buffer.pos], server)
-        .unwrap();
+        .map_err(|e| e.to_string())?;
 
-    let mut res_buffer = BytePacketBuffer::default();
-    query_socket.recv_from(&mut res_buffer.buf).unwrap();
+    // Anything that isn't the answer to this query is dropped and we keep waiting, so
+    // a forged or stray datagram can't take its place (RFC 5452 3)
+    let deadline = Instant::now() + config.timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err("timed out".to_string());
+        }
+        let (_, src) = query_socket
+            .set_read_timeout(Some(remaining))
+            .and_then(|_| query_socket.recv_from(&mut res_buffer.buf))
+            .map_err(|e| match e.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => "timed out".to_string(),
+                _ => e.to_string(),
+            })?;
+        res_buffer.seek(0)?;
+        if src == SocketAddr::from(server) && answers_query(&mut res_buffer, &packet) {
+            break;
+        }
+        DISCARDED_RESPONSES.fetch_add(1, Ordering::Relaxed);
+        println!("Discarded a mismatched response from {}", src);
+    }
+
+    res_buffer.seek(0)?;
+    let mut header = DnsHeader::default();
+    header.read(&mut res_buffer)?;
+    // A randomized name has to come back exactly as it was sent for 0x20 to add anything
+    if randomized && header.questions > 0 {
+        let mut name = String::new();
+        res_buffer.read_qname_preserving_case(&mut name)?;
+        if name != qname {
+            return Err(CASE_MISMATCH.to_string());
+        }
+    }
+
+    // The answer didn't fit in a datagram, ask again over TCP
+    if header.truncated_message {
+        println!("truncated response from {:?}, retrying over tcp", server);
+        return lookup_tcp(
+            &req_buffer.buf[0..req_buffer.pos],
+            &packet,
+            server,
+            config.timeout,
+        );
+    }
+    res_buffer.seek(0)?;
 
     DnsPacket::from_buffer(&mut res_buffer)
 }
 
-fn populate_dns_packet(packet: &mut DnsPacket, question: DnsQuestion, result: &DnsPacket) {
+// Flips the case of each letter at random (draft-vixie-dnsext-dns0x20)
+fn randomize_case(name: &str) -> String {
+    let mut rng = rand::thread_rng();
+    name.chars()
+        .map(|c| {
+            if rng.gen::<bool>() {
+                c.to_ascii_uppercase()
+            } else {
+                c.to_ascii_lowercase()
+            }
+        })
+        .collect()
+}
+
+// Every query gets a socket on a random port of its own, so a forged reply has to
+// guess the port as well as the ID (RFC 5452 9.2)
+fn bind_query_socket(config: &ResolverConfig, server: IpAddr) -> Result<UdpSocket, String> {
+    let unspecified: IpAddr = match server {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let mut error = "No query ports to choose from".to_string();
+    for _ in 0..QUERY_PORT_ATTEMPTS {
+        let Some(port) = random_query_port(config) else {
+            continue;
+        };
+        match UdpSocket::bind((unspecified, port)) {
+            Ok(socket) => return Ok(socket),
+            Err(e) => error = format!("Query port {}: {}", port, e),
+        }
+    }
+    Err(error)
+}
+
+// A port picked evenly from the configured ranges, unless it is excluded
+fn random_query_port(config: &ResolverConfig) -> Option<u16> {
+    let total: usize = config.query_ports.iter().map(|ports| ports.len()).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut index = rand::thread_rng().gen_range(0..total);
+    for ports in &config.query_ports {
+        if index < ports.len() {
+            let port = *ports.start() + index as u16;
+            return Some(port).filter(|port| !config.excluded_query_ports.contains(port));
+        }
+        index -= ports.len();
+    }
+    None
+}
+
+// Whether the response in buffer is for query: a reply with its ID and question
+fn answers_query(buffer: &mut BytePacketBuffer, query: &DnsPacket) -> bool {
+    let mut header = DnsHeader::default();
+    if header.read(buffer).is_err() || !header.response || header.id != query.header.id {
+        return false;
+    }
+    // Servers that can't parse a query may not echo its question
+    if header.questions == 0 {
+        return header.rescode == ResultCode::FORMERR;
+    }
+    let mut question = DnsQuestion::new(String::new(), QueryType::UNKNOWN(0));
+    if header.questions != 1 || question.read(buffer).is_err() {
+        return false;
+    }
+    let asked = &query.questions[0];
+    question.name.eq_ignore_ascii_case(&asked.name)
+        && question.qtype == asked.qtype
+        && question.qclass == asked.qclass
+}
+
+// How many upstream responses were dropped for not matching the query they came in for
+pub fn discarded_responses() -> u64 {
+    DISCARDED_RESPONSES.load(Ordering::Relaxed)
+}
+
+fn lookup_tcp(
+    request: &[u8],
+    query: &DnsPacket,
+    server: (IpAddr, u16),
+    timeout: Duration,
+) -> Result<DnsPacket, String> {
+    let mut stream =
+        TcpStream::connect_timeout(&server.into(), timeout).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .and_then(|_| stream.set_write_timeout(Some(timeout)))
+        .map_err(|e| e.to_string())?;
+    write_tcp_message(&mut stream, request)?;
+    let mut res_buffer = read_tcp_message(&mut stream)?;
+    if !answers_query(&mut res_buffer, query) {
+        return Err(format!("Mismatched response from {:?} over tcp", server));
+    }
+    res_buffer.seek(0)?;
+
+    DnsPacket::from_buffer(&mut res_buffer)
+}
+
+pub fn read_tcp_message(stream: &mut TcpStream) -> Result<BytePacketBuffer, String> {
+    let mut len_bytes = [0; 2];
+    stream
+        .read_exact(&mut len_bytes)
+        .map_err(|e| e.to_string())?;
+    let len = u16::from_be_bytes(len_bytes) as usize;
+
+    let mut buffer = BytePacketBuffer::with_size(len);
+    stream
+        .read_exact(&mut buffer.buf)
+        .map_err(|e| e.to_string())?;
+
+    Ok(buffer)
+}
+
+pub fn write_tcp_message(stream: &mut TcpStream, data: &[u8]) -> Result<(), String> {
+    let mut message = Vec::with_capacity(data.len() + 2);
+    message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    message.extend_from_slice(data);
+    stream.write_all(&message).map_err(|e| e.to_string())
+}
+
+fn write_response(packet: &DnsPacket, max_size: usize) -> BytePacketBuffer {
+    let mut res_buffer = BytePacketBuffer::with_max_size(max_size);
+    if packet.write(&mut res_buffer).is_err() {
+        println!("Packet size overflow, truncated.");
+        res_buffer = BytePacketBuffer::with_max_size(max_size);
+        truncate_packet(packet).write(&mut res_buffer).unwrap();
+    }
+    res_buffer
+}
+
+fn truncate_packet(packet: &DnsPacket) -> DnsPacket {
+    let mut truncated = DnsPacket {
+        header: packet.header.clone(),
+        questions: packet.questions.clone(),
+        ..Default::default()
+    };
+    truncated.header.truncated_message = true;
+    truncated.header.answers = 0;
+    truncated.header.authoritative_entries = 0;
+    truncated.header.resource_entries = 0;
+    if let Some(edns) = packet.get_edns() {
+        truncated.resources.push(edns.clone());
+        truncated.header.resource_entries = 1;
+    }
+    truncated
+}
+
+fn populate_dns_packet(
+    packet: &mut DnsPacket,
+    question: DnsQuestion,
+    result: &DnsPacket,
+    dnssec_ok: bool,
+) {
+    // DNSSEC records are only sent to clients that asked for them (RFC 4035 3.2.1)
+    let qtype = question.qtype;
+    let wanted = |rec: &&DnsRecord| match rec {
+        DnsRecord::RRSIG { .. } => dnssec_ok || qtype == QueryType::RRSIG,
+        DnsRecord::NSEC { .. } => dnssec_ok || qtype == QueryType::NSEC,
+        DnsRecord::NSEC3 { .. } => dnssec_ok || qtype == QueryType::NSEC3,
+        // The upstream OPT record only describes the upstream connection
+        DnsRecord::OPT { .. } => false,
+        _ => true,
+    };
     packet.questions.push(question);
     packet.header.rescode = result.header.rescode;
-    packet.header.answers = result.answers.len() as u16;
-    packet.header.authoritative_entries = result.authorities.len() as u16;
-    packet.header.resource_entries = result.resources.len() as u16;
-    for rec in result.answers.iter() {
+    packet.header.authed_data = result.header.authed_data;
+    for rec in result.answers.iter().filter(wanted) {
         println!("Answer: {:?}", rec);
         packet.answers.push(rec.clone());
     }
-    for rec in result.authorities.iter() {
+    for rec in result.authorities.iter().filter(wanted) {
         println!("Authority: {:?}", rec);
         packet.authorities.push(rec.clone());
     }
-    for rec in result.resources.iter() {
+    for rec in result.resources.iter().filter(wanted) {
         println!("Resource: {:?}", rec);
         packet.resources.push(rec.clone());
     }
+    packet.header.answers = packet.answers.len() as u16;
+    packet.header.authoritative_entries = packet.authorities.len() as u16;
+    packet.header.resource_entries = packet.resources.len() as u16;
 }
@@ -1,97 +1,416 @@
-use std::collections::{HashMap, VecDeque};
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::net::IpAddr;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
 
 use crate::dns_packet::DnsPacket;
+use crate::dns_question::DnsQuestion;
 use crate::dns_record::DnsRecord;
+use crate::dnssec::{is_subdomain, rrsets};
+use crate::query_class::QueryClass;
+use crate::query_type::QueryType;
+use crate::res_code::ResultCode;
+use crate::utils::MAX_CNAME_CHAIN;
 
+// Names compare case-insensitively, so they are lowercased in the key
+pub type CacheKey = (String, QueryType, QueryClass);
+
+// Small caches aren't worth splitting up, shards hold at least this many entries
+const MIN_SHARD_SIZE: usize = 1024;
+const MAX_SHARDS: usize = 16;
+
+// Marks the ends of a shard's recency list
+const NIL: usize = usize::MAX;
+
+// Caches individual RRsets, which answers are assembled from. Keys are spread over
+// independently locked shards, each evicting its own least recently used entries.
 #[derive(Debug)]
 pub struct LRUCache {
+    shards: Vec<Mutex<Shard>>,
+    hasher: RandomState,
+    // Longer CNAME chains than the resolver follows are neither cached nor served
+    max_cname_chain: usize,
+}
+
+#[derive(Debug)]
+struct Shard {
     capacity: usize,
-    map: HashMap<String, Arc<Mutex<Node>>>,
-    order: VecDeque<String>,
+    map: HashMap<CacheKey, usize>,
+    // Slots are linked from the most to the least recently used
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+    head: usize,
+    tail: usize,
+}
+
+#[derive(Debug)]
+struct Slot {
+    key: CacheKey,
+    node: Node,
+    prev: usize,
+    next: usize,
 }
 
 #[derive(Clone, Debug)]
 struct Node {
-    value: DnsPacket,
+    // An RRset with the RRSIGs covering it, or the authority section of a negative answer
+    records: Vec<DnsRecord>,
+    negative: Option<ResultCode>,
+    authed: bool,
+    // Learned from a referral, so it only steers recursion and is never served as an answer
+    glue: bool,
+    ttl: u32,
     time: Instant,
 }
 
+impl Node {
+    fn remaining_ttl(&self) -> u32 {
+        self.ttl
+            .saturating_sub(self.time.elapsed().as_secs().min(u32::MAX as u64) as u32)
+    }
+}
+
 impl LRUCache {
     pub fn new(capacity: usize) -> Self {
+        Self::with_shards(capacity, (capacity / MIN_SHARD_SIZE).clamp(1, MAX_SHARDS))
+    }
+
+    pub fn with_shards(capacity: usize, shards: usize) -> Self {
+        let shards = shards.max(1);
+        let shard_capacity = capacity.div_ceil(shards).max(1);
         LRUCache {
-            capacity,
-            map: HashMap::new(),
-            order: VecDeque::new(),
+            shards: (0..shards)
+                .map(|_| Mutex::new(Shard::new(shard_capacity)))
+                .collect(),
+            hasher: RandomState::new(),
+            max_cname_chain: MAX_CNAME_CHAIN,
         }
     }
 
-    pub fn get(&mut self, key: &String) -> Option<DnsPacket> {
-        if let Some(node) = self.map.get(key) {
-            let node = node.lock().unwrap();
-            // Check if the cache value is expired
-            if node.time < Instant::now() {
-                // TTL expired, remove the key from the cache
-                drop(node);
-                self.remove(key);
-                return None;
+    pub fn with_max_cname_chain(mut self, max_cname_chain: usize) -> Self {
+        self.max_cname_chain = max_cname_chain;
+        self
+    }
+
+    pub fn get(&self, question: &DnsQuestion) -> Option<DnsPacket> {
+        let mut packet = DnsPacket::default();
+        packet.header.authed_data = true;
+        let mut name = question.name.to_lowercase();
+        let mut complete = false;
+        for _ in 0..=self.max_cname_chain {
+            if let Some(node) = self.get_node(&(name.clone(), question.qtype, question.qclass)) {
+                packet.header.authed_data &= node.authed;
+                match node.negative {
+                    Some(rescode) => {
+                        packet.header.rescode = rescode;
+                        packet.authorities = node.records;
+                    }
+                    None => packet.answers.extend(node.records),
+                }
+                complete = true;
+                break;
+            }
+            if question.qtype == QueryType::CNAME {
+                break;
+            }
+            // Follow a cached alias to the RRsets of the name it points at
+            let Some(node) = self.get_node(&(name.clone(), QueryType::CNAME, question.qclass))
+            else {
+                break;
+            };
+            packet.header.authed_data &= node.authed;
+            let target = node.records.iter().find_map(|record| match record {
+                DnsRecord::CNAME { host, .. } => Some(host.to_lowercase()),
+                _ => None,
+            });
+            packet.answers.extend(node.records);
+            match target {
+                Some(target) => name = target,
+                None => break,
             }
-            // Move the key to the front of the order queue
-            self.order.retain(|x| x != key);
-            self.order.push_front(key.to_string());
-            Some(node.value.clone())
-        } else {
-            None
-        }
-    }
-
-    pub fn put(&mut self, key: &String, value: &DnsPacket) {
-        let ttl = value
-            .answers
-            .iter()
-            .map(|answer| match answer {
-                DnsRecord::A { ttl, .. }
-                | DnsRecord::NS { ttl, .. }
-                | DnsRecord::CNAME { ttl, .. }
-                | DnsRecord::MX { ttl, .. }
-                | DnsRecord::AAAA { ttl, .. }
-                | DnsRecord::UNKNOWN { ttl, .. } => *ttl,
-            })
-            .min()
-            .unwrap_or(0);
-
-        // If the key already exists, we update the value and move it to the front of the queue
-        if let Some(node) = self.map.get(key) {
-            let mut node = node.lock().unwrap();
-            node.value = value.clone();
-            node.time = Instant::now() + Duration::from_secs(ttl as u64);
-            self.order.retain(|x| x != key);
-            self.order.push_front(key.clone());
-        } else {
-            // If the cache is full, remove the least recently used element
-            if self.map.len() == self.capacity {
-                if let Some(lru_key) = self.order.pop_back() {
-                    self.map.remove(&lru_key);
+        }
+        // A chain whose later links have expired has to be resolved again
+        if !complete {
+            return None;
+        }
+        packet.header.answers = packet.answers.len() as u16;
+        packet.header.authoritative_entries = packet.authorities.len() as u16;
+        Some(packet)
+    }
+
+    pub fn put(&self, question: &DnsQuestion, value: &DnsPacket) {
+        let authed = value.header.authed_data;
+        // Only the RRsets on the way from the question to the answer are cached, anything
+        // else in the answer section wasn't asked for
+        let mut chain = vec![question.name.to_lowercase()];
+        while question.qtype != QueryType::CNAME && chain.len() <= self.max_cname_chain {
+            let name = &chain[chain.len() - 1];
+            match value.answers.iter().find_map(|record| match record {
+                DnsRecord::CNAME { domain, host, .. } if domain.eq_ignore_ascii_case(name) => {
+                    Some(host.to_lowercase())
                 }
+                _ => None,
+            }) {
+                Some(target) if !chain.contains(&target) => chain.push(target),
+                _ => break,
+            }
+        }
+        for (rrset, rrsigs) in rrsets(&value.answers) {
+            let owner = rrset[0].domain().to_lowercase();
+            let on_chain = match rrset[0] {
+                // CNAMEs synthesized from a DNAME are on the chain, the DNAME is above it
+                DnsRecord::DNAME { .. } => chain
+                    .iter()
+                    .any(|name| *name != owner && is_subdomain(name, &owner)),
+                _ => chain.contains(&owner),
+            };
+            if !on_chain {
+                continue;
             }
-            // Insert the new key-value pair with TTL
-            self.map.insert(
-                key.clone(),
-                Arc::new(Mutex::new(Node {
-                    value: value.clone(),
-                    time: Instant::now() + Duration::from_secs(ttl as u64),
-                })),
+            self.insert(
+                record_key(&rrset[0]),
+                rrset.into_iter().chain(rrsigs).collect(),
+                None,
+                authed,
+                false,
             );
-            self.order.push_front(key.clone());
+        }
+        if let Some(ttl) = value.get_negative_ttl() {
+            // The SOA sent back with a cached negative answer carries the negative ttl
+            let mut authorities = value.authorities.clone();
+            for record in authorities.iter_mut() {
+                if let DnsRecord::SOA { .. } = record {
+                    record.set_ttl(ttl);
+                }
+            }
+            // The denial is about the end of any CNAME chain in the answer
+            let name = chain.pop().unwrap_or_default();
+            let key = (name, question.qtype, question.qclass);
+            self.insert(key, authorities, Some(value.header.rescode), authed, false);
         }
     }
 
-    pub fn remove(&mut self, key: &String) {
-        // Remove the key from the map
-        if self.map.remove(key).is_some() {
-            // Remove the key from the order list
-            self.order.retain(|x| x != key);
+    // Keeps the NS RRsets and glue addresses of a referral for later recursion, as far
+    // as the servers for zone that sent it are in a position to know them
+    pub fn put_delegation(&self, response: &DnsPacket, qname: &str, zone: &str) {
+        let delegation: Vec<DnsRecord> = response.get_delegation(qname, zone).cloned().collect();
+        let glue: Vec<DnsRecord> = response.get_glue(qname, zone).cloned().collect();
+        for (rrset, _) in rrsets(&delegation).into_iter().chain(rrsets(&glue)) {
+            self.insert(record_key(&rrset[0]), rrset, None, false, true);
         }
     }
+
+    // Keeps the addresses a nameserver without glue was resolved to, so later lookups in
+    // its zones can go straight to it
+    pub fn put_nameserver(&self, host: &str, response: &DnsPacket) {
+        for (rrset, _) in rrsets(&response.answers) {
+            let qtype = rrset[0].qtype();
+            if matches!(qtype, QueryType::A | QueryType::AAAA)
+                && rrset[0].domain().eq_ignore_ascii_case(host)
+            {
+                self.insert(record_key(&rrset[0]), rrset, None, false, true);
+            }
+        }
+    }
+
+    // The deepest zone above qname with cached NS records and the addresses of its
+    // servers, the root with none if nothing is known
+    pub fn closest_nameservers(&self, qname: &str) -> (String, Vec<IpAddr>) {
+        let mut name = qname.to_lowercase();
+        loop {
+            let hosts = self
+                .get_any_node(&(name.clone(), QueryType::NS, QueryClass::IN))
+                .map_or(Vec::new(), |node| node.records);
+            let addrs: Vec<IpAddr> = hosts
+                .iter()
+                .filter_map(|record| match record {
+                    DnsRecord::NS { host, .. } => Some(host.to_lowercase()),
+                    _ => None,
+                })
+                .flat_map(|host| {
+                    [QueryType::A, QueryType::AAAA]
+                        .map(|qtype| self.get_any_node(&(host.clone(), qtype, QueryClass::IN)))
+                })
+                .flatten()
+                .flat_map(|node| node.records)
+                .filter_map(|record| match record {
+                    DnsRecord::A { addr, .. } => Some(IpAddr::V4(addr)),
+                    DnsRecord::AAAA { addr, .. } => Some(IpAddr::V6(addr)),
+                    _ => None,
+                })
+                .collect();
+            if !addrs.is_empty() || name.is_empty() {
+                return (name, addrs);
+            }
+            name = name
+                .split_once('.')
+                .map_or("", |(_, parent)| parent)
+                .to_string();
+        }
+    }
+
+    pub fn remove(&self, question: &DnsQuestion) {
+        self.remove_key(&cache_key(question));
+    }
+
+    fn get_node(&self, key: &CacheKey) -> Option<Node> {
+        self.get_any_node(key).filter(|node| !node.glue)
+    }
+
+    fn get_any_node(&self, key: &CacheKey) -> Option<Node> {
+        let mut node = self.shard(key).get(key)?;
+        // Clients see how long the records have left rather than their original TTL
+        let ttl = node.remaining_ttl();
+        for record in node.records.iter_mut() {
+            record.set_ttl(ttl);
+        }
+        Some(node)
+    }
+
+    fn insert(
+        &self,
+        key: CacheKey,
+        records: Vec<DnsRecord>,
+        negative: Option<ResultCode>,
+        authed: bool,
+        glue: bool,
+    ) {
+        let ttl = match negative {
+            Some(_) => records.iter().find(|r| r.qtype() == QueryType::SOA),
+            None => records.iter().min_by_key(|r| r.ttl()),
+        }
+        .map_or(0, DnsRecord::ttl);
+        if ttl == 0 {
+            return;
+        }
+        let node = Node {
+            records,
+            negative,
+            authed,
+            glue,
+            ttl,
+            time: Instant::now(),
+        };
+        self.shard(&key).insert(key, node);
+    }
+
+    fn remove_key(&self, key: &CacheKey) {
+        self.shard(key).remove(key);
+    }
+
+    fn shard(&self, key: &CacheKey) -> MutexGuard<'_, Shard> {
+        let index = self.hasher.hash_one(key) as usize % self.shards.len();
+        self.shards[index].lock().unwrap()
+    }
+}
+
+impl Shard {
+    fn new(capacity: usize) -> Self {
+        Shard {
+            capacity,
+            map: HashMap::new(),
+            slots: Vec::new(),
+            free: Vec::new(),
+            head: NIL,
+            tail: NIL,
+        }
+    }
+
+    fn get(&mut self, key: &CacheKey) -> Option<Node> {
+        let index = *self.map.get(key)?;
+        if self.slots[index].node.remaining_ttl() == 0 {
+            // TTL expired, remove the key from the cache
+            self.remove(key);
+            return None;
+        }
+        self.unlink(index);
+        self.push_front(index);
+        Some(self.slots[index].node.clone())
+    }
+
+    fn insert(&mut self, key: CacheKey, node: Node) {
+        if let Some(&index) = self.map.get(&key) {
+            let existing = &self.slots[index].node;
+            // Glue never replaces what an authoritative answer told us
+            if node.glue && !existing.glue && existing.remaining_ttl() > 0 {
+                return;
+            }
+            self.slots[index].node = node;
+            self.unlink(index);
+            self.push_front(index);
+            return;
+        }
+        // If the shard is full, remove the least recently used element
+        if self.map.len() >= self.capacity && self.tail != NIL {
+            let lru_key = self.slots[self.tail].key.clone();
+            self.remove(&lru_key);
+        }
+        let slot = Slot {
+            key: key.clone(),
+            node,
+            prev: NIL,
+            next: NIL,
+        };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index] = slot;
+                index
+            }
+            None => {
+                self.slots.push(slot);
+                self.slots.len() - 1
+            }
+        };
+        self.map.insert(key, index);
+        self.push_front(index);
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(index) = self.map.remove(key) {
+            self.unlink(index);
+            // Free the records now rather than when the slot is reused
+            self.slots[index].node.records = Vec::new();
+            self.free.push(index);
+        }
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let Slot { prev, next, .. } = self.slots[index];
+        match prev {
+            NIL => self.head = next,
+            prev => self.slots[prev].next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.slots[next].prev = prev,
+        }
+    }
+
+    fn push_front(&mut self, index: usize) {
+        self.slots[index].prev = NIL;
+        self.slots[index].next = self.head;
+        match self.head {
+            NIL => self.tail = index,
+            head => self.slots[head].prev = index,
+        }
+        self.head = index;
+    }
+}
+
+pub fn cache_key(question: &DnsQuestion) -> CacheKey {
+    (
+        question.name.to_lowercase(),
+        question.qtype,
+        question.qclass,
+    )
+}
+
+fn record_key(record: &DnsRecord) -> CacheKey {
+    (
+        record.domain().to_lowercase(),
+        record.qtype(),
+        record.class(),
+    )
 }
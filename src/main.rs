@@ -1,16 +1,52 @@
-use rdns_resolver_rs::{lookup::handle_queries, lru_cache::LRUCache, utils};
+use rdns_resolver_rs::{
+    in_flight::InFlight,
+    infra_cache::InfraCache,
+    lookup::{handle_queries, handle_tcp_queries},
+    lru_cache::LRUCache,
+    resolver_config::ResolverConfig,
+    utils,
+};
 use std::{
     io::Error,
-    net::UdpSocket,
-    sync::{Arc, Mutex},
+    net::{TcpListener, UdpSocket},
+    sync::Arc,
+    thread,
 };
 
 fn main() -> Result<(), Error> {
     let req_socket = UdpSocket::bind((utils::LOCAL_HOST, utils::REQ_PORT)).unwrap();
-    let query_socket = UdpSocket::bind((utils::LOCAL_HOST, utils::QUERY_PORT)).unwrap();
-    let cache = Arc::new(Mutex::new(LRUCache::new(100_000))); // Shared cache for all threads
+    let tcp_listener = TcpListener::bind((utils::LOCAL_HOST, utils::REQ_PORT)).unwrap();
+    let config = Arc::new(ResolverConfig::default());
+    // Shared cache for all threads
+    let cache = Arc::new(LRUCache::new(100_000).with_max_cname_chain(config.max_cname_chain));
+    let in_flight = Arc::new(InFlight::new()); // Questions being resolved by some thread
+    let infra = Arc::new(InfraCache::new()); // Round trip times of upstream servers
+
+    let tcp_cache = cache.clone();
+    let tcp_in_flight = in_flight.clone();
+    let tcp_infra = infra.clone();
+    let tcp_config = config.clone();
+    thread::spawn(move || loop {
+        match handle_tcp_queries(
+            &tcp_listener,
+            tcp_cache.clone(),
+            tcp_in_flight.clone(),
+            tcp_infra.clone(),
+            tcp_config.clone(),
+        ) {
+            Ok(_) => {}
+            Err(e) => eprintln!("An error occurred: {}", e),
+        }
+    });
+
     loop {
-        match handle_queries(&req_socket, &query_socket, cache.clone()) {
+        match handle_queries(
+            &req_socket,
+            cache.clone(),
+            in_flight.clone(),
+            infra.clone(),
+            config.clone(),
+        ) {
             Ok(_) => {}
             Err(e) => eprintln!("An error occurred: {}", e),
         }
@@ -0,0 +1,33 @@
+#[derive(PartialEq, Eq, Debug, Clone, Hash, Copy, PartialOrd, Ord)]
+pub enum QueryClass {
+    UNKNOWN(u16),
+    IN,   // 1
+    CH,   // 3
+    HS,   // 4
+    NONE, // 254
+    ANY,  // 255
+}
+
+impl QueryClass {
+    pub fn to_num(&self) -> u16 {
+        match *self {
+            QueryClass::UNKNOWN(x) => x,
+            QueryClass::IN => 1,
+            QueryClass::CH => 3,
+            QueryClass::HS => 4,
+            QueryClass::NONE => 254,
+            QueryClass::ANY => 255,
+        }
+    }
+
+    pub fn from_num(num: u16) -> QueryClass {
+        match num {
+            1 => QueryClass::IN,
+            3 => QueryClass::CH,
+            4 => QueryClass::HS,
+            254 => QueryClass::NONE,
+            255 => QueryClass::ANY,
+            _ => QueryClass::UNKNOWN(num),
+        }
+    }
+}
@@ -1,11 +1,26 @@
-#[derive(PartialEq, Eq, Debug, Clone, Hash, Copy)]
+#[derive(PartialEq, Eq, Debug, Clone, Hash, Copy, PartialOrd, Ord)]
 pub enum QueryType {
     UNKNOWN(u16),
-    A,     // 1
-    NS,    // 2
-    CNAME, // 5
-    MX,    // 15
-    AAAA,  // 28
+    A,          // 1
+    NS,         // 2
+    CNAME,      // 5
+    SOA,        // 6
+    PTR,        // 12
+    MX,         // 15
+    TXT,        // 16
+    AAAA,       // 28
+    SRV,        // 33
+    DNAME,      // 39
+    OPT,        // 41
+    DS,         // 43
+    RRSIG,      // 46
+    NSEC,       // 47
+    DNSKEY,     // 48
+    NSEC3,      // 50
+    NSEC3PARAM, // 51
+    SVCB,       // 64
+    HTTPS,      // 65
+    CAA,        // 257
 }
 
 impl QueryType {
@@ -15,8 +30,23 @@ impl QueryType {
             QueryType::A => 1,
             QueryType::NS => 2,
             QueryType::CNAME => 5,
+            QueryType::SOA => 6,
+            QueryType::PTR => 12,
             QueryType::MX => 15,
+            QueryType::TXT => 16,
             QueryType::AAAA => 28,
+            QueryType::SRV => 33,
+            QueryType::DNAME => 39,
+            QueryType::OPT => 41,
+            QueryType::DS => 43,
+            QueryType::RRSIG => 46,
+            QueryType::NSEC => 47,
+            QueryType::DNSKEY => 48,
+            QueryType::NSEC3 => 50,
+            QueryType::NSEC3PARAM => 51,
+            QueryType::SVCB => 64,
+            QueryType::HTTPS => 65,
+            QueryType::CAA => 257,
         }
     }
 
@@ -25,8 +55,23 @@ impl QueryType {
             1 => QueryType::A,
             2 => QueryType::NS,
             5 => QueryType::CNAME,
+            6 => QueryType::SOA,
+            12 => QueryType::PTR,
             15 => QueryType::MX,
+            16 => QueryType::TXT,
             28 => QueryType::AAAA,
+            33 => QueryType::SRV,
+            39 => QueryType::DNAME,
+            41 => QueryType::OPT,
+            43 => QueryType::DS,
+            46 => QueryType::RRSIG,
+            47 => QueryType::NSEC,
+            48 => QueryType::DNSKEY,
+            50 => QueryType::NSEC3,
+            51 => QueryType::NSEC3PARAM,
+            64 => QueryType::SVCB,
+            65 => QueryType::HTTPS,
+            257 => QueryType::CAA,
             _ => QueryType::UNKNOWN(num),
         }
     }
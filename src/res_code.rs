@@ -1,22 +1,42 @@
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ResultCode {
-    NOERROR = 0,
-    FORMERR = 1,
-    SERVFAIL = 2,
-    NXDOMAIN = 3,
-    NOTIMP = 4,
-    REFUSED = 5,
+    UNKNOWN(u16),
+    NOERROR,  // 0
+    FORMERR,  // 1
+    SERVFAIL, // 2
+    NXDOMAIN, // 3
+    NOTIMP,   // 4
+    REFUSED,  // 5
+    YXDOMAIN, // 6
+    BADVERS,  // 16, extended, needs the upper bits from an OPT record
 }
 
 impl ResultCode {
-    pub fn from_num(num: u8) -> ResultCode {
+    pub fn to_num(&self) -> u16 {
+        match *self {
+            ResultCode::UNKNOWN(x) => x,
+            ResultCode::NOERROR => 0,
+            ResultCode::FORMERR => 1,
+            ResultCode::SERVFAIL => 2,
+            ResultCode::NXDOMAIN => 3,
+            ResultCode::NOTIMP => 4,
+            ResultCode::REFUSED => 5,
+            ResultCode::YXDOMAIN => 6,
+            ResultCode::BADVERS => 16,
+        }
+    }
+
+    pub fn from_num(num: u16) -> ResultCode {
         match num {
+            0 => ResultCode::NOERROR,
             1 => ResultCode::FORMERR,
             2 => ResultCode::SERVFAIL,
             3 => ResultCode::NXDOMAIN,
             4 => ResultCode::NOTIMP,
             5 => ResultCode::REFUSED,
-            _ => ResultCode::NOERROR,
+            6 => ResultCode::YXDOMAIN,
+            16 => ResultCode::BADVERS,
+            _ => ResultCode::UNKNOWN(num),
         }
     }
 }
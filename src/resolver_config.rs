@@ -0,0 +1,105 @@
+use std::{net::IpAddr, ops::RangeInclusive, time::Duration};
+
+use crate::{
+    dns_record::DnsRecord,
+    query_class::QueryClass,
+    query_type::QueryType,
+    utils::{MAX_CNAME_CHAIN, REQ_PORT, ROOT_NAME_SERVERS, ROOT_TRUST_ANCHORS},
+};
+
+// Which address families upstream servers are queried over
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpMode {
+    V4Only,
+    V6Only,
+    DualStack,
+}
+
+impl IpMode {
+    pub fn allows(&self, addr: &IpAddr) -> bool {
+        match self {
+            IpMode::V4Only => addr.is_ipv4(),
+            IpMode::V6Only => addr.is_ipv6(),
+            IpMode::DualStack => true,
+        }
+    }
+
+    // The record types holding the addresses of servers we can reach
+    pub fn address_types(&self) -> &'static [QueryType] {
+        match self {
+            IpMode::V4Only => &[QueryType::A],
+            IpMode::V6Only => &[QueryType::AAAA],
+            IpMode::DualStack => &[QueryType::A, QueryType::AAAA],
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ResolverConfig {
+    // Addresses of either family, those ip_mode doesn't allow are skipped
+    pub root_servers: Vec<IpAddr>,
+    // The port every upstream server is queried on
+    pub port: u16,
+    pub ip_mode: IpMode,
+    // How long to wait for each upstream answer
+    pub timeout: Duration,
+    // How many more rounds through a zone's servers after the first has failed
+    pub retries: u8,
+    // Upstream queries are sent from a random port in these ranges, never an excluded one
+    pub query_ports: Vec<RangeInclusive<u16>>,
+    pub excluded_query_ports: Vec<u16>,
+    // Randomizes the case of names sent upstream (0x20), servers listed here or seen
+    // not to echo the case back are asked in lowercase
+    pub use_0x20: bool,
+    pub no_0x20_servers: Vec<IpAddr>,
+    // Only reveals one more label of the name than the zone being asked (RFC 9156)
+    pub qname_minimisation: bool,
+    // How many CNAMEs are followed for one question before giving up on it
+    pub max_cname_chain: usize,
+    pub dnssec_validation: bool,
+    // DS records for the root zone's keys
+    pub trust_anchors: Vec<DnsRecord>,
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ResolverConfig {
+    fn new() -> ResolverConfig {
+        ResolverConfig {
+            root_servers: ROOT_NAME_SERVERS
+                .iter()
+                .flat_map(|server| [IpAddr::V4(server.a), IpAddr::V6(server.aaaa)])
+                .collect(),
+            port: 53,
+            ip_mode: IpMode::DualStack,
+            timeout: Duration::from_secs(2),
+            retries: 1,
+            query_ports: vec![1024..=65535],
+            excluded_query_ports: vec![REQ_PORT],
+            use_0x20: false,
+            no_0x20_servers: Vec::new(),
+            qname_minimisation: false,
+            max_cname_chain: MAX_CNAME_CHAIN,
+            dnssec_validation: false,
+            trust_anchors: ROOT_TRUST_ANCHORS
+                .iter()
+                .map(|(key_tag, algorithm, digest_type, digest)| DnsRecord::DS {
+                    domain: "".to_string(),
+                    key_tag: *key_tag,
+                    algorithm: *algorithm,
+                    digest_type: *digest_type,
+                    digest: (0..digest.len())
+                        .step_by(2)
+                        .map(|i| u8::from_str_radix(&digest[i..i + 2], 16).unwrap())
+                        .collect(),
+                    class: QueryClass::IN,
+                    ttl: 0,
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::byte_packet_buffer::BytePacketBuffer;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SvcParam {
+    Mandatory(Vec<u16>),     // 0
+    Alpn(Vec<Vec<u8>>),      // 1, ids are opaque bytes
+    NoDefaultAlpn,           // 2
+    Port(u16),               // 3
+    Ipv4Hint(Vec<Ipv4Addr>), // 4
+    Ech(Vec<u8>),            // 5
+    Ipv6Hint(Vec<Ipv6Addr>), // 6
+    Unknown { key: u16, value: Vec<u8> },
+}
+
+impl SvcParam {
+    pub fn key(&self) -> u16 {
+        match self {
+            SvcParam::Mandatory(_) => 0,
+            SvcParam::Alpn(_) => 1,
+            SvcParam::NoDefaultAlpn => 2,
+            SvcParam::Port(_) => 3,
+            SvcParam::Ipv4Hint(_) => 4,
+            SvcParam::Ech(_) => 5,
+            SvcParam::Ipv6Hint(_) => 6,
+            SvcParam::Unknown { key, .. } => *key,
+        }
+    }
+
+    pub fn read(buffer: &mut BytePacketBuffer) -> Result<SvcParam, String> {
+        let key = buffer.read_u16()?;
+        let len = buffer.read_u16()? as usize;
+        let value = buffer.get_range(buffer.pos, len)?.to_vec();
+        buffer.step(len)?;
+
+        let invalid = || format!("Invalid value for SvcParam key{}", key);
+        match key {
+            0 if len.is_multiple_of(2) => {
+                let keys = value
+                    .chunks(2)
+                    .map(|key| u16::from_be_bytes([key[0], key[1]]))
+                    .collect();
+                Ok(SvcParam::Mandatory(keys))
+            }
+            // At least one alpn id, none of them empty (RFC 9460 7.1.1)
+            1 if len > 0 => {
+                let mut ids = Vec::new();
+                let mut pos = 0;
+                while pos < value.len() {
+                    let id_len = value[pos] as usize;
+                    if id_len == 0 {
+                        return Err(invalid());
+                    }
+                    let id = value.get(pos + 1..pos + 1 + id_len).ok_or_else(invalid)?;
+                    ids.push(id.to_vec());
+                    pos += 1 + id_len;
+                }
+                Ok(SvcParam::Alpn(ids))
+            }
+            2 if len == 0 => Ok(SvcParam::NoDefaultAlpn),
+            3 if len == 2 => Ok(SvcParam::Port(u16::from_be_bytes([value[0], value[1]]))),
+            4 if len.is_multiple_of(4) => {
+                let addrs = value
+                    .chunks(4)
+                    .map(|addr| Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]))
+                    .collect();
+                Ok(SvcParam::Ipv4Hint(addrs))
+            }
+            5 => Ok(SvcParam::Ech(value)),
+            6 if len.is_multiple_of(16) => {
+                let addrs = value
+                    .chunks(16)
+                    .map(|addr| Ipv6Addr::from(<[u8; 16]>::try_from(addr).unwrap()))
+                    .collect();
+                Ok(SvcParam::Ipv6Hint(addrs))
+            }
+            0..=6 => Err(invalid()),
+            _ => Ok(SvcParam::Unknown { key, value }),
+        }
+    }
+
+    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<(), String> {
+        let mut value = Vec::new();
+        match self {
+            SvcParam::Mandatory(keys) => {
+                for key in keys {
+                    value.extend_from_slice(&key.to_be_bytes());
+                }
+            }
+            SvcParam::Alpn(ids) => {
+                if ids.is_empty() {
+                    return Err("Empty alpn list".to_string());
+                }
+                for id in ids {
+                    if id.is_empty() || id.len() > 255 {
+                        return Err("Invalid alpn id length".to_string());
+                    }
+                    value.push(id.len() as u8);
+                    value.extend_from_slice(id);
+                }
+            }
+            SvcParam::NoDefaultAlpn => {}
+            SvcParam::Port(port) => value.extend_from_slice(&port.to_be_bytes()),
+            SvcParam::Ipv4Hint(addrs) => {
+                for addr in addrs {
+                    value.extend_from_slice(&addr.octets());
+                }
+            }
+            SvcParam::Ech(config) => value.extend_from_slice(config),
+            SvcParam::Ipv6Hint(addrs) => {
+                for addr in addrs {
+                    value.extend_from_slice(&addr.octets());
+                }
+            }
+            SvcParam::Unknown { value: raw, .. } => value.extend_from_slice(raw),
+        }
+
+        buffer.write_u16(self.key())?;
+        buffer.write_u16(value.len() as u16)?;
+        for byte in value {
+            buffer.write(byte)?;
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use std::net::{Ipv4Addr, Ipv6Addr};
 use std::sync::LazyLock;
+use std::time::Duration;
 
 pub struct RootNameServer {
     pub name: String,
@@ -12,7 +13,45 @@ pub static LOCAL_HOST: &str = "0.0.0.0";
 
 pub static REQ_PORT: u16 = 2053;
 
-pub static QUERY_PORT: u16 = 43210;
+// Random query ports tried before giving up, some may be excluded or in use
+pub static QUERY_PORT_ATTEMPTS: usize = 16;
+
+// How long a client connection may sit idle between queries
+pub static TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub static EDNS_PAYLOAD_SIZE: u16 = 1232;
+
+// Delegations followed for one name before giving up on a referral loop
+pub static MAX_REFERRALS: usize = 16;
+
+// Nameservers without glue looked up inside the lookup of another one's address, beyond
+// this they are assumed to depend on each other
+pub static MAX_GLUELESS_DEPTH: usize = 4;
+
+// CNAMEs followed from one name unless the config says otherwise
+pub static MAX_CNAME_CHAIN: usize = 8;
+
+// Minimised queries sent for one name before the rest of it is given away (RFC 9156)
+pub static MAX_MINIMISE_COUNT: usize = 10;
+
+// Answered for CHAOS class id.server / hostname.bind queries
+pub static SERVER_ID: &str = "rdns-resolver-rs";
+
+// The root zone KSKs as DS key tag, algorithm, digest type and digest
+pub static ROOT_TRUST_ANCHORS: [(u16, u8, u8, &str); 2] = [
+    (
+        20326,
+        8,
+        2,
+        "e06d44b80b8f1d39a95c0b0d7c65d08458e880409bbc683457104237c7f8ec8d",
+    ),
+    (
+        38696,
+        8,
+        2,
+        "683d2d0acb8c9b712a1948b27f741219298d0a450d612c483af444a4c0fb2b16",
+    ),
+];
 
 pub static ROOT_NAME_SERVERS: LazyLock<[RootNameServer; 13]> = LazyLock::new(|| {
     [
@@ -0,0 +1,302 @@
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    dns_packet::DnsPacket,
+    dns_question::DnsQuestion,
+    dns_record::DnsRecord,
+    dnssec::{
+        is_subdomain, label_count, prove_denial, prove_wildcard, rrset_of, rrsets,
+        supported_algorithm, supported_digest, verify_ds, verify_rrset, Denial, Security,
+    },
+    infra_cache::InfraCache,
+    lookup::{recursive_lookup, substitute_dname},
+    lru_cache::LRUCache,
+    query_type::QueryType,
+    res_code::ResultCode,
+    resolver_config::ResolverConfig,
+};
+
+#[derive(Clone, Debug)]
+enum ZoneKeys {
+    Secure { zone: String, keys: Vec<DnsRecord> },
+    Insecure,
+}
+
+// Walks the chain of trust down from the root trust anchors (RFC 4035 5)
+pub struct Validator<'a> {
+    config: &'a ResolverConfig,
+    cache: &'a LRUCache,
+    infra: &'a InfraCache,
+    // The keys of the zone every name walked so far belongs to
+    zones: HashMap<String, ZoneKeys>,
+    now: u32,
+}
+
+impl<'a> Validator<'a> {
+    pub fn new(
+        config: &'a ResolverConfig,
+        cache: &'a LRUCache,
+        infra: &'a InfraCache,
+    ) -> Validator<'a> {
+        Validator {
+            config,
+            cache,
+            infra,
+            zones: HashMap::new(),
+            now: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs() as u32),
+        }
+    }
+
+    pub fn validate(&mut self, qname: &str, qtype: QueryType, response: &DnsPacket) -> Security {
+        match self.validate_response(qname, qtype, response) {
+            Ok(true) => Security::Secure,
+            Ok(false) => Security::Insecure,
+            Err(reason) => Security::Bogus(reason),
+        }
+    }
+
+    fn validate_response(
+        &mut self,
+        qname: &str,
+        qtype: QueryType,
+        response: &DnsPacket,
+    ) -> Result<bool, String> {
+        let mut secure = true;
+        for (rrset, rrsigs) in rrsets(&response.answers) {
+            // CNAMEs synthesized from a DNAME are unsigned, the DNAME vouches for them
+            if rrsigs.is_empty() && synthesized(&rrset, &response.answers) {
+                continue;
+            }
+            let Some(keys) = self.verify_signed(&rrset, &rrsigs)? else {
+                secure = false;
+                continue;
+            };
+            let owner = rrset[0].domain();
+            // A wildcard expansion needs proof that there was no closer match
+            let labels = rrsigs.iter().filter_map(|r| match r {
+                DnsRecord::RRSIG { labels, .. } => Some(*labels),
+                _ => None,
+            });
+            if let Some(labels) = labels.min().filter(|l| *l < label_count(owner)) {
+                self.verify_authorities(response, &keys)?;
+                prove_wildcard(owner, labels, &response.authorities)?;
+            }
+        }
+
+        // Follow any CNAMEs to the name the answer is really about
+        let mut target = qname.to_lowercase();
+        for _ in 0..response.answers.len() {
+            match response
+                .answers
+                .iter()
+                .find(|r| r.domain().eq_ignore_ascii_case(&target))
+            {
+                Some(DnsRecord::CNAME { host, .. }) if qtype != QueryType::CNAME => {
+                    target = host.to_lowercase();
+                }
+                _ => break,
+            }
+        }
+        let answered = response
+            .answers
+            .iter()
+            .any(|r| r.qtype() == qtype && r.domain().eq_ignore_ascii_case(&target));
+        // A chain that stops short without a negative answer is left for the client to follow
+        let negative = response.header.rescode == ResultCode::NXDOMAIN
+            || target == qname
+            || response
+                .authorities
+                .iter()
+                .any(|r| matches!(r, DnsRecord::SOA { .. }));
+        // The signed DNAME alone explains a YXDOMAIN
+        if (answered && response.header.rescode != ResultCode::NXDOMAIN)
+            || response.header.rescode == ResultCode::YXDOMAIN
+            || !negative
+        {
+            // AD vouches for the authority section as well (RFC 4035 3.2.3)
+            if secure {
+                for (rrset, rrsigs) in rrsets(&response.authorities) {
+                    if self.verify_signed(&rrset, &rrsigs)?.is_none() {
+                        secure = false;
+                    }
+                }
+            }
+            return Ok(secure);
+        }
+
+        // Negative answers, also at the end of a CNAME chain, are proven by the NSEC or NSEC3 records in the authority section
+        let ZoneKeys::Secure { keys, .. } = self.zone_keys(&target)? else {
+            return Ok(false);
+        };
+        self.verify_authorities(response, &keys)?;
+        match prove_denial(&target, qtype, &response.authorities)? {
+            Denial::NoData { .. } if response.header.rescode == ResultCode::NXDOMAIN => {
+                Err(format!("{} exists but the answer was NXDOMAIN", target))
+            }
+            Denial::Insecure => Ok(false),
+            _ => Ok(secure),
+        }
+    }
+
+    fn zone_keys(&mut self, name: &str) -> Result<ZoneKeys, String> {
+        let name = name.to_lowercase();
+        if let Some(keys) = self.zones.get(&name) {
+            return Ok(keys.clone());
+        }
+        let keys = if name.is_empty() {
+            let response = self.resolve("", QueryType::DNSKEY)?;
+            let keys = self.trusted_keys("", &response, &self.config.trust_anchors)?;
+            let secure = matches!(keys, ZoneKeys::Secure { .. });
+            self.checked("", QueryType::DNSKEY, response, secure);
+            keys
+        } else {
+            let parent = name.split_once('.').map_or("", |(_, parent)| parent);
+            match self.zone_keys(parent)? {
+                ZoneKeys::Secure { zone, keys } => self.child_keys(&name, zone, keys)?,
+                ZoneKeys::Insecure => ZoneKeys::Insecure,
+            }
+        };
+        self.zones.insert(name, keys.clone());
+        Ok(keys)
+    }
+
+    // Whether name is a zone cut below zone and if so whether it is signed
+    fn child_keys(
+        &mut self,
+        name: &str,
+        zone: String,
+        keys: Vec<DnsRecord>,
+    ) -> Result<ZoneKeys, String> {
+        let response = self.resolve(name, QueryType::DS)?;
+        let (ds_set, rrsigs) = rrset_of(&response.answers, name, QueryType::DS);
+        if !ds_set.is_empty() {
+            verify_rrset(&ds_set, &rrsigs, &keys, self.now)
+                .map_err(|e| format!("{} DS: {}", name, e))?;
+            self.checked(name, QueryType::DS, response, true);
+            let response = self.resolve(name, QueryType::DNSKEY)?;
+            let child = self.trusted_keys(name, &response, &ds_set)?;
+            let secure = matches!(child, ZoneKeys::Secure { .. });
+            self.checked(name, QueryType::DNSKEY, response, secure);
+            return Ok(child);
+        }
+        // A CNAME can't be a zone cut
+        if response
+            .answers
+            .iter()
+            .any(|r| r.qtype() == QueryType::CNAME && r.domain().eq_ignore_ascii_case(name))
+        {
+            return Ok(ZoneKeys::Secure { zone, keys });
+        }
+        self.verify_authorities(&response, &keys)?;
+        let denial = prove_denial(name, QueryType::DS, &response.authorities)?;
+        self.checked(name, QueryType::DS, response, denial != Denial::Insecure);
+        match denial {
+            Denial::NoData { delegation: true } | Denial::Insecure => Ok(ZoneKeys::Insecure),
+            _ => Ok(ZoneKeys::Secure { zone, keys }),
+        }
+    }
+
+    // The zone's DNSKEY RRset, if it is signed by a key one of the DS records points at
+    fn trusted_keys(
+        &self,
+        zone: &str,
+        response: &DnsPacket,
+        ds_set: &[DnsRecord],
+    ) -> Result<ZoneKeys, String> {
+        // Zones signed only with algorithms we can't check are treated as unsigned
+        let usable = ds_set.iter().any(|ds| match ds {
+            DnsRecord::DS {
+                algorithm,
+                digest_type,
+                ..
+            } => supported_algorithm(*algorithm) && supported_digest(*digest_type),
+            _ => false,
+        });
+        if !usable {
+            return Ok(ZoneKeys::Insecure);
+        }
+        let (dnskeys, rrsigs) = rrset_of(&response.answers, zone, QueryType::DNSKEY);
+        let anchors: Vec<DnsRecord> = dnskeys
+            .iter()
+            .filter(|key| ds_set.iter().any(|ds| verify_ds(ds, key)))
+            .cloned()
+            .collect();
+        if anchors.is_empty() {
+            return Err(format!("No DNSKEY for {:?} matches its DS records", zone));
+        }
+        verify_rrset(&dnskeys, &rrsigs, &anchors, self.now)
+            .map_err(|e| format!("{:?} DNSKEY: {}", zone, e))?;
+        Ok(ZoneKeys::Secure {
+            zone: zone.to_string(),
+            keys: dnskeys,
+        })
+    }
+
+    // The keys rrset was verified with, None if the zone that signed it is insecure
+    fn verify_signed(
+        &mut self,
+        rrset: &[DnsRecord],
+        rrsigs: &[DnsRecord],
+    ) -> Result<Option<Vec<DnsRecord>>, String> {
+        let owner = rrset[0].domain();
+        let signer = match rrsigs.first() {
+            Some(DnsRecord::RRSIG { signer_name, .. }) => signer_name.as_str(),
+            _ => owner,
+        };
+        // Otherwise any unsigned zone could be named to vouch for a signed one's data
+        if !is_subdomain(owner, signer) {
+            return Err(format!(
+                "{} is signed by {}, which isn't above it",
+                owner, signer
+            ));
+        }
+        let ZoneKeys::Secure { keys, .. } = self.zone_keys(signer)? else {
+            return Ok(None);
+        };
+        verify_rrset(rrset, rrsigs, &keys, self.now)
+            .map_err(|e| format!("{} {:?}: {}", owner, rrset[0].qtype(), e))?;
+        Ok(Some(keys))
+    }
+
+    // Every RRset in the authority section except the unsigned delegation NS must verify
+    fn verify_authorities(&self, response: &DnsPacket, keys: &[DnsRecord]) -> Result<(), String> {
+        for (rrset, rrsigs) in rrsets(&response.authorities) {
+            if rrset[0].qtype() == QueryType::NS {
+                continue;
+            }
+            verify_rrset(&rrset, &rrsigs, keys, self.now)
+                .map_err(|e| format!("{} {:?}: {}", rrset[0].domain(), rrset[0].qtype(), e))?;
+        }
+        Ok(())
+    }
+
+    fn resolve(&mut self, qname: &str, qtype: QueryType) -> Result<DnsPacket, String> {
+        let question = DnsQuestion::new(qname.to_string(), qtype);
+        if let Some(response) = self.cache.get(&question) {
+            return Ok(response);
+        }
+        recursive_lookup(self.config, self.cache, self.infra, qname, qtype)
+    }
+
+    // DS and DNSKEY responses are only cached once they have been checked
+    fn checked(&mut self, qname: &str, qtype: QueryType, mut response: DnsPacket, secure: bool) {
+        response.header.authed_data = secure;
+        self.cache
+            .put(&DnsQuestion::new(qname.to_string(), qtype), &response);
+    }
+}
+
+// Whether rrset is a lone CNAME that a DNAME in answers implies
+fn synthesized(rrset: &[DnsRecord], answers: &[DnsRecord]) -> bool {
+    let [DnsRecord::CNAME { domain, host, .. }] = rrset else {
+        return false;
+    };
+    answers.iter().any(|record| {
+        substitute_dname(record, domain).is_some_and(|target| target.eq_ignore_ascii_case(host))
+    })
+}
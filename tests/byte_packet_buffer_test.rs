@@ -42,6 +42,22 @@ fn test_write_qname() {
     assert_eq!(result, "example.com");
 }
 
+#[test]
+fn test_read_qname_preserving_case() {
+    let mut buffer = BytePacketBuffer::default();
+    buffer.write_qname("ExAmPlE.cOm").unwrap();
+
+    buffer.seek(0).unwrap();
+    let mut result = String::new();
+    buffer.read_qname(&mut result).unwrap();
+    assert_eq!(result, "example.com");
+
+    buffer.seek(0).unwrap();
+    let mut result = String::new();
+    buffer.read_qname_preserving_case(&mut result).unwrap();
+    assert_eq!(result, "ExAmPlE.cOm");
+}
+
 #[test]
 fn test_set_u16() {
     let mut buffer = BytePacketBuffer::default();
@@ -77,3 +93,44 @@ fn test_read_qname_with_pointer() {
     buffer.read_qname(&mut result).unwrap();
     assert_eq!(result, "www");
 }
+
+#[test]
+fn test_with_size() {
+    let mut buffer = BytePacketBuffer::with_size(4096);
+    assert_eq!(buffer.buf.len(), 4096);
+    buffer.seek(4095).unwrap();
+    buffer.write_u8(1).unwrap();
+    assert!(buffer.write_u8(2).is_err());
+}
+
+#[test]
+fn test_with_max_size_grows() {
+    let mut buffer = BytePacketBuffer::with_max_size(1024);
+    assert_eq!(buffer.buf.len(), 0);
+    assert!(buffer.read().is_err());
+
+    for i in 0..1024 {
+        buffer.write_u8(i as u8).unwrap();
+    }
+    assert_eq!(buffer.buf.len(), 1024);
+    assert!(buffer.write_u8(0).is_err());
+
+    buffer.seek(600).unwrap();
+    assert_eq!(buffer.read_u16().unwrap(), (600 % 256) << 8 | (601 % 256));
+}
+
+#[test]
+fn test_write_qname_compression_beyond_pointer_range() {
+    let mut buffer = BytePacketBuffer::with_max_size(u16::MAX as usize);
+    buffer.seek(0x4000).unwrap();
+    buffer.write_qname("example.com").unwrap();
+    let end = buffer.pos;
+    buffer.write_qname("example.com").unwrap();
+    // The first name sits past the 14 bit pointer range, so it is written out again
+    assert_eq!(buffer.pos - end, end - 0x4000);
+
+    buffer.seek(end).unwrap();
+    let mut result = String::new();
+    buffer.read_qname(&mut result).unwrap();
+    assert_eq!(result, "example.com");
+}
@@ -0,0 +1,590 @@
+#![allow(dead_code)]
+
+use std::{
+    net::{IpAddr, Ipv4Addr, TcpListener, UdpSocket},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rdns_resolver_rs::{
+    byte_packet_buffer::BytePacketBuffer,
+    dns_packet::DnsPacket,
+    dns_record::DnsRecord,
+    dnssec::{
+        base32hex_encode, canonical_cmp, canonical_name, ds_digest, is_subdomain, key_tag,
+        label_count, nsec3_hash, signed_data,
+    },
+    lookup::{read_tcp_message, write_tcp_message},
+    query_class::QueryClass,
+    query_type::QueryType,
+    res_code::ResultCode,
+};
+use ring::{
+    rand::SystemRandom,
+    signature::{
+        EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair, RsaPublicKeyComponents,
+        ECDSA_P256_SHA256_FIXED_SIGNING, ECDSA_P384_SHA384_FIXED_SIGNING, RSA_PKCS1_SHA256,
+    },
+};
+
+pub fn response_to(request: &DnsPacket) -> DnsPacket {
+    let mut packet = DnsPacket::default();
+    packet.header.id = request.header.id;
+    packet.header.response = true;
+    packet.header.questions = 1;
+    packet.questions = request.questions.clone();
+    packet
+}
+
+pub fn write_packet(packet: &DnsPacket) -> Vec<u8> {
+    let mut buffer = BytePacketBuffer::with_max_size(u16::MAX as usize);
+    packet.write(&mut buffer).unwrap();
+    buffer.buf[0..buffer.pos].to_vec()
+}
+
+// A port that is free on 127.0.0.1, and most likely on the rest of 127.0.0.0/8
+pub fn free_port() -> u16 {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    socket.local_addr().unwrap().port()
+}
+
+pub fn a(domain: &str, addr: &str) -> DnsRecord {
+    DnsRecord::A {
+        domain: domain.to_string(),
+        addr: addr.parse().unwrap(),
+        class: QueryClass::IN,
+        ttl: 3600,
+    }
+}
+
+pub fn aaaa(domain: &str, addr: &str) -> DnsRecord {
+    DnsRecord::AAAA {
+        domain: domain.to_string(),
+        addr: addr.parse().unwrap(),
+        class: QueryClass::IN,
+        ttl: 3600,
+    }
+}
+
+pub fn ns(domain: &str, host: &str) -> DnsRecord {
+    DnsRecord::NS {
+        domain: domain.to_string(),
+        host: host.to_string(),
+        class: QueryClass::IN,
+        ttl: 3600,
+    }
+}
+
+pub fn cname(domain: &str, host: &str) -> DnsRecord {
+    DnsRecord::CNAME {
+        domain: domain.to_string(),
+        host: host.to_string(),
+        class: QueryClass::IN,
+        ttl: 3600,
+    }
+}
+
+pub fn dname(domain: &str, target: &str) -> DnsRecord {
+    DnsRecord::DNAME {
+        domain: domain.to_string(),
+        target: target.to_string(),
+        class: QueryClass::IN,
+        ttl: 3600,
+    }
+}
+
+pub enum TestKey {
+    Rsa(RsaKeyPair),
+    Ecdsa(EcdsaKeyPair, u8),
+    Ed25519(Ed25519KeyPair),
+}
+
+impl TestKey {
+    pub fn new(algorithm: u8) -> TestKey {
+        let rng = SystemRandom::new();
+        match algorithm {
+            // ring can't generate RSA keys, so this one is a fixture
+            8 => TestKey::Rsa(
+                RsaKeyPair::from_pkcs8(include_bytes!("../fixtures/rsa_2048.pk8")).unwrap(),
+            ),
+            13 | 14 => {
+                let signing = if algorithm == 13 {
+                    &ECDSA_P256_SHA256_FIXED_SIGNING
+                } else {
+                    &ECDSA_P384_SHA384_FIXED_SIGNING
+                };
+                let pkcs8 = EcdsaKeyPair::generate_pkcs8(signing, &rng).unwrap();
+                let key = EcdsaKeyPair::from_pkcs8(signing, pkcs8.as_ref(), &rng).unwrap();
+                TestKey::Ecdsa(key, algorithm)
+            }
+            15 => {
+                let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+                TestKey::Ed25519(Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap())
+            }
+            _ => panic!("No test keys for algorithm {}", algorithm),
+        }
+    }
+
+    pub fn algorithm(&self) -> u8 {
+        match self {
+            TestKey::Rsa(_) => 8,
+            TestKey::Ecdsa(_, algorithm) => *algorithm,
+            TestKey::Ed25519(_) => 15,
+        }
+    }
+
+    pub fn dnskey(&self, zone: &str) -> DnsRecord {
+        let public_key = match self {
+            TestKey::Rsa(key) => {
+                let components = RsaPublicKeyComponents::<Vec<u8>>::from(key.public());
+                let mut public_key = vec![components.e.len() as u8];
+                public_key.extend(components.e);
+                public_key.extend(components.n);
+                public_key
+            }
+            // Without the uncompressed point tag
+            TestKey::Ecdsa(key, _) => key.public_key().as_ref()[1..].to_vec(),
+            TestKey::Ed25519(key) => key.public_key().as_ref().to_vec(),
+        };
+        DnsRecord::DNSKEY {
+            domain: zone.to_string(),
+            flags: 257,
+            protocol: 3,
+            algorithm: self.algorithm(),
+            public_key,
+            class: QueryClass::IN,
+            ttl: 3600,
+        }
+    }
+
+    pub fn ds(&self, zone: &str) -> DnsRecord {
+        let dnskey = self.dnskey(zone);
+        DnsRecord::DS {
+            domain: zone.to_string(),
+            key_tag: key_tag(&dnskey).unwrap(),
+            algorithm: self.algorithm(),
+            digest_type: 2,
+            digest: ds_digest(&dnskey, 2).unwrap(),
+            class: QueryClass::IN,
+            ttl: 3600,
+        }
+    }
+
+    pub fn sign(&self, data: &[u8]) -> Vec<u8> {
+        let rng = SystemRandom::new();
+        match self {
+            TestKey::Rsa(key) => {
+                let mut signature = vec![0; key.public().modulus_len()];
+                key.sign(&RSA_PKCS1_SHA256, &rng, data, &mut signature)
+                    .unwrap();
+                signature
+            }
+            TestKey::Ecdsa(key, _) => key.sign(&rng, data).unwrap().as_ref().to_vec(),
+            TestKey::Ed25519(key) => key.sign(data).as_ref().to_vec(),
+        }
+    }
+}
+
+pub struct Zone {
+    pub apex: String,
+    pub records: Vec<DnsRecord>,
+}
+
+impl Zone {
+    pub fn new(apex: &str, mut records: Vec<DnsRecord>) -> Zone {
+        records.insert(
+            0,
+            DnsRecord::SOA {
+                domain: apex.to_string(),
+                mname: format!("ns.{}", apex),
+                rname: format!("hostmaster.{}", apex),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 300,
+                class: QueryClass::IN,
+                ttl: 3600,
+            },
+        );
+        Zone {
+            apex: apex.to_string(),
+            records,
+        }
+    }
+
+    // Signs the zone with an NSEC chain
+    pub fn sign(&mut self, key: &TestKey) {
+        self.records.push(key.dnskey(&self.apex));
+        let names = self.names();
+        for (i, name) in names.iter().enumerate() {
+            let mut types = self.types_at(name);
+            types.extend([QueryType::RRSIG, QueryType::NSEC]);
+            self.records.push(DnsRecord::NSEC {
+                domain: name.clone(),
+                next_domain: names[(i + 1) % names.len()].clone(),
+                types,
+                class: QueryClass::IN,
+                ttl: 300,
+            });
+        }
+        self.sign_rrsets(key);
+    }
+
+    // Signs the zone with an NSEC3 chain, leaving unsigned delegations out of it with opt-out
+    pub fn sign_nsec3(&mut self, key: &TestKey, iterations: u16, opt_out: bool) {
+        let salt = vec![0xAB, 0xCD];
+        self.records.push(key.dnskey(&self.apex));
+        self.records.push(DnsRecord::NSEC3PARAM {
+            domain: self.apex.clone(),
+            hash_algorithm: 1,
+            flags: 0,
+            iterations,
+            salt: salt.clone(),
+            class: QueryClass::IN,
+            ttl: 0,
+        });
+        let mut hashed: Vec<(Vec<u8>, String)> = self
+            .names()
+            .into_iter()
+            .filter(|name| !opt_out || !self.is_unsigned_cut(name))
+            .map(|name| (nsec3_hash(&name, &salt, iterations).unwrap(), name))
+            .collect();
+        hashed.sort();
+        for (i, (hash, name)) in hashed.iter().enumerate() {
+            let mut types = self.types_at(name);
+            if !self.is_unsigned_cut(name) {
+                types.push(QueryType::RRSIG);
+            }
+            self.records.push(DnsRecord::NSEC3 {
+                domain: format!("{}.{}", base32hex_encode(hash), self.apex),
+                hash_algorithm: 1,
+                flags: opt_out as u8,
+                iterations,
+                salt: salt.clone(),
+                next_hashed_owner: hashed[(i + 1) % hashed.len()].0.clone(),
+                types,
+                class: QueryClass::IN,
+                ttl: 300,
+            });
+        }
+        self.sign_rrsets(key);
+    }
+
+    fn sign_rrsets(&mut self, key: &TestKey) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32;
+        let dnskey = key.dnskey(&self.apex);
+        let mut rrsets: Vec<(String, QueryType)> = Vec::new();
+        for record in &self.records {
+            let rrset = (record.domain().to_string(), record.qtype());
+            if !rrsets.contains(&rrset) {
+                rrsets.push(rrset);
+            }
+        }
+        for (owner, qtype) in rrsets {
+            // Only the DS and NSEC at a delegation belong to this zone
+            let at_cut = self.cut_for(&owner).is_some();
+            if at_cut && !matches!(qtype, QueryType::DS | QueryType::NSEC) {
+                continue;
+            }
+            let rrset: Vec<DnsRecord> = self
+                .records
+                .iter()
+                .filter(|r| r.domain() == owner && r.qtype() == qtype)
+                .cloned()
+                .collect();
+            let mut rrsig = DnsRecord::RRSIG {
+                domain: owner.clone(),
+                type_covered: qtype,
+                algorithm: key.algorithm(),
+                labels: label_count(&owner),
+                original_ttl: rrset[0].ttl(),
+                expiration: now + 86400,
+                inception: now - 3600,
+                key_tag: key_tag(&dnskey).unwrap(),
+                signer_name: self.apex.clone(),
+                signature: Vec::new(),
+                class: QueryClass::IN,
+                ttl: rrset[0].ttl(),
+            };
+            let data = signed_data(&rrset, &rrsig).unwrap();
+            if let DnsRecord::RRSIG { signature, .. } = &mut rrsig {
+                *signature = key.sign(&data);
+            }
+            self.records.push(rrsig);
+        }
+    }
+
+    // The delegation at or above name, if any
+    fn cut_for(&self, name: &str) -> Option<String> {
+        self.records
+            .iter()
+            .filter(|r| r.qtype() == QueryType::NS && r.domain() != self.apex)
+            .map(|r| r.domain().to_string())
+            .filter(|cut| is_subdomain(name, cut))
+            .min_by_key(|cut| label_count(cut))
+    }
+
+    fn is_unsigned_cut(&self, name: &str) -> bool {
+        self.cut_for(name).as_deref() == Some(name)
+            && !self
+                .records
+                .iter()
+                .any(|r| r.qtype() == QueryType::DS && r.domain() == name)
+    }
+
+    // Owner names in canonical order, without glue below delegations
+    fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for record in &self.records {
+            let name = record.domain().to_string();
+            let glue = self.cut_for(&name).is_some_and(|cut| cut != name);
+            if !glue && !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names.sort_by(|a, b| canonical_cmp(a, b));
+        names
+    }
+
+    fn types_at(&self, name: &str) -> Vec<QueryType> {
+        let mut types: Vec<QueryType> = self
+            .records
+            .iter()
+            .filter(|r| r.domain() == name)
+            .map(DnsRecord::qtype)
+            .collect();
+        types.dedup();
+        types
+    }
+}
+
+// An authoritative server for the zones, answering over UDP and TCP on ip:port
+// Returns the number of queries answered so far
+pub fn serve(ip: impl Into<IpAddr>, port: u16, zones: Vec<Zone>) -> Arc<AtomicUsize> {
+    serve_with_delay(ip, port, zones, Duration::ZERO)
+}
+
+// Like serve, but every UDP answer is held back by delay without blocking other queries
+pub fn serve_with_delay(
+    ip: impl Into<IpAddr>,
+    port: u16,
+    zones: Vec<Zone>,
+    delay: Duration,
+) -> Arc<AtomicUsize> {
+    let ip = ip.into();
+    let zones = Arc::new(zones);
+    let queries = Arc::new(AtomicUsize::new(0));
+    let tcp_queries = queries.clone();
+    let udp_queries = queries.clone();
+    let socket = UdpSocket::bind((ip, port)).unwrap();
+    let listener = TcpListener::bind((ip, port)).unwrap();
+    let tcp_zones = zones.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { break };
+            while let Ok(mut buffer) = read_tcp_message(&mut stream) {
+                let request = DnsPacket::from_buffer(&mut buffer).unwrap();
+                tcp_queries.fetch_add(1, Ordering::SeqCst);
+                let response = write_packet(&answer(&tcp_zones, &request));
+                write_tcp_message(&mut stream, &response).unwrap();
+            }
+        }
+    });
+    thread::spawn(move || loop {
+        let mut buffer = BytePacketBuffer::with_size(4096);
+        let Ok((_, src)) = socket.recv_from(&mut buffer.buf) else {
+            break;
+        };
+        let Ok(request) = DnsPacket::from_buffer(&mut buffer) else {
+            continue;
+        };
+        udp_queries.fetch_add(1, Ordering::SeqCst);
+        let mut response = write_packet(&answer(&zones, &request));
+        if response.len() > request.max_udp_size() {
+            let mut truncated = response_to(&request);
+            truncated.header.truncated_message = true;
+            response = write_packet(&truncated);
+        }
+        let socket = socket.try_clone().unwrap();
+        thread::spawn(move || {
+            thread::sleep(delay);
+            socket.send_to(&response, src).unwrap();
+        });
+    });
+    queries
+}
+
+pub fn answer(zones: &[Zone], request: &DnsPacket) -> DnsPacket {
+    let mut response = response_to(request);
+    let question = &request.questions[0];
+    let qname = question.name.as_str();
+    let qtype = question.qtype;
+    let dnssec_ok = request.dnssec_ok();
+    if request.get_edns().is_some() {
+        response.set_edns(4096, dnssec_ok);
+    }
+
+    // DS records are served from the parent side of a zone cut
+    let enclosing = |name: &str| {
+        zones
+            .iter()
+            .filter(|z| is_subdomain(name, &z.apex))
+            .max_by_key(|z| label_count(&z.apex))
+    };
+    let mut zone = enclosing(qname);
+    if let Some(z) = zone {
+        if qtype == QueryType::DS && z.apex == qname && !qname.is_empty() {
+            let parent = qname.split_once('.').map_or("", |(_, p)| p);
+            zone = enclosing(parent).or(zone);
+        }
+    }
+    let Some(zone) = zone else {
+        response.header.rescode = ResultCode::REFUSED;
+        return finish(response);
+    };
+    let records = |name: &str, qtype: QueryType| -> Vec<DnsRecord> {
+        let mut found: Vec<DnsRecord> = zone
+            .records
+            .iter()
+            .filter(|r| r.domain() == name && r.qtype() == qtype)
+            .cloned()
+            .collect();
+        if dnssec_ok && !found.is_empty() {
+            found.extend(zone.records.iter().filter(|r| {
+                r.domain() == name
+                    && matches!(r, DnsRecord::RRSIG { type_covered, .. } if *type_covered == qtype)
+            }).cloned());
+        }
+        found
+    };
+    let denial = || -> Vec<DnsRecord> {
+        if !dnssec_ok {
+            return Vec::new();
+        }
+        let mut found: Vec<DnsRecord> = zone
+            .records
+            .iter()
+            .filter(|r| matches!(r, DnsRecord::NSEC { .. } | DnsRecord::NSEC3 { .. }))
+            .cloned()
+            .collect();
+        found.extend(
+            zone.records
+                .iter()
+                .filter(|r| {
+                    matches!(r, DnsRecord::RRSIG { type_covered, .. }
+                if matches!(type_covered, QueryType::NSEC | QueryType::NSEC3))
+                })
+                .cloned(),
+        );
+        found
+    };
+    response.header.authoritative_answer = true;
+
+    if let Some(cut) = zone.cut_for(qname) {
+        if !(qtype == QueryType::DS && cut == qname) {
+            response.header.authoritative_answer = false;
+            response.authorities = records(&cut, QueryType::NS);
+            let ds = records(&cut, QueryType::DS);
+            if ds.is_empty() {
+                response.authorities.extend(denial());
+            } else {
+                response.authorities.extend(ds);
+            }
+            for record in records(&cut, QueryType::NS) {
+                if let DnsRecord::NS { host, .. } = record {
+                    for zone in zones {
+                        response.resources.extend(
+                            zone.records
+                                .iter()
+                                .filter(|r| {
+                                    r.domain() == host
+                                        && matches!(r.qtype(), QueryType::A | QueryType::AAAA)
+                                })
+                                .cloned(),
+                        );
+                    }
+                }
+            }
+            return finish(response);
+        }
+    }
+
+    // Only the DNAME is sent back, so the resolver has to synthesize the CNAME itself
+    let redirect = zone.records.iter().find(|r| {
+        r.qtype() == QueryType::DNAME && r.domain() != qname && is_subdomain(qname, r.domain())
+    });
+    if let Some(dname) = redirect {
+        response.answers = records(dname.domain(), QueryType::DNAME);
+        return finish(response);
+    }
+
+    let answers = records(qname, qtype);
+    if !answers.is_empty() {
+        response.answers = answers;
+        return finish(response);
+    }
+    let cnames = records(qname, QueryType::CNAME);
+    if !cnames.is_empty() {
+        response.answers = cnames;
+        return finish(response);
+    }
+    let exists = zone.records.iter().any(|r| is_subdomain(r.domain(), qname));
+    if !exists {
+        // Wildcards match at the closest existing ancestor
+        let mut encloser = qname;
+        while let Some((_, parent)) = encloser.split_once('.') {
+            encloser = parent;
+            if zone
+                .records
+                .iter()
+                .any(|r| is_subdomain(r.domain(), encloser))
+            {
+                break;
+            }
+        }
+        let wildcard = format!("*.{}", encloser);
+        let expanded = records(&wildcard, qtype);
+        if !expanded.is_empty() {
+            response.answers = expanded
+                .into_iter()
+                .map(|record| rename(record, qname))
+                .collect();
+            response.authorities = denial();
+            return finish(response);
+        }
+        response.header.rescode = ResultCode::NXDOMAIN;
+    }
+    response.authorities = records(&zone.apex, QueryType::SOA);
+    response.authorities.extend(denial());
+    finish(response)
+}
+
+// The record under another owner name, for wildcard expansion
+fn rename(record: DnsRecord, name: &str) -> DnsRecord {
+    let mut original = BytePacketBuffer::with_max_size(u16::MAX as usize);
+    original.compression = false;
+    record.write(&mut original).unwrap();
+    let owner_len = canonical_name(record.domain()).unwrap().len();
+    let mut renamed = BytePacketBuffer::with_max_size(u16::MAX as usize);
+    renamed.write_qname(name).unwrap();
+    for byte in &original.buf[owner_len..original.pos] {
+        renamed.write_u8(*byte).unwrap();
+    }
+    renamed.seek(0).unwrap();
+    DnsRecord::read(&mut renamed).unwrap()
+}
+
+fn finish(mut response: DnsPacket) -> DnsPacket {
+    response.header.answers = response.answers.len() as u16;
+    response.header.authoritative_entries = response.authorities.len() as u16;
+    response.header.resource_entries = response.resources.len() as u16;
+    response
+}
@@ -1,8 +1,9 @@
 use rdns_resolver_rs::{
     byte_packet_buffer::BytePacketBuffer, dns_header::DnsHeader, dns_packet::DnsPacket,
-    dns_question::DnsQuestion, dns_record::DnsRecord, query_type::QueryType,
+    dns_question::DnsQuestion, dns_record::DnsRecord, query_class::QueryClass,
+    query_type::QueryType, res_code::ResultCode,
 };
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 #[test]
 fn test_dns_packet_creation() {
@@ -17,8 +18,10 @@ fn test_dns_packet_creation() {
 fn test_dns_packet_from_buffer() {
     let mut buffer = BytePacketBuffer::default();
     // Simulate writing a header to buffer
-    let mut header = DnsHeader::default();
-    header.questions = 1;
+    let header = DnsHeader {
+        questions: 1,
+        ..Default::default()
+    };
     header.write(&mut buffer).unwrap();
 
     // Simulate writing a question to buffer
@@ -53,6 +56,7 @@ fn test_get_random_a() {
     packet.answers.push(DnsRecord::A {
         domain: "example.com".to_string(),
         addr: Ipv4Addr::new(192, 168, 1, 1),
+        class: QueryClass::IN,
         ttl: 60,
     });
 
@@ -66,16 +70,18 @@ fn test_get_resolved_ns() {
     packet.authorities.push(DnsRecord::NS {
         domain: "example.com".to_string(),
         host: "ns1.example.com".to_string(),
+        class: QueryClass::IN,
         ttl: 60,
     });
     packet.resources.push(DnsRecord::A {
         domain: "ns1.example.com".to_string(),
         addr: Ipv4Addr::new(8, 8, 8, 8),
+        class: QueryClass::IN,
         ttl: 60,
     });
 
-    let result = packet.get_resolved_ns("example.com");
-    assert_eq!(result, Some(Ipv4Addr::new(8, 8, 8, 8)));
+    let result = packet.get_resolved_ns("example.com", "com");
+    assert_eq!(result, Some(IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))));
 }
 
 #[test]
@@ -84,9 +90,188 @@ fn test_get_unresolved_ns() {
     packet.authorities.push(DnsRecord::NS {
         domain: "example.com".to_string(),
         host: "ns1.example.com".to_string(),
+        class: QueryClass::IN,
         ttl: 60,
     });
 
-    let result = packet.get_unresolved_ns("example.com");
+    let result = packet.get_unresolved_ns("example.com", "com");
     assert_eq!(result, Some("ns1.example.com"));
 }
+
+#[test]
+fn test_edns() {
+    let mut packet = DnsPacket::default();
+    assert!(packet.get_edns().is_none());
+    assert_eq!(packet.max_udp_size(), 512);
+    assert!(!packet.dnssec_ok());
+
+    packet.set_edns(4096, true);
+    assert!(packet.get_edns().is_some());
+    assert_eq!(packet.header.resource_entries, 1);
+    assert_eq!(packet.max_udp_size(), 4096);
+    assert!(packet.dnssec_ok());
+}
+
+#[test]
+fn test_edns_extended_rcode() {
+    let mut buffer = BytePacketBuffer::default();
+    let mut packet = DnsPacket::default();
+    packet.header.rescode = ResultCode::BADVERS;
+    packet.set_edns(1232, false);
+    packet.write(&mut buffer).unwrap();
+
+    buffer.seek(0).unwrap();
+    let parsed = DnsPacket::from_buffer(&mut buffer).unwrap();
+    assert_eq!(parsed.header.rescode, ResultCode::BADVERS);
+    assert_eq!(parsed.max_udp_size(), 1232);
+
+    // BADCOOKIE isn't one we know, but mustn't be mistaken for NOERROR
+    let mut buffer = BytePacketBuffer::default();
+    packet.header.rescode = ResultCode::UNKNOWN(23);
+    packet.set_edns(1232, false);
+    packet.write(&mut buffer).unwrap();
+
+    buffer.seek(0).unwrap();
+    let parsed = DnsPacket::from_buffer(&mut buffer).unwrap();
+    assert_eq!(parsed.header.rescode, ResultCode::UNKNOWN(23));
+}
+
+#[test]
+fn test_unknown_record_passthrough() {
+    let mut buffer = BytePacketBuffer::default();
+    let mut packet = DnsPacket::default();
+    packet.answers.push(DnsRecord::UNKNOWN {
+        domain: "example.com".to_string(),
+        qtype: 99,
+        data: b"\x0bv=spf1 -all".to_vec(),
+        class: QueryClass::IN,
+        ttl: 60,
+    });
+    packet.answers.push(DnsRecord::A {
+        domain: "example.com".to_string(),
+        addr: Ipv4Addr::new(192, 168, 1, 1),
+        class: QueryClass::IN,
+        ttl: 60,
+    });
+    packet.header.answers = 2;
+    packet.write(&mut buffer).unwrap();
+
+    buffer.seek(0).unwrap();
+    let parsed = DnsPacket::from_buffer(&mut buffer).unwrap();
+    assert_eq!(parsed.answers, packet.answers);
+}
+
+#[test]
+fn test_get_all_ns() {
+    let mut packet = DnsPacket::default();
+    for host in ["ns1.example.com", "ns2.example.com", "ns.example.net"] {
+        packet.authorities.push(DnsRecord::NS {
+            domain: "example.com".to_string(),
+            host: host.to_string(),
+            class: QueryClass::IN,
+            ttl: 60,
+        });
+    }
+    for (host, addr) in [
+        ("ns1.example.com", Ipv4Addr::new(192, 0, 2, 1)),
+        ("ns2.example.com", Ipv4Addr::new(192, 0, 2, 2)),
+    ] {
+        packet.resources.push(DnsRecord::A {
+            domain: host.to_string(),
+            addr,
+            class: QueryClass::IN,
+            ttl: 60,
+        });
+    }
+    packet.resources.push(DnsRecord::AAAA {
+        domain: "ns2.example.com".to_string(),
+        addr: "2001:db8::2".parse::<Ipv6Addr>().unwrap(),
+        class: QueryClass::IN,
+        ttl: 60,
+    });
+
+    assert_eq!(
+        packet.get_all_resolved_ns("www.example.com", "com"),
+        vec![
+            IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)),
+            "2001:db8::2".parse::<IpAddr>().unwrap(),
+        ]
+    );
+    assert_eq!(
+        packet.get_all_unresolved_ns("www.example.com", "com"),
+        vec![("example.com", "ns.example.net")]
+    );
+}
+
+#[test]
+fn test_get_ns_matches_whole_labels() {
+    let mut packet = DnsPacket::default();
+    packet.authorities.push(DnsRecord::NS {
+        domain: "example.com".to_string(),
+        host: "ns1.example.com".to_string(),
+        class: QueryClass::IN,
+        ttl: 60,
+    });
+    packet.resources.push(DnsRecord::A {
+        domain: "ns1.example.com".to_string(),
+        addr: Ipv4Addr::new(192, 0, 2, 1),
+        class: QueryClass::IN,
+        ttl: 60,
+    });
+
+    assert_eq!(packet.get_resolved_ns("badexample.com", "com"), None);
+    assert_eq!(packet.get_unresolved_ns("www.badexample.com", "com"), None);
+    assert_eq!(
+        packet.get_resolved_ns("WWW.Example.com", "com"),
+        Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+    );
+    // Only servers for a zone above can delegate it
+    assert_eq!(
+        packet.get_resolved_ns("www.example.com", "example.com"),
+        None
+    );
+    assert_eq!(packet.get_resolved_ns("www.example.com", "org"), None);
+}
+
+#[test]
+fn test_glue_must_be_in_bailiwick() {
+    let mut packet = DnsPacket::default();
+    for host in ["ns1.example.com", "ns.example.net"] {
+        packet.authorities.push(DnsRecord::NS {
+            domain: "example.com".to_string(),
+            host: host.to_string(),
+            class: QueryClass::IN,
+            ttl: 60,
+        });
+    }
+    for (host, addr) in [
+        ("ns1.example.com", Ipv4Addr::new(192, 0, 2, 1)),
+        ("ns.example.net", Ipv4Addr::new(192, 0, 2, 2)),
+        ("www.example.org", Ipv4Addr::new(192, 0, 2, 3)),
+    ] {
+        packet.resources.push(DnsRecord::A {
+            domain: host.to_string(),
+            addr,
+            class: QueryClass::IN,
+            ttl: 60,
+        });
+    }
+
+    // The com servers can't vouch for an address in net, nor for names that aren't servers
+    assert_eq!(
+        packet.get_all_resolved_ns("www.example.com", "com"),
+        vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]
+    );
+    assert_eq!(
+        packet.get_all_unresolved_ns("www.example.com", "com"),
+        vec![("example.com", "ns.example.net")]
+    );
+    let glue: Vec<&str> = packet
+        .get_glue("www.example.com", "com")
+        .map(|record| record.domain())
+        .collect();
+    assert_eq!(glue, vec!["ns1.example.com"]);
+    // The root servers can
+    assert_eq!(packet.get_all_resolved_ns("www.example.com", "").len(), 2);
+}
@@ -1,5 +1,6 @@
 use rdns_resolver_rs::{
-    byte_packet_buffer::BytePacketBuffer, dns_question::DnsQuestion, query_type::QueryType,
+    byte_packet_buffer::BytePacketBuffer, dns_question::DnsQuestion, query_class::QueryClass,
+    query_type::QueryType,
 };
 
 #[cfg(test)]
@@ -18,12 +19,8 @@ mod tests {
             7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0, 0, 1, 0, 1,
         ];
         assert_eq!(buffer.pos, expected.len());
-        for i in 0..expected.len() {
-            assert_eq!(expected[i], buffer.buf[i]);
-        }
-        for i in expected.len()..512 {
-            assert_eq!(0, buffer.buf[i]);
-        }
+        assert_eq!(&expected[..], &buffer.buf[..expected.len()]);
+        assert!(buffer.buf[expected.len()..].iter().all(|&b| b == 0));
     }
 
     #[test]
@@ -40,4 +37,19 @@ mod tests {
         assert_eq!(question.name, "example.com");
         assert_eq!(question.qtype, QueryType::A);
     }
+
+    #[test]
+    fn test_read_class() {
+        let mut buffer = BytePacketBuffer::default();
+        let mut question = DnsQuestion::new("version.bind".to_string(), QueryType::TXT);
+        question.qclass = QueryClass::CH;
+        question.write(&mut buffer).unwrap();
+
+        buffer.seek(0).unwrap();
+        let mut resp = DnsQuestion::new("".to_string(), QueryType::A);
+        resp.read(&mut buffer).unwrap();
+
+        assert_eq!(question, resp);
+        assert_eq!(resp.qclass, QueryClass::CH);
+    }
 }
@@ -1,4 +1,7 @@
-use rdns_resolver_rs::{byte_packet_buffer::BytePacketBuffer, dns_record::DnsRecord};
+use rdns_resolver_rs::{
+    byte_packet_buffer::BytePacketBuffer, dns_record::DnsRecord, edns_option::EdnsOption,
+    query_class::QueryClass, query_type::QueryType, svc_param::SvcParam,
+};
 use std::net::{Ipv4Addr, Ipv6Addr};
 
 #[test]
@@ -7,6 +10,7 @@ fn test_dns_record_a() {
     let record = DnsRecord::A {
         domain: "example.com".to_string(),
         addr: Ipv4Addr::new(127, 0, 0, 1),
+        class: QueryClass::IN,
         ttl: 3600,
     };
     record.write(&mut buffer).unwrap();
@@ -23,6 +27,7 @@ fn test_dns_record_aaaa() {
     let record = DnsRecord::AAAA {
         domain: "example.com".to_string(),
         addr: Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 1),
+        class: QueryClass::IN,
         ttl: 3600,
     };
     record.write(&mut buffer).unwrap();
@@ -39,6 +44,7 @@ fn test_dns_record_ns() {
     let record = DnsRecord::NS {
         domain: "example.com".to_string(),
         host: "ns1.example.com".to_string(),
+        class: QueryClass::IN,
         ttl: 3600,
     };
     record.write(&mut buffer).unwrap();
@@ -55,6 +61,7 @@ fn test_dns_record_cname() {
     let record = DnsRecord::CNAME {
         domain: "www.example.com".to_string(),
         host: "example.com".to_string(),
+        class: QueryClass::IN,
         ttl: 3600,
     };
     record.write(&mut buffer).unwrap();
@@ -72,6 +79,7 @@ fn test_dns_record_mx() {
         domain: "example.com".to_string(),
         priority: 10,
         host: "mail.example.com".to_string(),
+        class: QueryClass::IN,
         ttl: 3600,
     };
     record.write(&mut buffer).unwrap();
@@ -88,7 +96,8 @@ fn test_dns_record_unknown() {
     let record = DnsRecord::UNKNOWN {
         domain: "example.com".to_string(),
         qtype: 99,
-        data_len: 4,
+        data: vec![10, 0, 0, 1],
+        class: QueryClass::IN,
         ttl: 3600,
     };
     record.write(&mut buffer).unwrap();
@@ -98,3 +107,580 @@ fn test_dns_record_unknown() {
 
     assert_eq!(record, parsed_record);
 }
+
+#[test]
+fn test_dns_record_opt() {
+    let mut buffer = BytePacketBuffer::default();
+    let record = DnsRecord::OPT {
+        packet_len: 1232,
+        extended_rcode: 1,
+        version: 0,
+        dnssec_ok: true,
+        options: vec![EdnsOption::new(10, vec![1, 2, 3, 4, 5, 6, 7, 8])],
+    };
+    record.write(&mut buffer).unwrap();
+
+    buffer.seek(0).unwrap();
+    let parsed_record = DnsRecord::read(&mut buffer).unwrap();
+
+    assert_eq!(record, parsed_record);
+}
+
+#[test]
+fn test_dns_record_class() {
+    let mut buffer = BytePacketBuffer::default();
+    let record = DnsRecord::A {
+        domain: "example.com".to_string(),
+        addr: Ipv4Addr::new(127, 0, 0, 1),
+        class: QueryClass::HS,
+        ttl: 3600,
+    };
+    record.write(&mut buffer).unwrap();
+
+    buffer.seek(0).unwrap();
+    let parsed_record = DnsRecord::read(&mut buffer).unwrap();
+
+    assert_eq!(record, parsed_record);
+}
+
+#[test]
+fn test_dns_record_unknown_generic_rdata() {
+    let record = DnsRecord::from_generic_rdata(
+        "example.com".to_string(),
+        99,
+        QueryClass::IN,
+        3600,
+        "\\# 4 0A00 0001",
+    )
+    .unwrap();
+    assert_eq!(
+        record,
+        DnsRecord::UNKNOWN {
+            domain: "example.com".to_string(),
+            qtype: 99,
+            data: vec![10, 0, 0, 1],
+            class: QueryClass::IN,
+            ttl: 3600,
+        }
+    );
+    assert_eq!(record.to_generic_rdata().unwrap(), "\\# 4 0a000001");
+
+    let empty =
+        DnsRecord::from_generic_rdata("example.com".to_string(), 99, QueryClass::IN, 0, "\\# 0")
+            .unwrap();
+    assert_eq!(empty.to_generic_rdata().unwrap(), "\\# 0");
+}
+
+#[test]
+fn test_dns_record_unknown_generic_rdata_invalid() {
+    let parse = |text| {
+        DnsRecord::from_generic_rdata("example.com".to_string(), 99, QueryClass::IN, 0, text)
+    };
+    assert!(parse("4 0a000001").is_err());
+    assert!(parse("\\# 3 0a000001").is_err());
+    assert!(parse("\\# 2 0a0").is_err());
+    assert!(parse("\\# 1 zz").is_err());
+    assert!(parse("\\#").is_err());
+}
+
+#[test]
+fn test_dns_record_soa() {
+    let mut buffer = BytePacketBuffer::default();
+    let record = DnsRecord::SOA {
+        domain: "example.com".to_string(),
+        mname: "ns1.example.com".to_string(),
+        rname: "hostmaster.example.com".to_string(),
+        serial: 2024010101,
+        refresh: 7200,
+        retry: 3600,
+        expire: 1209600,
+        minimum: 300,
+        class: QueryClass::IN,
+        ttl: 3600,
+    };
+    record.write(&mut buffer).unwrap();
+
+    buffer.seek(0).unwrap();
+    let parsed_record = DnsRecord::read(&mut buffer).unwrap();
+
+    assert_eq!(record, parsed_record);
+}
+
+#[test]
+fn test_dns_record_mx_compressed() {
+    let mut buffer = BytePacketBuffer::default();
+    let first = DnsRecord::MX {
+        domain: "example.com".to_string(),
+        priority: 10,
+        host: "mail.example.com".to_string(),
+        class: QueryClass::IN,
+        ttl: 3600,
+    };
+    let second = DnsRecord::MX {
+        domain: "example.com".to_string(),
+        priority: 20,
+        host: "mail.example.com".to_string(),
+        class: QueryClass::IN,
+        ttl: 3600,
+    };
+    first.write(&mut buffer).unwrap();
+    second.write(&mut buffer).unwrap();
+
+    buffer.seek(0).unwrap();
+    assert_eq!(DnsRecord::read(&mut buffer).unwrap(), first);
+    assert_eq!(DnsRecord::read(&mut buffer).unwrap(), second);
+}
+
+#[test]
+fn test_dns_record_ptr() {
+    let mut buffer = BytePacketBuffer::default();
+    let record = DnsRecord::PTR {
+        domain: "1.0.0.127.in-addr.arpa".to_string(),
+        host: "localhost".to_string(),
+        class: QueryClass::IN,
+        ttl: 3600,
+    };
+    record.write(&mut buffer).unwrap();
+
+    buffer.seek(0).unwrap();
+    let parsed_record = DnsRecord::read(&mut buffer).unwrap();
+
+    assert_eq!(record, parsed_record);
+}
+
+#[test]
+fn test_dns_record_txt() {
+    let mut buffer = BytePacketBuffer::default();
+    let record = DnsRecord::TXT {
+        domain: "example.com".to_string(),
+        data: vec![
+            b"v=spf1 include:_spf.example.com -all".to_vec(),
+            vec![],
+            vec![0xff, 0x00, 0x7f],
+        ],
+        class: QueryClass::IN,
+        ttl: 3600,
+    };
+    record.write(&mut buffer).unwrap();
+
+    buffer.seek(0).unwrap();
+    let parsed_record = DnsRecord::read(&mut buffer).unwrap();
+
+    assert_eq!(record, parsed_record);
+}
+
+#[test]
+fn test_dns_record_txt_string_too_long() {
+    let mut buffer = BytePacketBuffer::with_max_size(1024);
+    let record = DnsRecord::TXT {
+        domain: "example.com".to_string(),
+        data: vec![vec![b'a'; 256]],
+        class: QueryClass::IN,
+        ttl: 3600,
+    };
+
+    assert!(record.write(&mut buffer).is_err());
+}
+
+#[test]
+fn test_dns_record_srv() {
+    let mut buffer = BytePacketBuffer::default();
+    let record = DnsRecord::SRV {
+        domain: "_sip._tcp.example.com".to_string(),
+        priority: 10,
+        weight: 60,
+        port: 5060,
+        host: "example.com".to_string(),
+        class: QueryClass::IN,
+        ttl: 3600,
+    };
+    buffer.write_qname("example.com").unwrap();
+    let start = buffer.pos;
+    record.write(&mut buffer).unwrap();
+
+    // The target is written out in full even though it was seen before
+    let target = [
+        7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+    ];
+    assert_eq!(buffer.get_range(buffer.pos - 13, 13).unwrap(), &target);
+
+    buffer.seek(start).unwrap();
+    let parsed_record = DnsRecord::read(&mut buffer).unwrap();
+
+    assert_eq!(record, parsed_record);
+}
+
+#[test]
+fn test_dns_record_dname() {
+    let mut buffer = BytePacketBuffer::default();
+    let record = DnsRecord::DNAME {
+        domain: "old.example.com".to_string(),
+        target: "example.com".to_string(),
+        class: QueryClass::IN,
+        ttl: 3600,
+    };
+    buffer.write_qname("example.com").unwrap();
+    let start = buffer.pos;
+    record.write(&mut buffer).unwrap();
+
+    // Like SRV targets, DNAME targets are never compressed
+    let target = [
+        7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+    ];
+    assert_eq!(buffer.get_range(buffer.pos - 13, 13).unwrap(), &target);
+
+    buffer.seek(start).unwrap();
+    let parsed_record = DnsRecord::read(&mut buffer).unwrap();
+
+    assert_eq!(record, parsed_record);
+}
+
+#[test]
+fn test_dns_record_caa() {
+    let mut buffer = BytePacketBuffer::default();
+    let record = DnsRecord::CAA {
+        domain: "example.com".to_string(),
+        flags: 128,
+        tag: "issue".to_string(),
+        value: b"letsencrypt.org".to_vec(),
+        class: QueryClass::IN,
+        ttl: 3600,
+    };
+    record.write(&mut buffer).unwrap();
+
+    buffer.seek(0).unwrap();
+    let parsed_record = DnsRecord::read(&mut buffer).unwrap();
+
+    assert_eq!(record, parsed_record);
+}
+
+#[test]
+fn test_dns_record_caa_empty_tag() {
+    let mut buffer = BytePacketBuffer::default();
+    let record = DnsRecord::CAA {
+        domain: "example.com".to_string(),
+        flags: 0,
+        tag: "a".to_string(),
+        value: b"b".to_vec(),
+        class: QueryClass::IN,
+        ttl: 3600,
+    };
+    record.write(&mut buffer).unwrap();
+
+    // An empty tag can't be written, so clear the tag length by hand
+    buffer.set(buffer.pos - 3, 0).unwrap();
+    buffer.seek(0).unwrap();
+
+    assert!(DnsRecord::read(&mut buffer).is_err());
+}
+
+// Owner name, type, class and ttl followed by the given rdata
+fn record_wire(qtype: u16, rdata: &[u8]) -> Vec<u8> {
+    let mut wire = vec![
+        7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+    ];
+    wire.extend_from_slice(&qtype.to_be_bytes());
+    wire.extend_from_slice(&[0, 1, 0, 0, 0x01, 0x2c]);
+    wire.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    wire.extend_from_slice(rdata);
+    wire
+}
+
+fn assert_wire_round_trip(wire: &[u8], record: &DnsRecord) {
+    let mut buffer = BytePacketBuffer::default();
+    for &byte in wire {
+        buffer.write_u8(byte).unwrap();
+    }
+    buffer.seek(0).unwrap();
+    assert_eq!(&DnsRecord::read(&mut buffer).unwrap(), record);
+
+    let mut buffer = BytePacketBuffer::default();
+    record.write(&mut buffer).unwrap();
+    assert_eq!(&buffer.buf[0..buffer.pos], wire);
+}
+
+// Test vectors from RFC 9460 Appendix D
+#[test]
+fn test_dns_record_https_alias_mode() {
+    let rdata = [
+        0x00, 0x00, 0x03, b'f', b'o', b'o', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03,
+        b'c', b'o', b'm', 0x00,
+    ];
+    let record = DnsRecord::HTTPS {
+        domain: "example.com".to_string(),
+        priority: 0,
+        target: "foo.example.com".to_string(),
+        params: vec![],
+        class: QueryClass::IN,
+        ttl: 300,
+    };
+    // The target must not be compressed against the owner name
+    assert_wire_round_trip(&record_wire(65, &rdata), &record);
+}
+
+#[test]
+fn test_dns_record_svcb_root_target() {
+    let record = DnsRecord::SVCB {
+        domain: "example.com".to_string(),
+        priority: 1,
+        target: "".to_string(),
+        params: vec![],
+        class: QueryClass::IN,
+        ttl: 300,
+    };
+    assert_wire_round_trip(&record_wire(64, &[0x00, 0x01, 0x00]), &record);
+}
+
+#[test]
+fn test_dns_record_svcb_port() {
+    let rdata = [
+        0x00, 0x10, 0x03, b'f', b'o', b'o', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03,
+        b'c', b'o', b'm', 0x00, 0x00, 0x03, 0x00, 0x02, 0x00, 0x35,
+    ];
+    let record = DnsRecord::SVCB {
+        domain: "example.com".to_string(),
+        priority: 16,
+        target: "foo.example.com".to_string(),
+        params: vec![SvcParam::Port(53)],
+        class: QueryClass::IN,
+        ttl: 300,
+    };
+    assert_wire_round_trip(&record_wire(64, &rdata), &record);
+}
+
+#[test]
+fn test_dns_record_svcb_unknown_key() {
+    let rdata = [
+        0x00, 0x01, 0x03, b'f', b'o', b'o', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03,
+        b'c', b'o', b'm', 0x00, 0x02, 0x9b, 0x00, 0x09, b'h', b'e', b'l', b'l', b'o', 0xd2, b'q',
+        b'o', b'o',
+    ];
+    let record = DnsRecord::SVCB {
+        domain: "example.com".to_string(),
+        priority: 1,
+        target: "foo.example.com".to_string(),
+        params: vec![SvcParam::Unknown {
+            key: 667,
+            value: b"hello\xd2qoo".to_vec(),
+        }],
+        class: QueryClass::IN,
+        ttl: 300,
+    };
+    assert_wire_round_trip(&record_wire(64, &rdata), &record);
+}
+
+#[test]
+fn test_dns_record_svcb_ipv6hint() {
+    let rdata = [
+        0x00, 0x01, 0x03, b'f', b'o', b'o', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03,
+        b'c', b'o', b'm', 0x00, 0x00, 0x06, 0x00, 0x20, 0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x53, 0x00, 0x01,
+    ];
+    let record = DnsRecord::SVCB {
+        domain: "example.com".to_string(),
+        priority: 1,
+        target: "foo.example.com".to_string(),
+        params: vec![SvcParam::Ipv6Hint(vec![
+            "2001:db8::1".parse().unwrap(),
+            "2001:db8::53:1".parse().unwrap(),
+        ])],
+        class: QueryClass::IN,
+        ttl: 300,
+    };
+    assert_wire_round_trip(&record_wire(64, &rdata), &record);
+}
+
+#[test]
+fn test_dns_record_svcb_mandatory_alpn_ipv4hint() {
+    let rdata = [
+        0x00, 0x10, 0x03, b'f', b'o', b'o', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03,
+        b'o', b'r', b'g', 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x01, 0x00, 0x04, 0x00, 0x01, 0x00,
+        0x09, 0x02, b'h', b'2', 0x05, b'h', b'3', b'-', b'1', b'9', 0x00, 0x04, 0x00, 0x04, 0xc0,
+        0x00, 0x02, 0x01,
+    ];
+    let record = DnsRecord::SVCB {
+        domain: "example.com".to_string(),
+        priority: 16,
+        target: "foo.example.org".to_string(),
+        params: vec![
+            SvcParam::Mandatory(vec![1, 4]),
+            SvcParam::Alpn(vec![b"h2".to_vec(), b"h3-19".to_vec()]),
+            SvcParam::Ipv4Hint(vec![Ipv4Addr::new(192, 0, 2, 1)]),
+        ],
+        class: QueryClass::IN,
+        ttl: 300,
+    };
+    assert_wire_round_trip(&record_wire(64, &rdata), &record);
+}
+
+#[test]
+fn test_dns_record_svcb_escaped_alpn() {
+    let rdata = [
+        0x00, 0x10, 0x03, b'f', b'o', b'o', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03,
+        b'o', b'r', b'g', 0x00, 0x00, 0x01, 0x00, 0x0c, 0x08, b'f', b'\\', b'o', b'o', b',', b'b',
+        b'a', b'r', 0x02, b'h', b'2',
+    ];
+    let record = DnsRecord::SVCB {
+        domain: "example.com".to_string(),
+        priority: 16,
+        target: "foo.example.org".to_string(),
+        params: vec![SvcParam::Alpn(vec![b"f\\oo,bar".to_vec(), b"h2".to_vec()])],
+        class: QueryClass::IN,
+        ttl: 300,
+    };
+    assert_wire_round_trip(&record_wire(64, &rdata), &record);
+}
+
+#[test]
+fn test_dns_record_ds() {
+    let mut buffer = BytePacketBuffer::default();
+    let record = DnsRecord::DS {
+        domain: "example.com".to_string(),
+        key_tag: 370,
+        algorithm: 13,
+        digest_type: 2,
+        digest: vec![0xab; 32],
+        class: QueryClass::IN,
+        ttl: 86400,
+    };
+    record.write(&mut buffer).unwrap();
+
+    buffer.seek(0).unwrap();
+    let parsed_record = DnsRecord::read(&mut buffer).unwrap();
+
+    assert_eq!(record, parsed_record);
+}
+
+#[test]
+fn test_dns_record_dnskey() {
+    let mut buffer = BytePacketBuffer::default();
+    let record = DnsRecord::DNSKEY {
+        domain: "example.com".to_string(),
+        flags: 257,
+        protocol: 3,
+        algorithm: 15,
+        public_key: (0..32).collect(),
+        class: QueryClass::IN,
+        ttl: 3600,
+    };
+    record.write(&mut buffer).unwrap();
+
+    buffer.seek(0).unwrap();
+    let parsed_record = DnsRecord::read(&mut buffer).unwrap();
+
+    assert_eq!(record, parsed_record);
+}
+
+#[test]
+fn test_dns_record_rrsig() {
+    let mut buffer = BytePacketBuffer::default();
+    let record = DnsRecord::RRSIG {
+        domain: "example.com".to_string(),
+        type_covered: QueryType::A,
+        algorithm: 13,
+        labels: 2,
+        original_ttl: 3600,
+        expiration: 1700000000,
+        inception: 1690000000,
+        key_tag: 370,
+        signer_name: "example.com".to_string(),
+        signature: vec![0x5a; 64],
+        class: QueryClass::IN,
+        ttl: 3600,
+    };
+    record.write(&mut buffer).unwrap();
+
+    // The signer name is written out in full even though it matches the owner
+    let signer = [
+        7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+    ];
+    assert_eq!(buffer.get_range(buffer.pos - 64 - 13, 13).unwrap(), &signer);
+
+    buffer.seek(0).unwrap();
+    let parsed_record = DnsRecord::read(&mut buffer).unwrap();
+
+    assert_eq!(record, parsed_record);
+}
+
+#[test]
+fn test_dns_record_nsec() {
+    let mut buffer = BytePacketBuffer::default();
+    let record = DnsRecord::NSEC {
+        domain: "alfa.example.com".to_string(),
+        next_domain: "host.example.com".to_string(),
+        types: vec![
+            QueryType::A,
+            QueryType::MX,
+            QueryType::RRSIG,
+            QueryType::NSEC,
+            QueryType::UNKNOWN(1234),
+        ],
+        class: QueryClass::IN,
+        ttl: 86400,
+    };
+    record.write(&mut buffer).unwrap();
+
+    // Type bitmap example from RFC 4034 section 4.3
+    let mut bitmap = vec![0x00, 0x06, 0x40, 0x01, 0x00, 0x00, 0x00, 0x03, 0x04, 0x1b];
+    bitmap.extend_from_slice(&[0; 26]);
+    bitmap.push(0x20);
+    assert_eq!(
+        buffer
+            .get_range(buffer.pos - bitmap.len(), bitmap.len())
+            .unwrap(),
+        &bitmap[..]
+    );
+
+    buffer.seek(0).unwrap();
+    let parsed_record = DnsRecord::read(&mut buffer).unwrap();
+
+    assert_eq!(record, parsed_record);
+}
+
+#[test]
+fn test_dns_record_nsec3() {
+    let mut buffer = BytePacketBuffer::default();
+    let record = DnsRecord::NSEC3 {
+        domain: "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom.example".to_string(),
+        hash_algorithm: 1,
+        flags: 1,
+        iterations: 12,
+        salt: vec![0xaa, 0xbb, 0xcc, 0xdd],
+        next_hashed_owner: vec![0x11; 20],
+        types: vec![
+            QueryType::NS,
+            QueryType::SOA,
+            QueryType::RRSIG,
+            QueryType::DNSKEY,
+        ],
+        class: QueryClass::IN,
+        ttl: 3600,
+    };
+    record.write(&mut buffer).unwrap();
+
+    buffer.seek(0).unwrap();
+    let parsed_record = DnsRecord::read(&mut buffer).unwrap();
+
+    assert_eq!(record, parsed_record);
+}
+
+#[test]
+fn test_dns_record_nsec3param() {
+    let mut buffer = BytePacketBuffer::default();
+    let record = DnsRecord::NSEC3PARAM {
+        domain: "example".to_string(),
+        hash_algorithm: 1,
+        flags: 0,
+        iterations: 0,
+        salt: vec![],
+        class: QueryClass::IN,
+        ttl: 0,
+    };
+    record.write(&mut buffer).unwrap();
+
+    buffer.seek(0).unwrap();
+    let parsed_record = DnsRecord::read(&mut buffer).unwrap();
+
+    assert_eq!(record, parsed_record);
+}
@@ -0,0 +1,273 @@
+mod common;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use common::{a, ns, TestKey, Zone};
+use rdns_resolver_rs::{
+    dns_record::DnsRecord,
+    dnssec::{
+        base32hex_decode, base32hex_encode, canonical_cmp, ds_digest, key_tag, nsec3_hash,
+        prove_denial, prove_wildcard, verify_ds, verify_rrset, verify_rrsig, Denial,
+    },
+    query_class::QueryClass,
+    query_type::QueryType,
+};
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32
+}
+
+fn hex(text: &str) -> Vec<u8> {
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+fn rrsig_for(zone: &Zone, name: &str, qtype: QueryType) -> DnsRecord {
+    zone.records
+        .iter()
+        .find(|r| {
+            r.domain() == name
+                && matches!(r, DnsRecord::RRSIG { type_covered, .. } if *type_covered == qtype)
+        })
+        .unwrap()
+        .clone()
+}
+
+fn rrset_for(zone: &Zone, name: &str, qtype: QueryType) -> Vec<DnsRecord> {
+    zone.records
+        .iter()
+        .filter(|r| r.domain() == name && r.qtype() == qtype)
+        .cloned()
+        .collect()
+}
+
+fn denial_records(zone: &Zone) -> Vec<DnsRecord> {
+    zone.records
+        .iter()
+        .filter(|r| matches!(r, DnsRecord::NSEC { .. } | DnsRecord::NSEC3 { .. }))
+        .cloned()
+        .collect()
+}
+
+fn example_zone() -> Zone {
+    Zone::new(
+        "example",
+        vec![
+            ns("example", "ns.example"),
+            a("ns.example", "192.0.2.1"),
+            a("www.example", "192.0.2.2"),
+            a("*.wild.example", "192.0.2.3"),
+            a("wild.example", "192.0.2.4"),
+            ns("signed.example", "ns.signed.example"),
+            TestKey::new(15).ds("signed.example"),
+            ns("unsigned.example", "ns.unsigned.example"),
+            a("ns.unsigned.example", "192.0.2.5"),
+        ],
+    )
+}
+
+#[test]
+fn test_key_tag_and_ds_digest() {
+    // RFC 4034 5.4
+    let dnskey = DnsRecord::DNSKEY {
+        domain: "dskey.example.com".to_string(),
+        flags: 256,
+        protocol: 3,
+        algorithm: 5,
+        public_key: hex(
+            "01039e8a247418e318903b215a848acfd5f37f026bd4062db26c774c690968d5d56df8bfda91e6f3\
+             6d9a279888f41333357c5e6029990d10fdf5663062a512763326980a615ddbf17a05ddfcce7e5fb3\
+             abcca05a31b0957452d4521e83870789063115bf97f6c308ccf57cdc9ce7fe10f6ed1bd0cc066003\
+             8c50dcdb0feb963c2f17",
+        ),
+        class: QueryClass::IN,
+        ttl: 86400,
+    };
+    let ds = DnsRecord::DS {
+        domain: "dskey.example.com".to_string(),
+        key_tag: 60485,
+        algorithm: 5,
+        digest_type: 1,
+        digest: hex("2bb183af5f22588179a53b0a98631fad1a292118"),
+        class: QueryClass::IN,
+        ttl: 86400,
+    };
+
+    assert_eq!(key_tag(&dnskey), Ok(60485));
+    assert_eq!(
+        ds_digest(&dnskey, 1).unwrap(),
+        hex("2bb183af5f22588179a53b0a98631fad1a292118")
+    );
+    assert!(verify_ds(&ds, &dnskey));
+}
+
+#[test]
+fn test_nsec3_hash() {
+    // RFC 5155 Appendix A
+    let salt = hex("aabbccdd");
+    let hash = nsec3_hash("example", &salt, 12).unwrap();
+    assert_eq!(base32hex_encode(&hash), "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom");
+    let hash = nsec3_hash("a.example", &salt, 12).unwrap();
+    assert_eq!(base32hex_encode(&hash), "35mthgpgcu1qg68fab165klnsnk3dpvl");
+    assert_eq!(
+        base32hex_decode("35MTHGPGCU1QG68FAB165KLNSNK3DPVL"),
+        Some(hash)
+    );
+    assert_eq!(base32hex_decode("not-base32"), None);
+}
+
+#[test]
+fn test_canonical_order() {
+    // RFC 4034 6.1, without the escaped labels
+    let ordered = [
+        "example",
+        "a.example",
+        "yljkjljk.a.example",
+        "Z.a.example",
+        "zABC.a.EXAMPLE",
+        "z.example",
+        "*.z.example",
+    ];
+    let mut names = ordered.to_vec();
+    names.reverse();
+    names.sort_by(|a, b| canonical_cmp(a, b));
+    assert_eq!(names, ordered);
+}
+
+#[test]
+fn test_verify_rrsig_with_each_algorithm() {
+    for algorithm in [8, 13, 14, 15] {
+        let key = TestKey::new(algorithm);
+        let mut zone = example_zone();
+        zone.sign(&key);
+        let rrset = rrset_for(&zone, "www.example", QueryType::A);
+        let rrsig = rrsig_for(&zone, "www.example", QueryType::A);
+
+        assert_eq!(
+            verify_rrsig(&rrset, &rrsig, &key.dnskey("example"), now()),
+            Ok(()),
+            "algorithm {}",
+            algorithm
+        );
+        // Tampered data
+        let forged = vec![a("www.example", "192.0.2.99")];
+        assert!(verify_rrsig(&forged, &rrsig, &key.dnskey("example"), now()).is_err());
+        // Another key with the same algorithm
+        let other = TestKey::new(if algorithm == 8 { 13 } else { algorithm });
+        assert!(verify_rrsig(&rrset, &rrsig, &other.dnskey("example"), now()).is_err());
+    }
+}
+
+#[test]
+fn test_verify_rrsig_validity_window() {
+    let key = TestKey::new(13);
+    let mut zone = example_zone();
+    zone.sign(&key);
+    let rrset = rrset_for(&zone, "www.example", QueryType::A);
+    let rrsig = rrsig_for(&zone, "www.example", QueryType::A);
+    let keys = vec![key.dnskey("example")];
+
+    assert!(verify_rrset(&rrset, std::slice::from_ref(&rrsig), &keys, now()).is_ok());
+    assert_eq!(
+        verify_rrset(
+            &rrset,
+            std::slice::from_ref(&rrsig),
+            &keys,
+            now() + 2 * 86400
+        ),
+        Err("RRSIG has expired".to_string())
+    );
+    assert_eq!(
+        verify_rrset(&rrset, &[rrsig], &keys, now() - 2 * 3600),
+        Err("RRSIG is not yet valid".to_string())
+    );
+    assert!(verify_rrset(&rrset, &[], &keys, now()).is_err());
+}
+
+#[test]
+fn test_verify_wildcard_expansion() {
+    let key = TestKey::new(15);
+    let mut zone = example_zone();
+    zone.sign(&key);
+    let mut rrsig = rrsig_for(&zone, "*.wild.example", QueryType::A);
+    if let DnsRecord::RRSIG { domain, .. } = &mut rrsig {
+        *domain = "host.wild.example".to_string();
+    }
+    let expanded = vec![a("host.wild.example", "192.0.2.3")];
+
+    assert!(verify_rrsig(&expanded, &rrsig, &key.dnskey("example"), now()).is_ok());
+    assert!(prove_wildcard("host.wild.example", 2, &denial_records(&zone)).is_ok());
+    assert!(prove_wildcard("www.example", 1, &denial_records(&zone)).is_err());
+}
+
+#[test]
+fn test_prove_denial_with_nsec() {
+    let mut zone = example_zone();
+    zone.sign(&TestKey::new(15));
+    let records = denial_records(&zone);
+
+    assert_eq!(
+        prove_denial("missing.example", QueryType::A, &records),
+        Ok(Denial::NoName)
+    );
+    assert_eq!(
+        prove_denial("www.example", QueryType::AAAA, &records),
+        Ok(Denial::NoData { delegation: false })
+    );
+    assert_eq!(
+        prove_denial("unsigned.example", QueryType::DS, &records),
+        Ok(Denial::NoData { delegation: true })
+    );
+    assert!(prove_denial("www.example", QueryType::A, &records).is_err());
+    assert!(prove_denial("signed.example", QueryType::DS, &records).is_err());
+    // A wildcard would have matched
+    assert_eq!(
+        prove_denial("host.wild.example", QueryType::TXT, &records),
+        Ok(Denial::NoData { delegation: false })
+    );
+    assert!(prove_denial("host.wild.example", QueryType::A, &records).is_err());
+    assert!(prove_denial("missing.example", QueryType::A, &[]).is_err());
+}
+
+#[test]
+fn test_prove_denial_with_nsec3() {
+    let mut zone = example_zone();
+    zone.sign_nsec3(&TestKey::new(15), 5, false);
+    let records = denial_records(&zone);
+
+    assert_eq!(
+        prove_denial("missing.example", QueryType::A, &records),
+        Ok(Denial::NoName)
+    );
+    assert_eq!(
+        prove_denial("www.example", QueryType::AAAA, &records),
+        Ok(Denial::NoData { delegation: false })
+    );
+    assert_eq!(
+        prove_denial("unsigned.example", QueryType::DS, &records),
+        Ok(Denial::NoData { delegation: true })
+    );
+    assert!(prove_denial("www.example", QueryType::A, &records).is_err());
+    assert!(prove_denial("missing.example", QueryType::A, &records[0..1]).is_err());
+}
+
+#[test]
+fn test_prove_denial_with_nsec3_opt_out() {
+    let mut zone = example_zone();
+    zone.sign_nsec3(&TestKey::new(15), 0, true);
+    let records = denial_records(&zone);
+
+    assert_eq!(
+        prove_denial("unsigned.example", QueryType::DS, &records),
+        Ok(Denial::Insecure)
+    );
+    assert_eq!(
+        prove_denial("missing.example", QueryType::A, &records),
+        Ok(Denial::Insecure)
+    );
+}
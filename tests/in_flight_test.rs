@@ -0,0 +1,108 @@
+use std::{
+    panic,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use rdns_resolver_rs::{
+    dns_packet::DnsPacket,
+    dns_question::DnsQuestion,
+    in_flight::{InFlight, InFlightStats},
+    query_type::QueryType,
+    res_code::ResultCode,
+};
+
+fn question(name: &str) -> DnsQuestion {
+    DnsQuestion::new(name.to_string(), QueryType::A)
+}
+
+fn slow_answer(calls: &AtomicUsize) -> Result<DnsPacket, String> {
+    calls.fetch_add(1, Ordering::SeqCst);
+    thread::sleep(Duration::from_millis(300));
+    let mut packet = DnsPacket::default();
+    packet.header.rescode = ResultCode::NXDOMAIN;
+    Ok(packet)
+}
+
+#[test]
+fn test_identical_questions_share_a_resolution() {
+    let in_flight = Arc::new(InFlight::new());
+    let calls = Arc::new(AtomicUsize::new(0));
+    let threads: Vec<_> = ["example.com", "EXAMPLE.com", "example.COM", "Example.Com"]
+        .into_iter()
+        .map(|name| {
+            let in_flight = in_flight.clone();
+            let calls = calls.clone();
+            thread::spawn(move || in_flight.resolve(&question(name), || slow_answer(&calls)))
+        })
+        .collect();
+
+    for thread in threads {
+        let result = thread.join().unwrap().unwrap();
+        assert_eq!(result.header.rescode, ResultCode::NXDOMAIN);
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(
+        in_flight.stats(),
+        InFlightStats {
+            resolutions: 1,
+            coalesced: 3
+        }
+    );
+}
+
+#[test]
+fn test_different_questions_resolve_separately() {
+    let in_flight = Arc::new(InFlight::new());
+    let calls = Arc::new(AtomicUsize::new(0));
+    let threads: Vec<_> = ["one.example.com", "two.example.com"]
+        .into_iter()
+        .map(|name| {
+            let in_flight = in_flight.clone();
+            let calls = calls.clone();
+            thread::spawn(move || in_flight.resolve(&question(name), || slow_answer(&calls)))
+        })
+        .collect();
+    for thread in threads {
+        assert!(thread.join().unwrap().is_ok());
+    }
+
+    // Finished resolutions aren't reused, that is the cache's job
+    let calls_after = AtomicUsize::new(0);
+    assert!(in_flight
+        .resolve(&question("one.example.com"), || slow_answer(&calls_after))
+        .is_ok());
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    assert_eq!(calls_after.load(Ordering::SeqCst), 1);
+    assert_eq!(in_flight.stats().coalesced, 0);
+}
+
+#[test]
+fn test_waiters_are_released_when_the_resolution_fails() {
+    let in_flight = Arc::new(InFlight::new());
+    let leader = {
+        let in_flight = in_flight.clone();
+        thread::spawn(move || {
+            let _ = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                in_flight.resolve(&question("example.com"), || {
+                    thread::sleep(Duration::from_millis(300));
+                    panic!("upstream went away");
+                })
+            }));
+        })
+    };
+    thread::sleep(Duration::from_millis(100));
+
+    let result = in_flight.resolve(&question("example.com"), || unreachable!());
+    assert!(result.is_err());
+    leader.join().unwrap();
+    // Later questions start a new resolution
+    let calls = AtomicUsize::new(0);
+    assert!(in_flight
+        .resolve(&question("example.com"), || slow_answer(&calls))
+        .is_ok());
+}
@@ -0,0 +1,103 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    thread,
+    time::Duration,
+};
+
+use rdns_resolver_rs::infra_cache::InfraCache;
+
+const FAST: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+const SLOW: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+const LAME: IpAddr = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 3));
+
+fn millis(duration: Duration) -> u128 {
+    (duration.as_secs_f64() * 1000.0).round() as u128
+}
+
+#[test]
+fn test_srtt_is_smoothed() {
+    let infra = InfraCache::new();
+    assert!(infra.server_stats(FAST).is_none());
+
+    infra.record_rtt(FAST, Duration::from_millis(100));
+    assert_eq!(millis(infra.server_stats(FAST).unwrap().srtt), 100);
+    infra.record_rtt(FAST, Duration::from_millis(200));
+    let stats = infra.server_stats(FAST).unwrap();
+    assert_eq!(millis(stats.srtt), 130);
+    assert_eq!(stats.queries, 2);
+}
+
+#[test]
+fn test_timeouts_back_off() {
+    let infra = InfraCache::new();
+    infra.record_rtt(SLOW, Duration::from_millis(10));
+    infra.record_timeout(SLOW);
+    assert_eq!(millis(infra.server_stats(SLOW).unwrap().srtt), 400);
+    infra.record_timeout(SLOW);
+    let stats = infra.server_stats(SLOW).unwrap();
+    assert_eq!(millis(stats.srtt), 800);
+    assert_eq!(stats.timeouts, 2);
+    assert_eq!(stats.queries, 3);
+}
+
+#[test]
+fn test_order_prefers_fast_servers_and_avoids_lame_ones() {
+    let infra = InfraCache::new();
+    infra.record_rtt(FAST, Duration::from_millis(5));
+    infra.record_rtt(SLOW, Duration::from_millis(300));
+    infra.record_rtt(LAME, Duration::from_millis(1));
+    infra.record_lame(LAME);
+
+    assert!(infra.server_stats(LAME).unwrap().is_lame());
+    assert_eq!(
+        infra.order(&[LAME, SLOW, FAST, SLOW]),
+        vec![FAST, SLOW, LAME]
+    );
+    // A good answer clears the lame flag
+    infra.record_rtt(LAME, Duration::from_millis(1));
+    assert!(!infra.server_stats(LAME).unwrap().is_lame());
+}
+
+#[test]
+fn test_case_mismatches_expire() {
+    let infra = InfraCache::with_ttl(Duration::from_millis(100));
+    assert!(infra.preserves_case(FAST));
+    infra.record_case_mismatch(FAST);
+    assert!(!infra.preserves_case(FAST));
+    assert!(infra.preserves_case(SLOW));
+
+    // 0x20 is tried again once the mismatch is forgotten
+    thread::sleep(Duration::from_millis(150));
+    assert!(infra.preserves_case(FAST));
+}
+
+#[test]
+fn test_servers_within_the_band_share_the_load() {
+    let infra = InfraCache::new();
+    infra.record_rtt(FAST, Duration::from_millis(10));
+    infra.record_rtt(SLOW, Duration::from_millis(12));
+
+    let firsts: Vec<IpAddr> = (0..100).map(|_| infra.order(&[FAST, SLOW])[0]).collect();
+    assert!(firsts.contains(&FAST));
+    assert!(firsts.contains(&SLOW));
+}
+
+#[test]
+fn test_passed_over_servers_are_tried_again() {
+    let infra = InfraCache::new();
+    infra.record_rtt(FAST, Duration::from_millis(1));
+    infra.record_rtt(SLOW, Duration::from_millis(100));
+
+    assert_eq!(infra.order(&[FAST, SLOW])[0], FAST);
+    assert!((0..200).any(|_| infra.order(&[FAST, SLOW])[0] == SLOW));
+}
+
+#[test]
+fn test_stats_list_fastest_first() {
+    let infra = InfraCache::new();
+    infra.record_rtt(SLOW, Duration::from_millis(300));
+    infra.record_rtt(FAST, Duration::from_millis(5));
+
+    let servers: Vec<IpAddr> = infra.stats().iter().map(|(server, _)| *server).collect();
+    assert_eq!(servers, vec![FAST, SLOW]);
+}
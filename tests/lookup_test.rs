@@ -0,0 +1,1547 @@
+use std::{
+    collections::HashSet,
+    net::{Ipv4Addr, Ipv6Addr, TcpListener, TcpStream, UdpSocket},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+mod common;
+
+use common::{
+    a, aaaa, answer, cname, dname, free_port, ns, response_to, serve, serve_with_delay,
+    write_packet, Zone,
+};
+use rdns_resolver_rs::{
+    byte_packet_buffer::BytePacketBuffer,
+    dns_packet::DnsPacket,
+    dns_question::DnsQuestion,
+    dns_record::DnsRecord,
+    in_flight::InFlight,
+    infra_cache::InfraCache,
+    lookup::{
+        discarded_responses, handle_queries, handle_query, handle_tcp_queries, lookup,
+        read_tcp_message, recursive_lookup, write_tcp_message,
+    },
+    lru_cache::LRUCache,
+    query_class::QueryClass,
+    query_type::QueryType,
+    res_code::ResultCode,
+    resolver_config::{IpMode, ResolverConfig},
+};
+
+#[test]
+fn test_lookup_retries_truncated_response_over_tcp() {
+    let udp_server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = udp_server.local_addr().unwrap().port();
+    let tcp_server = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).unwrap();
+
+    thread::spawn(move || {
+        let mut buffer = BytePacketBuffer::default();
+        let (_, src) = udp_server.recv_from(&mut buffer.buf).unwrap();
+        let request = DnsPacket::from_buffer(&mut buffer).unwrap();
+        assert!(request.get_edns().is_some());
+        let mut response = response_to(&request);
+        response.header.truncated_message = true;
+        udp_server.send_to(&write_packet(&response), src).unwrap();
+    });
+    thread::spawn(move || {
+        let (mut stream, _) = tcp_server.accept().unwrap();
+        let mut buffer = read_tcp_message(&mut stream).unwrap();
+        let request = DnsPacket::from_buffer(&mut buffer).unwrap();
+        let mut response = response_to(&request);
+        // Large enough that it would never fit in a plain 512 byte datagram
+        for i in 0..40 {
+            response.answers.push(DnsRecord::A {
+                domain: "example.com".to_string(),
+                addr: Ipv4Addr::new(93, 184, 216, i),
+                class: QueryClass::IN,
+                ttl: 60,
+            });
+        }
+        response.header.answers = response.answers.len() as u16;
+        write_tcp_message(&mut stream, &write_packet(&response)).unwrap();
+    });
+
+    let response = lookup(
+        &ResolverConfig::default(),
+        "example.com",
+        QueryType::A,
+        (Ipv4Addr::LOCALHOST.into(), port),
+        false,
+    )
+    .unwrap();
+
+    assert!(!response.header.truncated_message);
+    assert_eq!(response.answers.len(), 40);
+    assert_eq!(response.get_all_a()[0], Ipv4Addr::new(93, 184, 216, 0));
+}
+
+#[test]
+fn test_lookup_discards_mismatched_responses() {
+    let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = server.local_addr().unwrap().port();
+
+    thread::spawn(move || {
+        let mut buffer = BytePacketBuffer::default();
+        let (_, src) = server.recv_from(&mut buffer.buf).unwrap();
+        let request = DnsPacket::from_buffer(&mut buffer).unwrap();
+        let mut response = response_to(&request);
+        response.answers.push(a("example.com", "192.0.2.1"));
+        response.header.answers = 1;
+
+        // From the wrong port
+        let spoofer = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        spoofer.send_to(&write_packet(&response), src).unwrap();
+        // With the wrong ID
+        let mut forged = response.clone();
+        forged.header.id = request.header.id.wrapping_add(1);
+        server.send_to(&write_packet(&forged), src).unwrap();
+        // For another question
+        let mut forged = response.clone();
+        forged.questions[0].name = "example.net".to_string();
+        server.send_to(&write_packet(&forged), src).unwrap();
+
+        response.answers[0] = a("example.com", "192.0.2.2");
+        server.send_to(&write_packet(&response), src).unwrap();
+    });
+
+    let discarded = discarded_responses();
+    let response = lookup(
+        &ResolverConfig::default(),
+        "example.com",
+        QueryType::A,
+        (Ipv4Addr::LOCALHOST.into(), port),
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(response.get_all_a(), vec![Ipv4Addr::new(192, 0, 2, 2)]);
+    assert!(discarded_responses() >= discarded + 3);
+}
+
+#[test]
+fn test_lookup_uses_random_source_ports() {
+    let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = server.local_addr().unwrap().port();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || loop {
+        let mut buffer = BytePacketBuffer::default();
+        let (_, src) = server.recv_from(&mut buffer.buf).unwrap();
+        let request = DnsPacket::from_buffer(&mut buffer).unwrap();
+        server
+            .send_to(&write_packet(&response_to(&request)), src)
+            .unwrap();
+        sender.send(src.port()).unwrap();
+    });
+
+    let config = ResolverConfig {
+        query_ports: vec![41000..=41004, 42000..=42004],
+        excluded_query_ports: vec![41000, 41001, 42004],
+        ..Default::default()
+    };
+    let mut ports = HashSet::new();
+    for _ in 0..20 {
+        lookup(
+            &config,
+            "example.com",
+            QueryType::A,
+            (Ipv4Addr::LOCALHOST.into(), port),
+            false,
+        )
+        .unwrap();
+        ports.insert(receiver.recv().unwrap());
+    }
+
+    assert!(ports.len() > 1);
+    for port in ports {
+        assert!((41002..=41004).contains(&port) || (42000..=42003).contains(&port));
+    }
+}
+
+#[test]
+fn test_0x20_randomizes_the_case_of_names_sent_upstream() {
+    // Echoes queries back byte for byte, so the case of the question is preserved
+    let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = server.local_addr().unwrap().port();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || loop {
+        let mut buffer = BytePacketBuffer::default();
+        let (len, src) = server.recv_from(&mut buffer.buf).unwrap();
+        let mut name = String::new();
+        buffer.seek(12).unwrap();
+        buffer.read_qname_preserving_case(&mut name).unwrap();
+        buffer.buf[2] |= 0x80;
+        server.send_to(&buffer.buf[..len], src).unwrap();
+        sender.send(name).unwrap();
+    });
+    let config = ResolverConfig {
+        root_servers: vec![Ipv4Addr::LOCALHOST.into()],
+        port,
+        use_0x20: true,
+        ..Default::default()
+    };
+    let infra = InfraCache::new();
+
+    let mut names = Vec::new();
+    for _ in 0..5 {
+        let mut request = DnsPacket::default();
+        request.header.questions = 1;
+        request.questions.push(DnsQuestion::new(
+            "abcdefghijklmnop.example.test".to_string(),
+            QueryType::A,
+        ));
+        let response = handle_query(
+            request,
+            &LRUCache::new(10),
+            &InFlight::new(),
+            &infra,
+            &config,
+        )
+        .unwrap();
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        names.push(receiver.recv().unwrap());
+    }
+
+    assert!(names
+        .iter()
+        .all(|name| name.eq_ignore_ascii_case("abcdefghijklmnop.example.test")));
+    assert!(names
+        .iter()
+        .any(|name| name != "abcdefghijklmnop.example.test"));
+    assert!(infra.preserves_case(Ipv4Addr::LOCALHOST.into()));
+}
+
+#[test]
+fn test_0x20_falls_back_for_servers_that_change_case() {
+    let port = free_port();
+    // The stand-in server echoes the question in lowercase
+    let queries = serve(
+        Ipv4Addr::LOCALHOST,
+        port,
+        vec![Zone::new(
+            "example.test",
+            vec![a("abcdefghijklmnop.example.test", "192.0.2.1")],
+        )],
+    );
+    let config = ResolverConfig {
+        root_servers: vec![Ipv4Addr::LOCALHOST.into()],
+        port,
+        retries: 0,
+        use_0x20: true,
+        ..Default::default()
+    };
+    let infra = InfraCache::new();
+    let query = || {
+        let mut request = DnsPacket::default();
+        request.header.questions = 1;
+        request.questions.push(DnsQuestion::new(
+            "abcdefghijklmnop.example.test".to_string(),
+            QueryType::A,
+        ));
+        handle_query(
+            request,
+            &LRUCache::new(10),
+            &InFlight::new(),
+            &infra,
+            &config,
+        )
+        .unwrap()
+    };
+
+    assert_eq!(query().answers.len(), 1);
+    assert!(!infra.preserves_case(Ipv4Addr::LOCALHOST.into()));
+    // Later queries go out in lowercase right away
+    let sent = queries.load(Ordering::SeqCst);
+    assert_eq!(query().answers.len(), 1);
+    assert_eq!(queries.load(Ordering::SeqCst), sent + 1);
+}
+
+#[test]
+fn test_0x20_only_checks_the_case_of_randomized_names() {
+    // Echoes the question in uppercase
+    let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = server.local_addr().unwrap().port();
+    let queries = Arc::new(AtomicUsize::new(0));
+    let counter = queries.clone();
+    thread::spawn(move || loop {
+        let mut buffer = BytePacketBuffer::default();
+        let (_, src) = server.recv_from(&mut buffer.buf).unwrap();
+        let request = DnsPacket::from_buffer(&mut buffer).unwrap();
+        let mut response = response_to(&request);
+        let name = request.questions[0].name.to_uppercase();
+        response.questions[0].name = name.clone();
+        response.answers.push(a(&name, "192.0.2.1"));
+        response.header.answers = 1;
+        counter.fetch_add(1, Ordering::SeqCst);
+        server.send_to(&write_packet(&response), src).unwrap();
+    });
+    let server = (Ipv4Addr::LOCALHOST.into(), port);
+    let config = ResolverConfig {
+        root_servers: vec![Ipv4Addr::LOCALHOST.into()],
+        port,
+        retries: 0,
+        use_0x20: true,
+        ..Default::default()
+    };
+
+    assert!(lookup(&config, "ExAmPlE.test", QueryType::A, server, true).is_err());
+    assert!(lookup(&config, "ExAmPlE.test", QueryType::A, server, false).is_ok());
+
+    // Names that weren't randomized, here for a server that is listed as not
+    // preserving case, are taken however they come back
+    let query = |config: &ResolverConfig, infra: &InfraCache| {
+        let mut request = DnsPacket::default();
+        request.header.questions = 1;
+        request.questions.push(DnsQuestion::new(
+            "abcdefghijklmnop.example.test".to_string(),
+            QueryType::A,
+        ));
+        handle_query(request, &LRUCache::new(10), &InFlight::new(), infra, config).unwrap()
+    };
+    let excluded = ResolverConfig {
+        no_0x20_servers: vec![Ipv4Addr::LOCALHOST.into()],
+        ..config.clone()
+    };
+    let infra = InfraCache::new();
+    let sent = queries.load(Ordering::SeqCst);
+    assert_eq!(query(&excluded, &infra).answers.len(), 1);
+    assert_eq!(queries.load(Ordering::SeqCst), sent + 1);
+    assert!(infra.preserves_case(Ipv4Addr::LOCALHOST.into()));
+
+    // Otherwise the mismatch is noticed and the name asked for again as it was
+    let sent = queries.load(Ordering::SeqCst);
+    assert_eq!(query(&config, &infra).answers.len(), 1);
+    assert_eq!(queries.load(Ordering::SeqCst), sent + 2);
+    assert!(!infra.preserves_case(Ipv4Addr::LOCALHOST.into()));
+}
+
+#[test]
+fn test_handle_tcp_queries() {
+    let mut cached = DnsPacket::default();
+    cached.answers.push(DnsRecord::A {
+        domain: "example.com".to_string(),
+        addr: Ipv4Addr::new(93, 184, 216, 34),
+        class: QueryClass::IN,
+        ttl: 60,
+    });
+    let cache = LRUCache::new(10);
+    cache.put(
+        &DnsQuestion::new("example.com".to_string(), QueryType::A),
+        &cached,
+    );
+    let cache = Arc::new(cache);
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        handle_tcp_queries(
+            &listener,
+            cache,
+            Arc::new(InFlight::new()),
+            Arc::new(InfraCache::new()),
+            Arc::new(ResolverConfig::default()),
+        )
+    });
+
+    let mut request = DnsPacket::default();
+    request.header.id = 4242;
+    request.header.questions = 1;
+    request
+        .questions
+        .push(DnsQuestion::new("example.com".to_string(), QueryType::A));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    for _ in 0..2 {
+        write_tcp_message(&mut stream, &write_packet(&request)).unwrap();
+        let mut buffer = read_tcp_message(&mut stream).unwrap();
+        let response = DnsPacket::from_buffer(&mut buffer).unwrap();
+
+        assert_eq!(response.header.id, 4242);
+        assert_eq!(
+            response.get_random_a(),
+            Some(Ipv4Addr::new(93, 184, 216, 34))
+        );
+    }
+}
+
+#[test]
+fn test_handle_query_edns() {
+    let mut cached = DnsPacket::default();
+    cached.answers.push(DnsRecord::A {
+        domain: "example.com".to_string(),
+        addr: Ipv4Addr::new(93, 184, 216, 34),
+        class: QueryClass::IN,
+        ttl: 60,
+    });
+    cached.set_edns(4096, false);
+    let cache = LRUCache::new(10);
+    cache.put(
+        &DnsQuestion::new("example.com".to_string(), QueryType::A),
+        &cached,
+    );
+
+    let mut request = DnsPacket::default();
+    request.header.questions = 1;
+    request
+        .questions
+        .push(DnsQuestion::new("example.com".to_string(), QueryType::A));
+    request.set_edns(4096, true);
+
+    let response = handle_query(
+        request.clone(),
+        &cache,
+        &InFlight::new(),
+        &InfraCache::new(),
+        &ResolverConfig::default(),
+    )
+    .unwrap();
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert_eq!(response.resources.len(), 1);
+    assert!(response.dnssec_ok());
+
+    if let Some(DnsRecord::OPT { version, .. }) = request.resources.last_mut() {
+        *version = 1;
+    }
+    let response = handle_query(
+        request,
+        &cache,
+        &InFlight::new(),
+        &InfraCache::new(),
+        &ResolverConfig::default(),
+    )
+    .unwrap();
+    assert_eq!(response.header.rescode, ResultCode::BADVERS);
+    assert!(response.answers.is_empty());
+}
+
+#[test]
+fn test_handle_query_refuses_other_classes() {
+    let cache = LRUCache::new(10);
+
+    let mut question = DnsQuestion::new("example.com".to_string(), QueryType::A);
+    question.qclass = QueryClass::HS;
+    let mut request = DnsPacket::default();
+    request.header.questions = 1;
+    request.questions.push(question.clone());
+
+    let response = handle_query(
+        request,
+        &cache,
+        &InFlight::new(),
+        &InfraCache::new(),
+        &ResolverConfig::default(),
+    )
+    .unwrap();
+    assert_eq!(response.header.rescode, ResultCode::REFUSED);
+    assert_eq!(response.questions, vec![question]);
+}
+
+#[test]
+fn test_handle_query_chaos() {
+    let cache = LRUCache::new(10);
+    let chaos_request = |name: &str| {
+        let mut question = DnsQuestion::new(name.to_string(), QueryType::TXT);
+        question.qclass = QueryClass::CH;
+        let mut request = DnsPacket::default();
+        request.header.questions = 1;
+        request.questions.push(question);
+        request
+    };
+
+    let response = handle_query(
+        chaos_request("version.bind"),
+        &cache,
+        &InFlight::new(),
+        &InfraCache::new(),
+        &ResolverConfig::default(),
+    )
+    .unwrap();
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    match &response.answers[..] {
+        [DnsRecord::TXT { data, class, .. }] => {
+            assert_eq!(*class, QueryClass::CH);
+            assert!(data[0].starts_with(b"rdns-resolver-rs"));
+        }
+        answers => panic!("Expected a single TXT answer, got {:?}", answers),
+    }
+
+    let response = handle_query(
+        chaos_request("id.server"),
+        &cache,
+        &InFlight::new(),
+        &InfraCache::new(),
+        &ResolverConfig::default(),
+    )
+    .unwrap();
+    assert_eq!(response.answers.len(), 1);
+
+    let response = handle_query(
+        chaos_request("example.com"),
+        &cache,
+        &InFlight::new(),
+        &InfraCache::new(),
+        &ResolverConfig::default(),
+    )
+    .unwrap();
+    assert_eq!(response.header.rescode, ResultCode::REFUSED);
+    assert!(response.answers.is_empty());
+}
+
+#[test]
+fn test_handle_query_dnssec_records_need_do() {
+    let mut cached = DnsPacket::default();
+    cached.answers.push(DnsRecord::A {
+        domain: "example.com".to_string(),
+        addr: Ipv4Addr::new(93, 184, 216, 34),
+        class: QueryClass::IN,
+        ttl: 60,
+    });
+    cached.answers.push(DnsRecord::RRSIG {
+        domain: "example.com".to_string(),
+        type_covered: QueryType::A,
+        algorithm: 13,
+        labels: 2,
+        original_ttl: 60,
+        expiration: 1700000000,
+        inception: 1690000000,
+        key_tag: 370,
+        signer_name: "example.com".to_string(),
+        signature: vec![0; 64],
+        class: QueryClass::IN,
+        ttl: 60,
+    });
+    let cache = LRUCache::new(10);
+    cache.put(
+        &DnsQuestion::new("example.com".to_string(), QueryType::A),
+        &cached,
+    );
+
+    let mut request = DnsPacket::default();
+    request.header.questions = 1;
+    request
+        .questions
+        .push(DnsQuestion::new("example.com".to_string(), QueryType::A));
+    let response = handle_query(
+        request.clone(),
+        &cache,
+        &InFlight::new(),
+        &InfraCache::new(),
+        &ResolverConfig::default(),
+    )
+    .unwrap();
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.header.answers, 1);
+
+    request.set_edns(1232, true);
+    let response = handle_query(
+        request,
+        &cache,
+        &InFlight::new(),
+        &InfraCache::new(),
+        &ResolverConfig::default(),
+    )
+    .unwrap();
+    assert_eq!(response.answers, cached.answers);
+    assert_eq!(response.header.answers, 2);
+}
+
+#[test]
+fn test_handle_query_caches_each_type_separately() {
+    let port = free_port();
+    let zone = Zone::new(
+        "example.test",
+        vec![
+            ns("example.test", "ns.example.test"),
+            a("example.test", "192.0.2.1"),
+            DnsRecord::MX {
+                domain: "example.test".to_string(),
+                priority: 10,
+                host: "mail.example.test".to_string(),
+                class: QueryClass::IN,
+                ttl: 3600,
+            },
+        ],
+    );
+    serve(Ipv4Addr::LOCALHOST, port, vec![zone]);
+    let config = ResolverConfig {
+        root_servers: vec![Ipv4Addr::LOCALHOST.into()],
+        port,
+        ..Default::default()
+    };
+    let cache = LRUCache::new(10);
+
+    for qtype in [QueryType::A, QueryType::MX, QueryType::A, QueryType::MX] {
+        let mut request = DnsPacket::default();
+        request.header.questions = 1;
+        request
+            .questions
+            .push(DnsQuestion::new("example.test".to_string(), qtype));
+        let response = handle_query(
+            request,
+            &cache,
+            &InFlight::new(),
+            &InfraCache::new(),
+            &config,
+        )
+        .unwrap();
+
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].qtype(), qtype);
+    }
+    assert!(cache
+        .get(&DnsQuestion::new("example.test".to_string(), QueryType::MX))
+        .is_some());
+}
+
+#[test]
+fn test_handle_query_reuses_delegations_and_counts_down_ttls() {
+    let port = free_port();
+    let root = Zone::new(
+        "",
+        vec![
+            ns("", "a.root"),
+            a("a.root", "127.0.0.1"),
+            ns("example.test", "ns.example.test"),
+            a("ns.example.test", "127.0.0.2"),
+        ],
+    );
+    let zone = Zone::new(
+        "example.test",
+        vec![
+            ns("example.test", "ns.example.test"),
+            a("ns.example.test", "127.0.0.2"),
+            a("www.example.test", "192.0.2.1"),
+            a("mail.example.test", "192.0.2.2"),
+        ],
+    );
+    let root_queries = serve(Ipv4Addr::new(127, 0, 0, 1), port, vec![root]);
+    serve(Ipv4Addr::new(127, 0, 0, 2), port, vec![zone]);
+    let config = ResolverConfig {
+        root_servers: vec![Ipv4Addr::new(127, 0, 0, 1).into()],
+        port,
+        ..Default::default()
+    };
+    let cache = LRUCache::new(10);
+    let query = |name: &str| {
+        let mut request = DnsPacket::default();
+        request.header.questions = 1;
+        request
+            .questions
+            .push(DnsQuestion::new(name.to_string(), QueryType::A));
+        handle_query(
+            request,
+            &cache,
+            &InFlight::new(),
+            &InfraCache::new(),
+            &config,
+        )
+        .unwrap()
+    };
+
+    assert_eq!(query("www.example.test").answers[0].ttl(), 3600);
+    assert_eq!(root_queries.load(Ordering::SeqCst), 1);
+    // The referral to example.test was cached, so the root isn't asked again
+    assert_eq!(query("mail.example.test").answers.len(), 1);
+    assert_eq!(root_queries.load(Ordering::SeqCst), 1);
+
+    thread::sleep(Duration::from_secs(1));
+    assert_eq!(query("www.example.test").answers[0].ttl(), 3599);
+}
+
+#[test]
+fn test_handle_query_reuses_glueless_nameserver_addresses() {
+    let port = free_port();
+    // example.test is served by a host in hosting.test, which the root has no glue for
+    let root = Zone::new(
+        "",
+        vec![
+            ns("", "a.root"),
+            a("a.root", "127.0.0.1"),
+            ns("hosting.test", "a.ns.hosting.test"),
+            a("a.ns.hosting.test", "127.0.0.2"),
+            ns("example.test", "b.ns.hosting.test"),
+        ],
+    );
+    let hosting = Zone::new(
+        "hosting.test",
+        vec![
+            ns("hosting.test", "a.ns.hosting.test"),
+            a("a.ns.hosting.test", "127.0.0.2"),
+            a("b.ns.hosting.test", "127.0.0.3"),
+        ],
+    );
+    let zone = Zone::new(
+        "example.test",
+        vec![
+            ns("example.test", "b.ns.hosting.test"),
+            a("www.example.test", "192.0.2.1"),
+            a("mail.example.test", "192.0.2.2"),
+        ],
+    );
+    let root_queries = serve(Ipv4Addr::new(127, 0, 0, 1), port, vec![root]);
+    serve(Ipv4Addr::new(127, 0, 0, 2), port, vec![hosting]);
+    serve(Ipv4Addr::new(127, 0, 0, 3), port, vec![zone]);
+    let config = ResolverConfig {
+        root_servers: vec![Ipv4Addr::new(127, 0, 0, 1).into()],
+        port,
+        ..Default::default()
+    };
+    let cache = LRUCache::new(10);
+    let query = |name: &str| {
+        let mut request = DnsPacket::default();
+        request.header.questions = 1;
+        request
+            .questions
+            .push(DnsQuestion::new(name.to_string(), QueryType::A));
+        handle_query(
+            request,
+            &cache,
+            &InFlight::new(),
+            &InfraCache::new(),
+            &config,
+        )
+        .unwrap()
+    };
+
+    assert_eq!(query("www.example.test").answers.len(), 1);
+    let asked = root_queries.load(Ordering::SeqCst);
+    // The address of b.ns.hosting.test was kept, so the root isn't asked again
+    assert_eq!(query("mail.example.test").answers.len(), 1);
+    assert_eq!(root_queries.load(Ordering::SeqCst), asked);
+}
+
+#[test]
+fn test_handle_query_resolves_in_parallel() {
+    let port = free_port();
+    let delay = Duration::from_millis(500);
+    let zone = Zone::new(
+        "example.test",
+        vec![
+            ns("example.test", "ns.example.test"),
+            a("one.example.test", "192.0.2.1"),
+            a("two.example.test", "192.0.2.2"),
+        ],
+    );
+    serve_with_delay(Ipv4Addr::LOCALHOST, port, vec![zone], delay);
+    let config = Arc::new(ResolverConfig {
+        root_servers: vec![Ipv4Addr::LOCALHOST.into()],
+        port,
+        ..Default::default()
+    });
+    let cache = Arc::new(LRUCache::new(10));
+    let cached = DnsQuestion::new("cached.example.test".to_string(), QueryType::A);
+    cache.put(&cached, &{
+        let mut packet = DnsPacket::default();
+        packet.answers.push(a("cached.example.test", "192.0.2.3"));
+        packet
+    });
+
+    // Through the server loop, with its workers resolving side by side
+    let req_socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let server = req_socket.local_addr().unwrap();
+    let server_cache = cache.clone();
+    thread::spawn(move || {
+        handle_queries(
+            &req_socket,
+            server_cache,
+            Arc::new(InFlight::new()),
+            Arc::new(InfraCache::new()),
+            config,
+        )
+    });
+
+    let start = Instant::now();
+    let clients: Vec<_> = [
+        ("one.example.test", Ipv4Addr::new(192, 0, 2, 1)),
+        ("two.example.test", Ipv4Addr::new(192, 0, 2, 2)),
+    ]
+    .into_iter()
+    .map(|(name, addr)| {
+        let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        client.set_read_timeout(Some(delay * 4)).unwrap();
+        let mut request = DnsPacket::default();
+        request.header.questions = 1;
+        request
+            .questions
+            .push(DnsQuestion::new(name.to_string(), QueryType::A));
+        client.send_to(&write_packet(&request), server).unwrap();
+        (client, name, addr)
+    })
+    .collect();
+
+    // The cache stays usable while both lookups wait on the network
+    thread::sleep(delay / 5);
+    assert!(cache.get(&cached).is_some());
+    assert!(start.elapsed() < delay);
+
+    // And each client gets the answer to its own question
+    for (client, name, addr) in clients {
+        let mut buffer = BytePacketBuffer::default();
+        client.recv_from(&mut buffer.buf).unwrap();
+        let response = DnsPacket::from_buffer(&mut buffer).unwrap();
+        assert_eq!(response.questions[0].name, name);
+        assert_eq!(response.get_random_a(), Some(addr));
+    }
+    assert!(start.elapsed() < delay * 2);
+}
+
+#[test]
+fn test_handle_query_coalesces_identical_questions() {
+    let port = free_port();
+    let zone = Zone::new(
+        "example.test",
+        vec![
+            ns("example.test", "ns.example.test"),
+            a("www.example.test", "192.0.2.1"),
+        ],
+    );
+    let upstream = serve_with_delay(
+        Ipv4Addr::LOCALHOST,
+        port,
+        vec![zone],
+        Duration::from_millis(300),
+    );
+    let config = Arc::new(ResolverConfig {
+        root_servers: vec![Ipv4Addr::LOCALHOST.into()],
+        port,
+        ..Default::default()
+    });
+    let cache = Arc::new(LRUCache::new(10));
+    let in_flight = Arc::new(InFlight::new());
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let (cache, in_flight, config) = (cache.clone(), in_flight.clone(), config.clone());
+            thread::spawn(move || {
+                let mut request = DnsPacket::default();
+                request.header.questions = 1;
+                request.questions.push(DnsQuestion::new(
+                    "www.example.test".to_string(),
+                    QueryType::A,
+                ));
+                handle_query(request, &cache, &in_flight, &InfraCache::new(), &config).unwrap()
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        assert_eq!(thread.join().unwrap().answers.len(), 1);
+    }
+    assert_eq!(upstream.load(Ordering::SeqCst), 1);
+    assert_eq!(in_flight.stats().resolutions, 1);
+    assert_eq!(in_flight.stats().coalesced, 3);
+}
+
+fn query_a(cache: &LRUCache, config: &ResolverConfig, name: &str) -> DnsPacket {
+    let mut request = DnsPacket::default();
+    request.header.questions = 1;
+    request
+        .questions
+        .push(DnsQuestion::new(name.to_string(), QueryType::A));
+    handle_query(request, cache, &InFlight::new(), &InfraCache::new(), config).unwrap()
+}
+
+#[test]
+fn test_recursive_lookup_fails_over_to_other_servers() {
+    let port = free_port();
+    // Bound but never answers
+    let _silent_root = UdpSocket::bind((Ipv4Addr::new(127, 0, 0, 1), port)).unwrap();
+    let _silent_ns = UdpSocket::bind((Ipv4Addr::new(127, 0, 0, 4), port)).unwrap();
+    let root = Zone::new(
+        "",
+        vec![
+            ns("example.test", "ns1.example.test"),
+            ns("example.test", "ns2.example.test"),
+            a("ns1.example.test", "127.0.0.4"),
+            a("ns2.example.test", "127.0.0.5"),
+        ],
+    );
+    let zone = Zone::new(
+        "example.test",
+        vec![
+            ns("example.test", "ns1.example.test"),
+            ns("example.test", "ns2.example.test"),
+            a("www.example.test", "192.0.2.1"),
+        ],
+    );
+    serve(Ipv4Addr::new(127, 0, 0, 3), port, vec![root]);
+    serve(Ipv4Addr::new(127, 0, 0, 5), port, vec![zone]);
+    let config = ResolverConfig {
+        // Nothing at all listens on 127.0.0.2
+        root_servers: vec![
+            Ipv4Addr::new(127, 0, 0, 1).into(),
+            Ipv4Addr::new(127, 0, 0, 2).into(),
+            Ipv4Addr::new(127, 0, 0, 3).into(),
+        ],
+        port,
+        timeout: Duration::from_millis(200),
+        retries: 0,
+        ..Default::default()
+    };
+
+    let response = query_a(&LRUCache::new(10), &config, "www.example.test");
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert_eq!(response.answers.len(), 1);
+}
+
+#[test]
+fn test_recursive_lookup_servfails_once_every_server_is_exhausted() {
+    let port = free_port();
+    let _silent_root = UdpSocket::bind((Ipv4Addr::new(127, 0, 0, 1), port)).unwrap();
+    let config = ResolverConfig {
+        root_servers: vec![
+            Ipv4Addr::new(127, 0, 0, 1).into(),
+            Ipv4Addr::new(127, 0, 0, 2).into(),
+        ],
+        port,
+        timeout: Duration::from_millis(100),
+        retries: 1,
+        ..Default::default()
+    };
+
+    let start = Instant::now();
+    let response = query_a(&LRUCache::new(10), &config, "www.example.test");
+    assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+    // Two rounds through both roots, each waiting at most the timeout
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn test_recursive_lookup_skips_servers_answering_unknown_rcodes() {
+    let port = free_port();
+    let broken = UdpSocket::bind((Ipv4Addr::new(127, 0, 0, 1), port)).unwrap();
+    thread::spawn(move || loop {
+        let mut buffer = BytePacketBuffer::default();
+        let Ok((_, src)) = broken.recv_from(&mut buffer.buf) else {
+            break;
+        };
+        let request = DnsPacket::from_buffer(&mut buffer).unwrap();
+        let mut response = response_to(&request);
+        response.header.rescode = ResultCode::UNKNOWN(15);
+        broken.send_to(&write_packet(&response), src).unwrap();
+    });
+    let zone = Zone::new(
+        "example.test",
+        vec![
+            ns("example.test", "ns.example.test"),
+            a("www.example.test", "192.0.2.1"),
+        ],
+    );
+    serve(Ipv4Addr::new(127, 0, 0, 2), port, vec![zone]);
+    let config = ResolverConfig {
+        root_servers: vec![
+            Ipv4Addr::new(127, 0, 0, 1).into(),
+            Ipv4Addr::new(127, 0, 0, 2).into(),
+        ],
+        port,
+        ..Default::default()
+    };
+
+    let response = query_a(&LRUCache::new(10), &config, "www.example.test");
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert_eq!(response.answers.len(), 1);
+}
+
+#[test]
+fn test_recursive_lookup_gives_up_on_mutually_glueless_zones() {
+    let port = free_port();
+    let root = Zone::new(
+        "",
+        vec![ns("a.test", "ns.b.test"), ns("b.test", "ns.a.test")],
+    );
+    let root_queries = serve(Ipv4Addr::new(127, 0, 0, 1), port, vec![root]);
+    let config = ResolverConfig {
+        root_servers: vec![Ipv4Addr::new(127, 0, 0, 1).into()],
+        port,
+        ..Default::default()
+    };
+
+    // Each zone's servers can only be found through the other's
+    let response = query_a(&LRUCache::new(100), &config, "www.a.test");
+    assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+    assert!(root_queries.load(Ordering::SeqCst) < 1000);
+}
+
+#[test]
+fn test_recursive_lookup_prefers_the_fastest_nameserver() {
+    let port = free_port();
+    let root = Zone::new(
+        "",
+        vec![
+            ns("example.test", "fast.example.test"),
+            ns("example.test", "slow.example.test"),
+            a("fast.example.test", "127.0.0.2"),
+            a("slow.example.test", "127.0.0.3"),
+        ],
+    );
+    let records: Vec<_> = (0..10)
+        .map(|i| a(&format!("host{}.example.test", i), "192.0.2.1"))
+        .chain([
+            ns("example.test", "fast.example.test"),
+            ns("example.test", "slow.example.test"),
+        ])
+        .collect();
+    serve(Ipv4Addr::new(127, 0, 0, 1), port, vec![root]);
+    serve(
+        Ipv4Addr::new(127, 0, 0, 2),
+        port,
+        vec![Zone::new("example.test", records.clone())],
+    );
+    serve_with_delay(
+        Ipv4Addr::new(127, 0, 0, 3),
+        port,
+        vec![Zone::new("example.test", records)],
+        Duration::from_millis(150),
+    );
+    let config = ResolverConfig {
+        root_servers: vec![Ipv4Addr::new(127, 0, 0, 1).into()],
+        port,
+        ..Default::default()
+    };
+    let cache = LRUCache::new(100);
+    let infra = InfraCache::new();
+
+    for i in 0..10 {
+        let mut request = DnsPacket::default();
+        request.header.questions = 1;
+        request.questions.push(DnsQuestion::new(
+            format!("host{}.example.test", i),
+            QueryType::A,
+        ));
+        let response = handle_query(request, &cache, &InFlight::new(), &infra, &config).unwrap();
+        assert_eq!(response.answers.len(), 1);
+    }
+
+    let fast = infra
+        .server_stats(Ipv4Addr::new(127, 0, 0, 2).into())
+        .unwrap();
+    let slow = infra
+        .server_stats(Ipv4Addr::new(127, 0, 0, 3).into())
+        .unwrap();
+    assert!(fast.queries >= 8, "{:?}", fast);
+    assert!(slow.queries <= 2, "{:?}", slow);
+    assert!(slow.srtt > fast.srtt);
+}
+
+// A v4 root referring example.test to a server that only has an IPv6 address
+fn serve_v6_only_delegation(port: u16) {
+    let root = Zone::new(
+        "",
+        vec![
+            ns("example.test", "ns.example.test"),
+            aaaa("ns.example.test", "::1"),
+        ],
+    );
+    let zone = Zone::new(
+        "example.test",
+        vec![
+            ns("example.test", "ns.example.test"),
+            aaaa("ns.example.test", "::1"),
+            a("www.example.test", "192.0.2.1"),
+        ],
+    );
+    serve(Ipv4Addr::LOCALHOST, port, vec![root]);
+    serve(Ipv6Addr::LOCALHOST, port, vec![zone]);
+}
+
+#[test]
+fn test_recursive_lookup_follows_ipv6_glue() {
+    let port = free_port();
+    serve_v6_only_delegation(port);
+    let config = ResolverConfig {
+        root_servers: vec![Ipv4Addr::LOCALHOST.into()],
+        port,
+        ip_mode: IpMode::DualStack,
+        ..Default::default()
+    };
+
+    let response = query_a(&LRUCache::new(10), &config, "www.example.test");
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert_eq!(response.get_all_a(), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+}
+
+#[test]
+fn test_recursive_lookup_v4_only_ignores_ipv6_servers() {
+    let port = free_port();
+    serve_v6_only_delegation(port);
+    let config = ResolverConfig {
+        root_servers: vec![Ipv4Addr::LOCALHOST.into()],
+        port,
+        ip_mode: IpMode::V4Only,
+        ..Default::default()
+    };
+
+    let response = query_a(&LRUCache::new(10), &config, "www.example.test");
+    assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+}
+
+#[test]
+fn test_recursive_lookup_v6_only_never_asks_ipv4_servers() {
+    let port = free_port();
+    let glue = [
+        ns("example.test", "ns.example.test"),
+        a("ns.example.test", "127.0.0.1"),
+        aaaa("ns.example.test", "::1"),
+    ];
+    let root = || Zone::new("", glue.to_vec());
+    let zone = Zone::new(
+        "example.test",
+        glue.iter()
+            .cloned()
+            .chain([a("www.example.test", "192.0.2.1")])
+            .collect(),
+    );
+    let v4_queries = serve(Ipv4Addr::LOCALHOST, port, vec![root()]);
+    serve(Ipv6Addr::LOCALHOST, port, vec![root(), zone]);
+    let config = ResolverConfig {
+        root_servers: vec![Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()],
+        port,
+        ip_mode: IpMode::V6Only,
+        ..Default::default()
+    };
+
+    let response = query_a(&LRUCache::new(10), &config, "www.example.test");
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert_eq!(response.get_all_a(), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    assert_eq!(v4_queries.load(Ordering::SeqCst), 0);
+}
+
+// Two zones on servers of their own, with aliases pointing across and within them
+fn serve_aliased_zones(port: u16) -> (Arc<AtomicUsize>, Arc<AtomicUsize>) {
+    let root = Zone::new(
+        "",
+        vec![
+            ns("a.test", "ns.a.test"),
+            a("ns.a.test", "127.0.0.2"),
+            ns("b.test", "ns.b.test"),
+            a("ns.b.test", "127.0.0.3"),
+        ],
+    );
+    let zone_a = Zone::new(
+        "a.test",
+        vec![
+            ns("a.test", "ns.a.test"),
+            a("ns.a.test", "127.0.0.2"),
+            cname("www.a.test", "alias.b.test"),
+            cname("loop.a.test", "loop.b.test"),
+            cname("c1.a.test", "c2.a.test"),
+            cname("c2.a.test", "c3.a.test"),
+            cname("c3.a.test", "www.b.test"),
+        ],
+    );
+    let zone_b = Zone::new(
+        "b.test",
+        vec![
+            ns("b.test", "ns.b.test"),
+            a("ns.b.test", "127.0.0.3"),
+            cname("alias.b.test", "www.b.test"),
+            a("www.b.test", "192.0.2.7"),
+            cname("loop.b.test", "loop.a.test"),
+        ],
+    );
+    serve(Ipv4Addr::new(127, 0, 0, 1), port, vec![root]);
+    (
+        serve(Ipv4Addr::new(127, 0, 0, 2), port, vec![zone_a]),
+        serve(Ipv4Addr::new(127, 0, 0, 3), port, vec![zone_b]),
+    )
+}
+
+#[test]
+fn test_recursive_lookup_chases_cnames_across_zones() {
+    let port = free_port();
+    let (_, zone_b_queries) = serve_aliased_zones(port);
+    let config = ResolverConfig {
+        root_servers: vec![Ipv4Addr::new(127, 0, 0, 1).into()],
+        port,
+        ..Default::default()
+    };
+    let cache = LRUCache::new(100);
+
+    let response = query_a(&cache, &config, "www.a.test");
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert_eq!(
+        response.answers,
+        vec![
+            cname("www.a.test", "alias.b.test"),
+            cname("alias.b.test", "www.b.test"),
+            a("www.b.test", "192.0.2.7"),
+        ]
+    );
+
+    // Every link is cached on its own, so other names on the chain are answered from it
+    let queries = zone_b_queries.load(Ordering::SeqCst);
+    let response = query_a(&cache, &config, "alias.b.test");
+    assert_eq!(response.answers.len(), 2);
+    let response = query_a(&cache, &config, "www.a.test");
+    assert_eq!(response.answers.len(), 3);
+    assert_eq!(zone_b_queries.load(Ordering::SeqCst), queries);
+}
+
+#[test]
+fn test_recursive_lookup_detects_cname_loops() {
+    let port = free_port();
+    serve_aliased_zones(port);
+    let config = ResolverConfig {
+        root_servers: vec![Ipv4Addr::new(127, 0, 0, 1).into()],
+        port,
+        ..Default::default()
+    };
+
+    let response = query_a(&LRUCache::new(100), &config, "loop.a.test");
+    assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+}
+
+#[test]
+fn test_recursive_lookup_limits_cname_chains() {
+    let port = free_port();
+    serve_aliased_zones(port);
+    let config = ResolverConfig {
+        root_servers: vec![Ipv4Addr::new(127, 0, 0, 1).into()],
+        port,
+        max_cname_chain: 3,
+        ..Default::default()
+    };
+
+    let response = query_a(&LRUCache::new(100), &config, "c1.a.test");
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert_eq!(response.get_all_a(), vec![Ipv4Addr::new(192, 0, 2, 7)]);
+
+    let config = ResolverConfig {
+        max_cname_chain: 2,
+        ..config
+    };
+    let response = query_a(&LRUCache::new(100), &config, "c1.a.test");
+    assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+}
+
+#[test]
+fn test_recursive_lookup_follows_dnames() {
+    let port = free_port();
+    let target = ["x".repeat(60), "x".repeat(60), "x".repeat(60)].join(".") + ".b.test";
+    let root = Zone::new(
+        "",
+        vec![
+            ns("a.test", "ns.a.test"),
+            a("ns.a.test", "127.0.0.2"),
+            ns("b.test", "ns.b.test"),
+            a("ns.b.test", "127.0.0.3"),
+        ],
+    );
+    let zone_a = Zone::new(
+        "a.test",
+        vec![
+            ns("a.test", "ns.a.test"),
+            a("ns.a.test", "127.0.0.2"),
+            dname("old.a.test", "new.b.test"),
+            dname("long.a.test", &target),
+        ],
+    );
+    let zone_b = Zone::new(
+        "b.test",
+        vec![
+            ns("b.test", "ns.b.test"),
+            a("ns.b.test", "127.0.0.3"),
+            a("www.new.b.test", "192.0.2.7"),
+        ],
+    );
+    serve(Ipv4Addr::new(127, 0, 0, 1), port, vec![root]);
+    serve(Ipv4Addr::new(127, 0, 0, 2), port, vec![zone_a]);
+    serve(Ipv4Addr::new(127, 0, 0, 3), port, vec![zone_b]);
+    let config = ResolverConfig {
+        root_servers: vec![Ipv4Addr::new(127, 0, 0, 1).into()],
+        port,
+        ..Default::default()
+    };
+
+    // Clients get the DNAME and the CNAME it implies ahead of the answer
+    let response = query_a(&LRUCache::new(100), &config, "www.old.a.test");
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert_eq!(
+        response.answers,
+        vec![
+            dname("old.a.test", "new.b.test"),
+            cname("www.old.a.test", "www.new.b.test"),
+            a("www.new.b.test", "192.0.2.7"),
+        ]
+    );
+
+    // Substitution would make this name longer than 255 octets
+    let name = format!("{}.{}.long.a.test", "z".repeat(10), "y".repeat(60));
+    let response = query_a(&LRUCache::new(100), &config, &name);
+    assert_eq!(response.header.rescode, ResultCode::YXDOMAIN);
+    assert_eq!(response.answers, vec![dname("long.a.test", &target)]);
+}
+
+#[test]
+fn test_recursive_lookup_ignores_out_of_bailiwick_referrals() {
+    let port = free_port();
+    let root = Zone::new(
+        "",
+        vec![
+            ns("evil.test", "ns.evil.test"),
+            a("ns.evil.test", "127.0.0.2"),
+            ns("example.test", "ns.example.test"),
+            a("ns.example.test", "127.0.0.3"),
+        ],
+    );
+    let zone = Zone::new(
+        "example.test",
+        vec![
+            ns("example.test", "ns.example.test"),
+            a("www.example.test", "192.0.2.1"),
+        ],
+    );
+    serve(Ipv4Addr::new(127, 0, 0, 1), port, vec![root]);
+    serve(Ipv4Addr::new(127, 0, 0, 3), port, vec![zone]);
+    // Answers everything, and claims example.test and the zone above it along the way
+    let evil = UdpSocket::bind((Ipv4Addr::new(127, 0, 0, 2), port)).unwrap();
+    thread::spawn(move || loop {
+        let mut buffer = BytePacketBuffer::default();
+        let Ok((_, src)) = evil.recv_from(&mut buffer.buf) else {
+            break;
+        };
+        let request = DnsPacket::from_buffer(&mut buffer).unwrap();
+        let mut response = response_to(&request);
+        response.header.authoritative_answer = true;
+        response.answers = vec![a(&request.questions[0].name, "192.0.2.66")];
+        response.authorities = vec![
+            ns("example.test", "ns.evil.test"),
+            ns("test", "ns.evil.test"),
+        ];
+        response.resources = vec![
+            a("ns.evil.test", "127.0.0.2"),
+            a("ns.example.test", "127.0.0.2"),
+        ];
+        response.header.answers = 1;
+        response.header.authoritative_entries = 2;
+        response.header.resource_entries = 2;
+        evil.send_to(&write_packet(&response), src).unwrap();
+    });
+    let config = ResolverConfig {
+        root_servers: vec![Ipv4Addr::new(127, 0, 0, 1).into()],
+        port,
+        ..Default::default()
+    };
+
+    let cache = LRUCache::new(100);
+    let response = query_a(&cache, &config, "www.evil.test");
+    assert_eq!(response.get_all_a(), vec![Ipv4Addr::new(192, 0, 2, 66)]);
+
+    let response = query_a(&cache, &config, "www.example.test");
+    assert_eq!(response.get_all_a(), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+}
+
+#[test]
+fn test_recursive_lookup_ignores_out_of_bailiwick_answers() {
+    let port = free_port();
+    let root = Zone::new(
+        "",
+        vec![
+            ns("a.test", "ns.a.test"),
+            a("ns.a.test", "127.0.0.2"),
+            ns("b.test", "ns.b.test"),
+            a("ns.b.test", "127.0.0.3"),
+        ],
+    );
+    let zone_b = Zone::new(
+        "b.test",
+        vec![ns("b.test", "ns.b.test"), a("www.b.test", "192.0.2.7")],
+    );
+    serve(Ipv4Addr::new(127, 0, 0, 1), port, vec![root]);
+    serve(Ipv4Addr::new(127, 0, 0, 3), port, vec![zone_b]);
+    // Aliases into b.test and answers for the target itself, along with a stray record
+    let evil = UdpSocket::bind((Ipv4Addr::new(127, 0, 0, 2), port)).unwrap();
+    thread::spawn(move || loop {
+        let mut buffer = BytePacketBuffer::default();
+        let Ok((_, src)) = evil.recv_from(&mut buffer.buf) else {
+            break;
+        };
+        let request = DnsPacket::from_buffer(&mut buffer).unwrap();
+        let mut response = response_to(&request);
+        response.header.authoritative_answer = true;
+        response.answers = vec![
+            cname(&request.questions[0].name, "www.b.test"),
+            a("www.b.test", "192.0.2.66"),
+            a("mail.b.test", "192.0.2.66"),
+        ];
+        response.header.answers = 3;
+        evil.send_to(&write_packet(&response), src).unwrap();
+    });
+    let config = ResolverConfig {
+        root_servers: vec![Ipv4Addr::new(127, 0, 0, 1).into()],
+        port,
+        ..Default::default()
+    };
+
+    let cache = LRUCache::new(100);
+    let response = query_a(&cache, &config, "www.a.test");
+    assert_eq!(
+        response.answers,
+        vec![
+            cname("www.a.test", "www.b.test"),
+            a("www.b.test", "192.0.2.7")
+        ]
+    );
+    assert!(cache
+        .get(&DnsQuestion::new("mail.b.test".to_string(), QueryType::A))
+        .is_none());
+}
+
+type Asked = Arc<Mutex<Vec<(String, QueryType)>>>;
+
+// Serves the zones over UDP, keeping every question asked. Broken servers answer
+// NXDOMAIN for names that only exist because there are names below them.
+fn serve_recording(ip: Ipv4Addr, port: u16, zones: Vec<Zone>, broken: bool) -> Asked {
+    let questions = Arc::new(Mutex::new(Vec::new()));
+    let recorded = questions.clone();
+    let socket = UdpSocket::bind((ip, port)).unwrap();
+    thread::spawn(move || loop {
+        let mut buffer = BytePacketBuffer::default();
+        let Ok((_, src)) = socket.recv_from(&mut buffer.buf) else {
+            break;
+        };
+        let request = DnsPacket::from_buffer(&mut buffer).unwrap();
+        let question = &request.questions[0];
+        recorded
+            .lock()
+            .unwrap()
+            .push((question.name.to_lowercase(), question.qtype));
+        let mut response = answer(&zones, &request);
+        let empty_non_terminal = response.header.rescode == ResultCode::NOERROR
+            && response.answers.is_empty()
+            && response
+                .authorities
+                .iter()
+                .all(|r| r.qtype() != QueryType::NS)
+            && !zones
+                .iter()
+                .flat_map(|zone| &zone.records)
+                .any(|r| r.domain() == question.name);
+        if broken && empty_non_terminal {
+            response.header.rescode = ResultCode::NXDOMAIN;
+        }
+        socket.send_to(&write_packet(&response), src).unwrap();
+    });
+    questions
+}
+
+// The root, test and example.test, with www.a.b.example.test below two empty non-terminals
+fn serve_minimisation_hierarchy(port: u16, broken: bool) -> [Asked; 3] {
+    let root = Zone::new("", vec![ns("test", "ns.test"), a("ns.test", "127.0.0.2")]);
+    let tld = Zone::new(
+        "test",
+        vec![
+            ns("test", "ns.test"),
+            a("ns.test", "127.0.0.2"),
+            ns("example.test", "ns.example.test"),
+            a("ns.example.test", "127.0.0.3"),
+        ],
+    );
+    let zone = Zone::new(
+        "example.test",
+        vec![
+            ns("example.test", "ns.example.test"),
+            a("ns.example.test", "127.0.0.3"),
+            aaaa("www.a.b.example.test", "2001:db8::1"),
+        ],
+    );
+    [
+        serve_recording(Ipv4Addr::new(127, 0, 0, 1), port, vec![root], false),
+        serve_recording(Ipv4Addr::new(127, 0, 0, 2), port, vec![tld], false),
+        serve_recording(Ipv4Addr::new(127, 0, 0, 3), port, vec![zone], broken),
+    ]
+}
+
+fn asked(questions: &Asked) -> Vec<(String, QueryType)> {
+    questions.lock().unwrap().clone()
+}
+
+fn minimising_config(port: u16) -> ResolverConfig {
+    ResolverConfig {
+        root_servers: vec![Ipv4Addr::new(127, 0, 0, 1).into()],
+        port,
+        qname_minimisation: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_qname_minimisation_reveals_one_label_per_step() {
+    let port = free_port();
+    let [root, tld, zone] = serve_minimisation_hierarchy(port, false);
+    let config = minimising_config(port);
+
+    let response = recursive_lookup(
+        &config,
+        &LRUCache::new(100),
+        &InfraCache::new(),
+        "www.a.b.example.test",
+        QueryType::AAAA,
+    )
+    .unwrap();
+    assert_eq!(
+        response.answers,
+        vec![aaaa("www.a.b.example.test", "2001:db8::1")]
+    );
+
+    assert_eq!(asked(&root), vec![("test".to_string(), QueryType::A)]);
+    assert_eq!(
+        asked(&tld),
+        vec![("example.test".to_string(), QueryType::A)]
+    );
+    // Within the zone, labels are still added one at a time until the name is complete
+    assert_eq!(
+        asked(&zone),
+        vec![
+            ("b.example.test".to_string(), QueryType::A),
+            ("a.b.example.test".to_string(), QueryType::A),
+            ("www.a.b.example.test".to_string(), QueryType::AAAA),
+        ]
+    );
+}
+
+#[test]
+fn test_qname_minimisation_falls_back_on_nxdomain() {
+    let port = free_port();
+    let [_, _, zone] = serve_minimisation_hierarchy(port, true);
+    let config = minimising_config(port);
+    let cache = LRUCache::new(100);
+    let infra = InfraCache::new();
+
+    let response = recursive_lookup(
+        &config,
+        &cache,
+        &infra,
+        "www.a.b.example.test",
+        QueryType::AAAA,
+    )
+    .unwrap();
+    assert_eq!(
+        response.answers,
+        vec![aaaa("www.a.b.example.test", "2001:db8::1")]
+    );
+    // The denial of an empty non-terminal isn't believed, the whole name is asked for
+    assert_eq!(
+        asked(&zone),
+        vec![
+            ("b.example.test".to_string(), QueryType::A),
+            ("www.a.b.example.test".to_string(), QueryType::AAAA),
+        ]
+    );
+
+    // Names that really don't exist still don't
+    let response = recursive_lookup(
+        &config,
+        &cache,
+        &infra,
+        "www.nothere.example.test",
+        QueryType::AAAA,
+    )
+    .unwrap();
+    assert_eq!(response.header.rescode, ResultCode::NXDOMAIN);
+}
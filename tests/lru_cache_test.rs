@@ -1,12 +1,43 @@
-use std::{thread, time::Duration};
+use std::{net::Ipv4Addr, sync::Arc, thread, time::Duration};
 
-use rdns_resolver_rs::{dns_packet::DnsPacket, dns_record::DnsRecord, lru_cache::LRUCache};
+use rdns_resolver_rs::{
+    dns_packet::DnsPacket, dns_question::DnsQuestion, dns_record::DnsRecord, lru_cache::LRUCache,
+    query_class::QueryClass, query_type::QueryType, res_code::ResultCode,
+};
 
 fn sample_dns_packet(ttl: u32) -> DnsPacket {
+    packet_for("example.com", ttl)
+}
+
+fn packet_for(domain: &str, ttl: u32) -> DnsPacket {
+    DnsPacket {
+        answers: vec![DnsRecord::A {
+            domain: domain.to_string(),
+            addr: "93.184.216.34".parse().unwrap(),
+            class: QueryClass::IN,
+            ttl,
+        }],
+        ..Default::default()
+    }
+}
+
+fn question(name: &str, qtype: QueryType) -> DnsQuestion {
+    DnsQuestion::new(name.to_string(), qtype)
+}
+
+fn sample_negative_packet(rescode: ResultCode, ttl: u32, minimum: u32) -> DnsPacket {
     let mut packet = DnsPacket::default();
-    packet.answers = vec![DnsRecord::A {
+    packet.header.rescode = rescode;
+    packet.authorities = vec![DnsRecord::SOA {
         domain: "example.com".to_string(),
-        addr: "93.184.216.34".parse().unwrap(),
+        mname: "ns1.example.com".to_string(),
+        rname: "hostmaster.example.com".to_string(),
+        serial: 1,
+        refresh: 7200,
+        retry: 3600,
+        expire: 1209600,
+        minimum,
+        class: QueryClass::IN,
         ttl,
     }];
     packet
@@ -14,8 +45,8 @@ fn sample_dns_packet(ttl: u32) -> DnsPacket {
 
 #[test]
 fn test_put_and_get() {
-    let mut cache = LRUCache::new(2);
-    let key = "example.com".to_string();
+    let cache = LRUCache::new(2);
+    let key = question("example.com", QueryType::A);
     let packet = sample_dns_packet(10);
 
     cache.put(&key, &packet);
@@ -27,20 +58,59 @@ fn test_put_and_get() {
 
 #[test]
 fn test_lru_eviction() {
-    let mut cache = LRUCache::new(2);
-    cache.put(&"key1".to_string(), &sample_dns_packet(10));
-    cache.put(&"key2".to_string(), &sample_dns_packet(10));
-    cache.put(&"key3".to_string(), &sample_dns_packet(10));
+    let cache = LRUCache::new(2);
+    cache.put(&question("key1", QueryType::A), &packet_for("key1", 10));
+    cache.put(&question("key2", QueryType::A), &packet_for("key2", 10));
+    cache.put(&question("key3", QueryType::A), &packet_for("key3", 10));
 
-    assert!(cache.get(&"key1".to_string()).is_none());
-    assert!(cache.get(&"key2".to_string()).is_some());
-    assert!(cache.get(&"key3".to_string()).is_some());
+    assert!(cache.get(&question("key1", QueryType::A)).is_none());
+    assert!(cache.get(&question("key2", QueryType::A)).is_some());
+    assert!(cache.get(&question("key3", QueryType::A)).is_some());
+}
+
+#[test]
+fn test_get_refreshes_recency() {
+    let cache = LRUCache::new(2);
+    cache.put(&question("key1", QueryType::A), &packet_for("key1", 10));
+    cache.put(&question("key2", QueryType::A), &packet_for("key2", 10));
+    assert!(cache.get(&question("key1", QueryType::A)).is_some());
+    cache.put(&question("key3", QueryType::A), &packet_for("key3", 10));
+
+    assert!(cache.get(&question("key1", QueryType::A)).is_some());
+    assert!(cache.get(&question("key2", QueryType::A)).is_none());
+    assert!(cache.get(&question("key3", QueryType::A)).is_some());
+}
+
+#[test]
+fn test_sharded_cache_shared_between_threads() {
+    let cache = Arc::new(LRUCache::with_shards(8000, 8));
+    let threads: Vec<_> = (0..8)
+        .map(|t| {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                for i in 0..100 {
+                    let name = format!("host{}-{}.example.com", t, i);
+                    cache.put(&question(&name, QueryType::A), &packet_for(&name, 10));
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    for t in 0..8 {
+        for i in 0..100 {
+            let name = format!("host{}-{}.example.com", t, i);
+            assert!(cache.get(&question(&name, QueryType::A)).is_some());
+        }
+    }
 }
 
 #[test]
 fn test_ttl_expiry() {
-    let mut cache = LRUCache::new(2);
-    let key = "example.com".to_string();
+    let cache = LRUCache::new(2);
+    let key = question("example.com", QueryType::A);
     cache.put(&key, &sample_dns_packet(1));
 
     thread::sleep(Duration::from_secs(1));
@@ -51,8 +121,8 @@ fn test_ttl_expiry() {
 
 #[test]
 fn test_update_existing_key() {
-    let mut cache = LRUCache::new(2);
-    let key = "example.com".to_string();
+    let cache = LRUCache::new(2);
+    let key = question("example.com", QueryType::A);
     cache.put(&key, &sample_dns_packet(10));
     cache.put(&key, &sample_dns_packet(20));
 
@@ -62,10 +132,419 @@ fn test_update_existing_key() {
 
 #[test]
 fn test_remove_key() {
-    let mut cache = LRUCache::new(2);
-    let key = "example.com".to_string();
+    let cache = LRUCache::new(2);
+    let key = question("example.com", QueryType::A);
     cache.put(&key, &sample_dns_packet(10));
     cache.remove(&key);
 
     assert!(cache.get(&key).is_none());
 }
+
+#[test]
+fn test_negative_caching() {
+    let cache = LRUCache::new(2);
+    let nxdomain = question("missing.example.com", QueryType::A);
+    let nodata = question("example.com", QueryType::A);
+    cache.put(
+        &nxdomain,
+        &sample_negative_packet(ResultCode::NXDOMAIN, 3600, 300),
+    );
+    cache.put(
+        &nodata,
+        &sample_negative_packet(ResultCode::NOERROR, 60, 300),
+    );
+
+    let retrieved = cache.get(&nxdomain).unwrap();
+    assert_eq!(retrieved.header.rescode, ResultCode::NXDOMAIN);
+    assert_eq!(retrieved.authorities.len(), 1);
+    assert_eq!(retrieved.authorities[0].ttl(), 300);
+
+    let retrieved = cache.get(&nodata).unwrap();
+    assert_eq!(retrieved.header.rescode, ResultCode::NOERROR);
+    assert_eq!(retrieved.authorities[0].ttl(), 60);
+}
+
+#[test]
+fn test_negative_caching_expiry() {
+    let cache = LRUCache::new(2);
+    let key = question("missing.example.com", QueryType::A);
+    cache.put(&key, &sample_negative_packet(ResultCode::NXDOMAIN, 3600, 1));
+    assert!(cache.get(&key).is_some());
+
+    thread::sleep(Duration::from_secs(1));
+    assert!(cache.get(&key).is_none());
+}
+
+#[test]
+fn test_negative_without_soa_not_cached() {
+    let cache = LRUCache::new(2);
+    let key = question("missing.example.com", QueryType::A);
+    let mut packet = DnsPacket::default();
+    packet.header.rescode = ResultCode::NXDOMAIN;
+    cache.put(&key, &packet);
+
+    assert!(cache.get(&key).is_none());
+}
+
+#[test]
+fn test_types_cached_independently() {
+    let cache = LRUCache::new(10);
+    let mut mx_packet = DnsPacket::default();
+    mx_packet.answers.push(DnsRecord::MX {
+        domain: "example.com".to_string(),
+        priority: 10,
+        host: "mail.example.com".to_string(),
+        class: QueryClass::IN,
+        ttl: 10,
+    });
+    cache.put(
+        &question("example.com", QueryType::A),
+        &sample_dns_packet(10),
+    );
+    cache.put(&question("example.com", QueryType::MX), &mx_packet);
+
+    let a = cache.get(&question("example.com", QueryType::A)).unwrap();
+    assert!(matches!(a.answers[0], DnsRecord::A { .. }));
+    let mx = cache.get(&question("example.com", QueryType::MX)).unwrap();
+    assert!(matches!(mx.answers[0], DnsRecord::MX { .. }));
+    assert!(cache
+        .get(&question("example.com", QueryType::AAAA))
+        .is_none());
+
+    cache.remove(&question("example.com", QueryType::A));
+    assert!(cache.get(&question("example.com", QueryType::A)).is_none());
+    assert!(cache.get(&question("example.com", QueryType::MX)).is_some());
+}
+
+#[test]
+fn test_names_match_case_insensitively() {
+    let cache = LRUCache::new(10);
+    cache.put(
+        &question("Example.COM", QueryType::A),
+        &sample_dns_packet(10),
+    );
+
+    assert!(cache.get(&question("example.com", QueryType::A)).is_some());
+    assert!(cache.get(&question("EXAMPLE.com", QueryType::A)).is_some());
+}
+
+#[test]
+fn test_classes_cached_independently() {
+    let cache = LRUCache::new(10);
+    cache.put(
+        &question("example.com", QueryType::A),
+        &sample_dns_packet(10),
+    );
+    let chaos = DnsQuestion {
+        qclass: QueryClass::CH,
+        ..question("example.com", QueryType::A)
+    };
+
+    assert!(cache.get(&chaos).is_none());
+}
+
+#[test]
+fn test_ttl_counts_down() {
+    let cache = LRUCache::new(10);
+    let key = question("example.com", QueryType::A);
+    cache.put(&key, &sample_dns_packet(10));
+    cache.put(
+        &question("missing.example.com", QueryType::A),
+        &sample_negative_packet(ResultCode::NXDOMAIN, 3600, 300),
+    );
+    assert_eq!(cache.get(&key).unwrap().answers[0].ttl(), 10);
+
+    thread::sleep(Duration::from_secs(1));
+    assert_eq!(cache.get(&key).unwrap().answers[0].ttl(), 9);
+    let negative = cache
+        .get(&question("missing.example.com", QueryType::A))
+        .unwrap();
+    assert_eq!(negative.authorities[0].ttl(), 299);
+}
+
+#[test]
+fn test_answers_are_assembled_from_rrsets() {
+    let cache = LRUCache::new(10);
+    let mut packet = packet_for("www.example.com", 20);
+    packet.answers.insert(
+        0,
+        DnsRecord::CNAME {
+            domain: "alias.example.com".to_string(),
+            host: "www.example.com".to_string(),
+            class: QueryClass::IN,
+            ttl: 10,
+        },
+    );
+    cache.put(&question("alias.example.com", QueryType::A), &packet);
+
+    // Each RRset keeps its own TTL
+    let retrieved = cache
+        .get(&question("alias.example.com", QueryType::A))
+        .unwrap();
+    assert_eq!(retrieved.answers, packet.answers);
+    assert_eq!(retrieved.header.answers, 2);
+    let retrieved = cache
+        .get(&question("www.example.com", QueryType::A))
+        .unwrap();
+    assert_eq!(retrieved.answers, packet.answers[1..]);
+    let retrieved = cache
+        .get(&question("alias.example.com", QueryType::CNAME))
+        .unwrap();
+    assert_eq!(retrieved.answers, packet.answers[..1]);
+}
+
+#[test]
+fn test_records_off_the_chain_are_not_cached() {
+    let cache = LRUCache::new(10);
+    let mut packet = packet_for("www.example.com", 20);
+    packet.answers.insert(
+        0,
+        DnsRecord::CNAME {
+            domain: "alias.example.com".to_string(),
+            host: "www.example.com".to_string(),
+            class: QueryClass::IN,
+            ttl: 60,
+        },
+    );
+    packet.answers.push(DnsRecord::A {
+        domain: "bank.example.net".to_string(),
+        addr: "192.0.2.66".parse().unwrap(),
+        class: QueryClass::IN,
+        ttl: 3600,
+    });
+    cache.put(&question("alias.example.com", QueryType::A), &packet);
+
+    assert!(cache
+        .get(&question("www.example.com", QueryType::A))
+        .is_some());
+    assert!(cache
+        .get(&question("bank.example.net", QueryType::A))
+        .is_none());
+    let retrieved = cache
+        .get(&question("alias.example.com", QueryType::A))
+        .unwrap();
+    assert_eq!(retrieved.answers, packet.answers[..2]);
+}
+
+#[test]
+fn test_dnames_above_the_chain_are_cached() {
+    let cache = LRUCache::new(10);
+    let mut packet = packet_for("www.example.net", 20);
+    packet.answers.insert(
+        0,
+        DnsRecord::CNAME {
+            domain: "www.example.com".to_string(),
+            host: "www.example.net".to_string(),
+            class: QueryClass::IN,
+            ttl: 60,
+        },
+    );
+    for domain in ["example.com", "example.org"] {
+        packet.answers.insert(
+            0,
+            DnsRecord::DNAME {
+                domain: domain.to_string(),
+                target: "example.net".to_string(),
+                class: QueryClass::IN,
+                ttl: 60,
+            },
+        );
+    }
+    cache.put(&question("www.example.com", QueryType::A), &packet);
+
+    assert!(cache
+        .get(&question("example.com", QueryType::DNAME))
+        .is_some());
+    assert!(cache
+        .get(&question("example.org", QueryType::DNAME))
+        .is_none());
+}
+
+#[test]
+fn test_broken_chains_are_not_served() {
+    let cache = LRUCache::new(10);
+    let mut packet = packet_for("www.example.com", 20);
+    packet.answers.insert(
+        0,
+        DnsRecord::CNAME {
+            domain: "alias.example.com".to_string(),
+            host: "www.example.com".to_string(),
+            class: QueryClass::IN,
+            ttl: 60,
+        },
+    );
+    cache.put(&question("alias.example.com", QueryType::A), &packet);
+
+    // Without the RRset at the end of the alias there is nothing to answer with
+    cache.remove(&question("www.example.com", QueryType::A));
+    assert!(cache
+        .get(&question("alias.example.com", QueryType::A))
+        .is_none());
+    assert!(cache
+        .get(&question("alias.example.com", QueryType::CNAME))
+        .is_some());
+}
+
+#[test]
+fn test_chains_longer_than_the_configured_limit_are_not_served() {
+    let mut packet = packet_for("www.example.com", 20);
+    for (domain, host) in [
+        ("c.example.com", "www.example.com"),
+        ("b.example.com", "c.example.com"),
+        ("a.example.com", "b.example.com"),
+    ] {
+        packet.answers.insert(
+            0,
+            DnsRecord::CNAME {
+                domain: domain.to_string(),
+                host: host.to_string(),
+                class: QueryClass::IN,
+                ttl: 60,
+            },
+        );
+    }
+
+    let cache = LRUCache::new(10).with_max_cname_chain(3);
+    cache.put(&question("a.example.com", QueryType::A), &packet);
+    assert_eq!(
+        cache
+            .get(&question("a.example.com", QueryType::A))
+            .unwrap()
+            .answers,
+        packet.answers
+    );
+
+    let cache = LRUCache::new(10).with_max_cname_chain(2);
+    cache.put(&question("a.example.com", QueryType::A), &packet);
+    assert!(cache
+        .get(&question("a.example.com", QueryType::A))
+        .is_none());
+    assert!(cache
+        .get(&question("b.example.com", QueryType::A))
+        .is_none());
+}
+
+#[test]
+fn test_delegations_steer_recursion() {
+    let cache = LRUCache::new(10);
+    let mut referral = DnsPacket::default();
+    referral.authorities.push(DnsRecord::NS {
+        domain: "example.com".to_string(),
+        host: "ns.example.com".to_string(),
+        class: QueryClass::IN,
+        ttl: 3600,
+    });
+    referral.resources.push(DnsRecord::A {
+        domain: "ns.example.com".to_string(),
+        addr: Ipv4Addr::new(192, 0, 2, 53),
+        class: QueryClass::IN,
+        ttl: 3600,
+    });
+    cache.put_delegation(&referral, "www.example.com", "com");
+
+    assert_eq!(
+        cache.closest_nameservers("www.example.com"),
+        (
+            "example.com".to_string(),
+            vec![Ipv4Addr::new(192, 0, 2, 53).into()]
+        )
+    );
+    assert_eq!(
+        cache.closest_nameservers("example.org"),
+        (String::new(), Vec::new())
+    );
+    // Referral data is never served as an answer
+    assert!(cache
+        .get(&question("ns.example.com", QueryType::A))
+        .is_none());
+
+    // Nor does it replace an answer
+    cache.put(
+        &question("ns.example.com", QueryType::A),
+        &packet_for("ns.example.com", 60),
+    );
+    cache.put_delegation(&referral, "www.example.com", "com");
+    let retrieved = cache
+        .get(&question("ns.example.com", QueryType::A))
+        .unwrap();
+    assert_eq!(retrieved.answers[0].ttl(), 60);
+}
+
+#[test]
+fn test_nameserver_addresses_steer_recursion() {
+    let cache = LRUCache::new(10);
+    let mut referral = DnsPacket::default();
+    referral.authorities.push(DnsRecord::NS {
+        domain: "example.com".to_string(),
+        host: "ns.example.net".to_string(),
+        class: QueryClass::IN,
+        ttl: 3600,
+    });
+    cache.put_delegation(&referral, "www.example.com", "com");
+    assert_eq!(
+        cache.closest_nameservers("www.example.com"),
+        (String::new(), Vec::new())
+    );
+
+    let mut response = packet_for("ns.example.net", 3600);
+    response.answers.push(DnsRecord::A {
+        domain: "other.example.net".to_string(),
+        addr: Ipv4Addr::new(192, 0, 2, 66),
+        class: QueryClass::IN,
+        ttl: 3600,
+    });
+    cache.put_nameserver("ns.example.net", &response);
+
+    assert_eq!(
+        cache.closest_nameservers("www.example.com"),
+        (
+            "example.com".to_string(),
+            vec!["93.184.216.34".parse().unwrap()]
+        )
+    );
+    // Like glue, the addresses are only used for recursion
+    assert!(cache
+        .get(&question("ns.example.net", QueryType::A))
+        .is_none());
+}
+
+#[test]
+fn test_delegations_outside_the_zone_are_not_cached() {
+    let cache = LRUCache::new(10);
+    // The servers for evil.com answering for www.evil.com, trying to take over other names
+    let mut referral = DnsPacket::default();
+    for (domain, host) in [
+        ("example.com", "ns.evil.com"),
+        ("www.evil.com", "ns.example.com"),
+        ("com", "ns.evil.com"),
+    ] {
+        referral.authorities.push(DnsRecord::NS {
+            domain: domain.to_string(),
+            host: host.to_string(),
+            class: QueryClass::IN,
+            ttl: 3600,
+        });
+    }
+    for (domain, addr) in [
+        ("ns.evil.com", Ipv4Addr::new(192, 0, 2, 66)),
+        ("ns.example.com", Ipv4Addr::new(192, 0, 2, 67)),
+    ] {
+        referral.resources.push(DnsRecord::A {
+            domain: domain.to_string(),
+            addr,
+            class: QueryClass::IN,
+            ttl: 3600,
+        });
+    }
+    cache.put_delegation(&referral, "www.evil.com", "evil.com");
+
+    assert_eq!(
+        cache.closest_nameservers("www.example.com"),
+        (String::new(), Vec::new())
+    );
+    // The one delegation they can make names a server they aren't authoritative for
+    assert_eq!(
+        cache.closest_nameservers("www.evil.com"),
+        (String::new(), Vec::new())
+    );
+}
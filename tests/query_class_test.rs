@@ -0,0 +1,35 @@
+use rdns_resolver_rs::query_class::QueryClass;
+
+#[test]
+fn test_to_num() {
+    assert_eq!(QueryClass::IN.to_num(), 1);
+    assert_eq!(QueryClass::CH.to_num(), 3);
+    assert_eq!(QueryClass::HS.to_num(), 4);
+    assert_eq!(QueryClass::NONE.to_num(), 254);
+    assert_eq!(QueryClass::ANY.to_num(), 255);
+
+    let unknown_class = QueryClass::UNKNOWN(42);
+    assert_eq!(unknown_class.to_num(), 42);
+}
+
+#[test]
+fn test_from_num() {
+    assert_eq!(QueryClass::from_num(1), QueryClass::IN);
+    assert_eq!(QueryClass::from_num(3), QueryClass::CH);
+    assert_eq!(QueryClass::from_num(4), QueryClass::HS);
+    assert_eq!(QueryClass::from_num(254), QueryClass::NONE);
+    assert_eq!(QueryClass::from_num(255), QueryClass::ANY);
+
+    let unknown_class = QueryClass::from_num(42);
+    match unknown_class {
+        QueryClass::UNKNOWN(num) => assert_eq!(num, 42),
+        _ => panic!("Expected QueryClass::UNKNOWN(42)"),
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    for num in 0..=u16::MAX {
+        assert_eq!(QueryClass::from_num(num).to_num(), num);
+    }
+}
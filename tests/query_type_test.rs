@@ -5,8 +5,23 @@ fn test_to_num() {
     assert_eq!(QueryType::A.to_num(), 1);
     assert_eq!(QueryType::NS.to_num(), 2);
     assert_eq!(QueryType::CNAME.to_num(), 5);
+    assert_eq!(QueryType::SOA.to_num(), 6);
+    assert_eq!(QueryType::PTR.to_num(), 12);
     assert_eq!(QueryType::MX.to_num(), 15);
+    assert_eq!(QueryType::TXT.to_num(), 16);
     assert_eq!(QueryType::AAAA.to_num(), 28);
+    assert_eq!(QueryType::SRV.to_num(), 33);
+    assert_eq!(QueryType::DNAME.to_num(), 39);
+    assert_eq!(QueryType::OPT.to_num(), 41);
+    assert_eq!(QueryType::DS.to_num(), 43);
+    assert_eq!(QueryType::RRSIG.to_num(), 46);
+    assert_eq!(QueryType::NSEC.to_num(), 47);
+    assert_eq!(QueryType::DNSKEY.to_num(), 48);
+    assert_eq!(QueryType::NSEC3.to_num(), 50);
+    assert_eq!(QueryType::NSEC3PARAM.to_num(), 51);
+    assert_eq!(QueryType::SVCB.to_num(), 64);
+    assert_eq!(QueryType::HTTPS.to_num(), 65);
+    assert_eq!(QueryType::CAA.to_num(), 257);
 
     let unknown_query = QueryType::UNKNOWN(99);
     assert_eq!(unknown_query.to_num(), 99);
@@ -17,8 +32,23 @@ fn test_from_num() {
     assert_eq!(QueryType::from_num(1), QueryType::A);
     assert_eq!(QueryType::from_num(2), QueryType::NS);
     assert_eq!(QueryType::from_num(5), QueryType::CNAME);
+    assert_eq!(QueryType::from_num(6), QueryType::SOA);
+    assert_eq!(QueryType::from_num(12), QueryType::PTR);
     assert_eq!(QueryType::from_num(15), QueryType::MX);
+    assert_eq!(QueryType::from_num(16), QueryType::TXT);
     assert_eq!(QueryType::from_num(28), QueryType::AAAA);
+    assert_eq!(QueryType::from_num(33), QueryType::SRV);
+    assert_eq!(QueryType::from_num(39), QueryType::DNAME);
+    assert_eq!(QueryType::from_num(41), QueryType::OPT);
+    assert_eq!(QueryType::from_num(43), QueryType::DS);
+    assert_eq!(QueryType::from_num(46), QueryType::RRSIG);
+    assert_eq!(QueryType::from_num(47), QueryType::NSEC);
+    assert_eq!(QueryType::from_num(48), QueryType::DNSKEY);
+    assert_eq!(QueryType::from_num(50), QueryType::NSEC3);
+    assert_eq!(QueryType::from_num(51), QueryType::NSEC3PARAM);
+    assert_eq!(QueryType::from_num(64), QueryType::SVCB);
+    assert_eq!(QueryType::from_num(65), QueryType::HTTPS);
+    assert_eq!(QueryType::from_num(257), QueryType::CAA);
 
     let unknown_query = QueryType::from_num(99);
     match unknown_query {
@@ -41,6 +71,7 @@ fn test_equality() {
 }
 
 #[test]
+#[allow(clippy::clone_on_copy)]
 fn test_clone() {
     let query = QueryType::MX;
     let cloned_query = query.clone();
@@ -8,6 +8,16 @@ fn test_from_num() {
     assert_eq!(ResultCode::from_num(3), ResultCode::NXDOMAIN);
     assert_eq!(ResultCode::from_num(4), ResultCode::NOTIMP);
     assert_eq!(ResultCode::from_num(5), ResultCode::REFUSED);
-    assert_eq!(ResultCode::from_num(6), ResultCode::NOERROR);
-    assert_eq!(ResultCode::from_num(255), ResultCode::NOERROR);
+    assert_eq!(ResultCode::from_num(6), ResultCode::YXDOMAIN);
+    assert_eq!(ResultCode::from_num(7), ResultCode::UNKNOWN(7));
+    assert_eq!(ResultCode::from_num(16), ResultCode::BADVERS);
+    assert_eq!(ResultCode::from_num(23), ResultCode::UNKNOWN(23));
+    assert_eq!(ResultCode::from_num(255), ResultCode::UNKNOWN(255));
+}
+
+#[test]
+fn test_to_num() {
+    for num in [0, 1, 2, 3, 4, 5, 6, 16, 23, 4095] {
+        assert_eq!(ResultCode::from_num(num).to_num(), num);
+    }
 }
@@ -0,0 +1,102 @@
+use rdns_resolver_rs::{byte_packet_buffer::BytePacketBuffer, svc_param::SvcParam};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+fn round_trip(param: &SvcParam) -> SvcParam {
+    let mut buffer = BytePacketBuffer::default();
+    param.write(&mut buffer).unwrap();
+    buffer.seek(0).unwrap();
+    SvcParam::read(&mut buffer).unwrap()
+}
+
+#[test]
+fn test_key() {
+    assert_eq!(SvcParam::Mandatory(vec![]).key(), 0);
+    assert_eq!(SvcParam::Alpn(vec![]).key(), 1);
+    assert_eq!(SvcParam::NoDefaultAlpn.key(), 2);
+    assert_eq!(SvcParam::Port(443).key(), 3);
+    assert_eq!(SvcParam::Ipv4Hint(vec![]).key(), 4);
+    assert_eq!(SvcParam::Ech(vec![]).key(), 5);
+    assert_eq!(SvcParam::Ipv6Hint(vec![]).key(), 6);
+    let unknown = SvcParam::Unknown {
+        key: 667,
+        value: vec![],
+    };
+    assert_eq!(unknown.key(), 667);
+}
+
+#[test]
+fn test_round_trip() {
+    let params = vec![
+        SvcParam::Mandatory(vec![1, 4]),
+        SvcParam::Alpn(vec![b"h2".to_vec(), b"h3".to_vec()]),
+        // Not UTF-8, which alpn ids don't have to be
+        SvcParam::Alpn(vec![vec![0xff, 0xfe, b'x']]),
+        SvcParam::NoDefaultAlpn,
+        SvcParam::Port(8443),
+        SvcParam::Ipv4Hint(vec![Ipv4Addr::new(192, 0, 2, 1)]),
+        SvcParam::Ech(vec![0xfe, 0x0d, 0x00, 0x01]),
+        SvcParam::Ipv6Hint(vec![Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)]),
+        SvcParam::Unknown {
+            key: 667,
+            value: b"hello".to_vec(),
+        },
+    ];
+    for param in params {
+        assert_eq!(round_trip(&param), param);
+    }
+}
+
+#[test]
+fn test_read_port() {
+    let mut buffer = BytePacketBuffer::default();
+    for byte in [0x00, 0x03, 0x00, 0x02, 0x00, 0x35] {
+        buffer.write_u8(byte).unwrap();
+    }
+    buffer.seek(0).unwrap();
+    assert_eq!(SvcParam::read(&mut buffer).unwrap(), SvcParam::Port(53));
+    assert_eq!(buffer.pos, 6);
+}
+
+#[test]
+fn test_read_invalid() {
+    // A port has to be exactly two bytes
+    let mut buffer = BytePacketBuffer::default();
+    for byte in [0x00, 0x03, 0x00, 0x01, 0x35] {
+        buffer.write_u8(byte).unwrap();
+    }
+    buffer.seek(0).unwrap();
+    assert!(SvcParam::read(&mut buffer).is_err());
+
+    // An alpn id running past the end of the value
+    let mut buffer = BytePacketBuffer::default();
+    for byte in [0x00, 0x01, 0x00, 0x02, 0x05, b'h'] {
+        buffer.write_u8(byte).unwrap();
+    }
+    buffer.seek(0).unwrap();
+    assert!(SvcParam::read(&mut buffer).is_err());
+
+    // An empty alpn id, which write refuses to produce
+    let mut buffer = BytePacketBuffer::default();
+    for byte in [0x00, 0x01, 0x00, 0x04, 0x02, b'h', b'2', 0x00] {
+        buffer.write_u8(byte).unwrap();
+    }
+    buffer.seek(0).unwrap();
+    assert!(SvcParam::read(&mut buffer).is_err());
+
+    // An alpn value without any id
+    let mut buffer = BytePacketBuffer::default();
+    for byte in [0x00, 0x01, 0x00, 0x00] {
+        buffer.write_u8(byte).unwrap();
+    }
+    buffer.seek(0).unwrap();
+    assert!(SvcParam::read(&mut buffer).is_err());
+}
+
+#[test]
+fn test_write_invalid_alpn() {
+    let mut buffer = BytePacketBuffer::default();
+    assert!(SvcParam::Alpn(vec![]).write(&mut buffer).is_err());
+    assert!(SvcParam::Alpn(vec![b"h2".to_vec(), vec![]])
+        .write(&mut buffer)
+        .is_err());
+}
@@ -0,0 +1,435 @@
+mod common;
+
+use std::net::Ipv4Addr;
+
+use common::{a, cname, dname, free_port, ns, serve, TestKey, Zone};
+use rdns_resolver_rs::{
+    dns_packet::DnsPacket,
+    dns_question::DnsQuestion,
+    dns_record::DnsRecord,
+    dnssec::Security,
+    in_flight::InFlight,
+    infra_cache::InfraCache,
+    lookup::{handle_query, recursive_lookup},
+    lru_cache::LRUCache,
+    query_type::QueryType,
+    res_code::ResultCode,
+    resolver_config::ResolverConfig,
+    validator::Validator,
+};
+
+// root (RSA) -> test (P-256, NSEC) -> secure.test (Ed25519, NSEC3)
+//                                  -> bogus.test (P-384, forged data)
+//                                  -> insecure.test (unsigned)
+fn signed_hierarchy() -> ResolverConfig {
+    let port = free_port();
+    let root_key = TestKey::new(8);
+    let test_key = TestKey::new(13);
+    let secure_key = TestKey::new(15);
+    let bogus_key = TestKey::new(14);
+
+    let mut root = Zone::new(
+        "",
+        vec![
+            ns("", "a.root"),
+            a("a.root", "127.0.0.1"),
+            ns("test", "ns.test"),
+            a("ns.test", "127.0.0.2"),
+            test_key.ds("test"),
+        ],
+    );
+    root.sign(&root_key);
+
+    let mut test = Zone::new(
+        "test",
+        vec![
+            ns("test", "ns.test"),
+            a("ns.test", "127.0.0.2"),
+            ns("secure.test", "ns.secure.test"),
+            a("ns.secure.test", "127.0.0.3"),
+            secure_key.ds("secure.test"),
+            ns("insecure.test", "ns.insecure.test"),
+            a("ns.insecure.test", "127.0.0.4"),
+            ns("bogus.test", "ns.bogus.test"),
+            a("ns.bogus.test", "127.0.0.5"),
+            bogus_key.ds("bogus.test"),
+        ],
+    );
+    test.sign(&test_key);
+
+    let mut secure = Zone::new(
+        "secure.test",
+        vec![
+            ns("secure.test", "ns.secure.test"),
+            a("ns.secure.test", "127.0.0.3"),
+            a("www.secure.test", "192.0.2.1"),
+            cname("alias.secure.test", "www.secure.test"),
+            dname("old.secure.test", "secure.test"),
+            a("wild.secure.test", "192.0.2.2"),
+            a("*.wild.secure.test", "192.0.2.3"),
+        ],
+    );
+    secure.sign_nsec3(&secure_key, 1, false);
+    // Claims to be signed by an unsigned zone that isn't above it
+    let mut forged = secure
+        .records
+        .iter()
+        .find(|r| {
+            r.domain() == "www.secure.test"
+                && matches!(r, DnsRecord::RRSIG { type_covered, .. } if *type_covered == QueryType::A)
+        })
+        .unwrap()
+        .clone();
+    if let DnsRecord::RRSIG {
+        domain,
+        signer_name,
+        ..
+    } = &mut forged
+    {
+        *domain = "forged.secure.test".to_string();
+        *signer_name = "insecure.test".to_string();
+    }
+    secure.records.push(a("forged.secure.test", "192.0.2.7"));
+    secure.records.push(forged);
+
+    let insecure = Zone::new(
+        "insecure.test",
+        vec![
+            ns("insecure.test", "ns.insecure.test"),
+            a("ns.insecure.test", "127.0.0.4"),
+            a("www.insecure.test", "192.0.2.4"),
+        ],
+    );
+
+    let mut bogus = Zone::new(
+        "bogus.test",
+        vec![
+            ns("bogus.test", "ns.bogus.test"),
+            a("ns.bogus.test", "127.0.0.5"),
+            a("www.bogus.test", "192.0.2.5"),
+            a("unsigned.bogus.test", "192.0.2.6"),
+        ],
+    );
+    bogus.sign(&bogus_key);
+    for record in bogus.records.iter_mut() {
+        if let DnsRecord::A { domain, addr, .. } = record {
+            if domain == "www.bogus.test" {
+                *addr = Ipv4Addr::new(192, 0, 2, 66);
+            }
+        }
+    }
+    bogus.records.retain(|r| {
+        !(r.domain() == "unsigned.bogus.test"
+            && matches!(r, DnsRecord::RRSIG { type_covered, .. } if *type_covered == QueryType::A))
+    });
+
+    serve(Ipv4Addr::new(127, 0, 0, 1), port, vec![root]);
+    serve(Ipv4Addr::new(127, 0, 0, 2), port, vec![test]);
+    serve(Ipv4Addr::new(127, 0, 0, 3), port, vec![secure]);
+    serve(Ipv4Addr::new(127, 0, 0, 4), port, vec![insecure]);
+    serve(Ipv4Addr::new(127, 0, 0, 5), port, vec![bogus]);
+
+    ResolverConfig {
+        root_servers: vec![Ipv4Addr::new(127, 0, 0, 1).into()],
+        port,
+        dnssec_validation: true,
+        trust_anchors: vec![root_key.ds("")],
+        ..Default::default()
+    }
+}
+
+fn query(
+    config: &ResolverConfig,
+    name: &str,
+    qtype: QueryType,
+    dnssec_ok: bool,
+    checking_disabled: bool,
+) -> DnsPacket {
+    let cache = LRUCache::new(10);
+    query_with(config, &cache, name, qtype, dnssec_ok, checking_disabled)
+}
+
+fn query_with(
+    config: &ResolverConfig,
+    cache: &LRUCache,
+    name: &str,
+    qtype: QueryType,
+    dnssec_ok: bool,
+    checking_disabled: bool,
+) -> DnsPacket {
+    let mut request = DnsPacket::default();
+    request.header.id = 1;
+    request.header.questions = 1;
+    request.header.checking_disabled = checking_disabled;
+    request
+        .questions
+        .push(DnsQuestion::new(name.to_string(), qtype));
+    if dnssec_ok {
+        request.set_edns(1232, true);
+    }
+    handle_query(request, cache, &InFlight::new(), &InfraCache::new(), config).unwrap()
+}
+
+fn addresses(packet: &DnsPacket) -> Vec<Ipv4Addr> {
+    packet
+        .answers
+        .iter()
+        .filter_map(|r| match r {
+            DnsRecord::A { addr, .. } => Some(*addr),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_secure_answer_sets_ad() {
+    let config = signed_hierarchy();
+
+    let response = query(&config, "www.secure.test", QueryType::A, true, false);
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert!(response.header.authed_data);
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    assert!(response
+        .answers
+        .iter()
+        .any(|r| matches!(r, DnsRecord::RRSIG { .. })));
+
+    let response = query(&config, "alias.secure.test", QueryType::A, true, false);
+    assert!(response.header.authed_data);
+
+    // The CNAME synthesized from a signed DNAME needs no signature of its own
+    let response = query(&config, "www.old.secure.test", QueryType::A, true, false);
+    assert!(response.header.authed_data);
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+
+    // Wildcard expansions are proven with NSEC3
+    let response = query(&config, "host.wild.secure.test", QueryType::A, true, false);
+    assert!(response.header.authed_data);
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 3)]);
+}
+
+#[test]
+fn test_ad_is_only_set_for_clients_that_understand_it() {
+    let config = signed_hierarchy();
+
+    let response = query(&config, "www.secure.test", QueryType::A, false, false);
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert!(!response.header.authed_data);
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+}
+
+#[test]
+fn test_secure_denial() {
+    let config = signed_hierarchy();
+
+    let response = query(&config, "missing.secure.test", QueryType::A, true, false);
+    assert_eq!(response.header.rescode, ResultCode::NXDOMAIN);
+    assert!(response.header.authed_data);
+
+    let response = query(&config, "www.secure.test", QueryType::AAAA, true, false);
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert!(response.answers.is_empty());
+    assert!(response.header.authed_data);
+
+    // test is signed with NSEC rather than NSEC3
+    let response = query(&config, "missing.test", QueryType::A, true, false);
+    assert_eq!(response.header.rescode, ResultCode::NXDOMAIN);
+    assert!(response.header.authed_data);
+}
+
+#[test]
+fn test_insecure_delegation() {
+    let config = signed_hierarchy();
+
+    let response = query(&config, "www.insecure.test", QueryType::A, true, false);
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert!(!response.header.authed_data);
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 4)]);
+}
+
+#[test]
+fn test_bogus_answers_are_servfail() {
+    let config = signed_hierarchy();
+
+    let response = query(&config, "www.bogus.test", QueryType::A, true, false);
+    assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+    assert!(response.answers.is_empty());
+
+    let response = query(&config, "unsigned.bogus.test", QueryType::A, true, false);
+    assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+}
+
+#[test]
+fn test_signer_must_be_above_the_owner() {
+    let config = signed_hierarchy();
+
+    let response = query(&config, "forged.secure.test", QueryType::A, true, false);
+    assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+    assert!(response.answers.is_empty());
+}
+
+#[test]
+fn test_nodata_at_the_end_of_a_cname_chain_is_proven() {
+    let config = signed_hierarchy();
+    let infra = InfraCache::new();
+    let lookup = |name: &str| {
+        recursive_lookup(&config, &LRUCache::new(100), &infra, name, QueryType::AAAA).unwrap()
+    };
+
+    // The alias with the denial for its target, as a server following the chain sends them
+    let mut response = lookup("alias.secure.test");
+    response.authorities = lookup("www.secure.test").authorities;
+    let cache = LRUCache::new(100);
+    let mut validator = Validator::new(&config, &cache, &infra);
+    assert_eq!(
+        validator.validate("alias.secure.test", QueryType::AAAA, &response),
+        Security::Secure
+    );
+
+    // The SOA alone doesn't prove the target has no AAAA
+    response.authorities.retain(|r| {
+        matches!(r, DnsRecord::SOA { .. })
+            || matches!(r, DnsRecord::RRSIG { type_covered, .. } if *type_covered == QueryType::SOA)
+    });
+    let cache = LRUCache::new(100);
+    let mut validator = Validator::new(&config, &cache, &infra);
+    assert!(matches!(
+        validator.validate("alias.secure.test", QueryType::AAAA, &response),
+        Security::Bogus(_)
+    ));
+}
+
+#[test]
+fn test_authority_section_of_a_positive_answer_is_checked() {
+    let config = signed_hierarchy();
+    let infra = InfraCache::new();
+    let mut response = recursive_lookup(
+        &config,
+        &LRUCache::new(100),
+        &infra,
+        "www.secure.test",
+        QueryType::A,
+    )
+    .unwrap();
+    let cache = LRUCache::new(100);
+    let mut validator = Validator::new(&config, &cache, &infra);
+    assert_eq!(
+        validator.validate("www.secure.test", QueryType::A, &response),
+        Security::Secure
+    );
+
+    // An unsigned record slipped in next to a signed answer
+    response
+        .authorities
+        .push(ns("secure.test", "ns.attacker.example"));
+    let cache = LRUCache::new(100);
+    let mut validator = Validator::new(&config, &cache, &infra);
+    assert!(matches!(
+        validator.validate("www.secure.test", QueryType::A, &response),
+        Security::Bogus(_)
+    ));
+}
+
+#[test]
+fn test_keys_are_only_cached_once_checked() {
+    let config = signed_hierarchy();
+    let cache = LRUCache::new(100);
+    query_with(
+        &config,
+        &cache,
+        "www.secure.test",
+        QueryType::A,
+        true,
+        false,
+    );
+
+    let dnskey = DnsQuestion::new("secure.test".to_string(), QueryType::DNSKEY);
+    assert!(cache.get(&dnskey).unwrap().header.authed_data);
+
+    // A DNSKEY RRset that doesn't match its DS never makes it in
+    let config = ResolverConfig {
+        trust_anchors: vec![TestKey::new(15).ds("")],
+        ..config
+    };
+    let cache = LRUCache::new(100);
+    query_with(
+        &config,
+        &cache,
+        "www.secure.test",
+        QueryType::A,
+        true,
+        false,
+    );
+    let dnskey = DnsQuestion::new("".to_string(), QueryType::DNSKEY);
+    assert!(cache.get(&dnskey).is_none());
+}
+
+#[test]
+fn test_cached_insecure_answers_are_not_validated_again() {
+    let config = signed_hierarchy();
+    let cache = LRUCache::new(100);
+    let response = query_with(
+        &config,
+        &cache,
+        "www.insecure.test",
+        QueryType::A,
+        true,
+        false,
+    );
+    assert!(!response.header.authed_data);
+
+    // Validating again would now fail at the root, the cached result is served as it is
+    let config = ResolverConfig {
+        trust_anchors: vec![TestKey::new(15).ds("")],
+        ..config
+    };
+    let response = query_with(
+        &config,
+        &cache,
+        "www.insecure.test",
+        QueryType::A,
+        true,
+        false,
+    );
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 4)]);
+}
+
+#[test]
+fn test_checking_disabled_skips_validation() {
+    let config = signed_hierarchy();
+
+    let response = query(&config, "www.bogus.test", QueryType::A, true, true);
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert!(!response.header.authed_data);
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 66)]);
+}
+
+#[test]
+fn test_lookups_for_checking_disabled_clients_are_validated_for_the_cache() {
+    let config = signed_hierarchy();
+    let cache = LRUCache::new(100);
+
+    query_with(&config, &cache, "www.secure.test", QueryType::A, true, true);
+    let question = DnsQuestion::new("www.secure.test".to_string(), QueryType::A);
+    assert!(cache.get(&question).unwrap().header.authed_data);
+
+    // Bogus answers are still handed to the client, but never cached
+    let response = query_with(&config, &cache, "www.bogus.test", QueryType::A, true, true);
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 66)]);
+    let question = DnsQuestion::new("www.bogus.test".to_string(), QueryType::A);
+    assert!(cache.get(&question).is_none());
+    let response = query_with(&config, &cache, "www.bogus.test", QueryType::A, true, false);
+    assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+}
+
+#[test]
+fn test_untrusted_root_is_servfail() {
+    let config = ResolverConfig {
+        trust_anchors: vec![TestKey::new(15).ds("")],
+        ..signed_hierarchy()
+    };
+
+    let response = query(&config, "www.secure.test", QueryType::A, true, false);
+    assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+}